@@ -1,14 +1,59 @@
 use byteorder::{LittleEndian, ReadBytesExt};
-use std::fs::File;
-use std::io::{Read, BufReader, Seek, SeekFrom, Cursor};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom, Cursor};
 use block_modes::{BlockMode, Ecb, block_padding::ZeroPadding};
 use aes::Aes256;
-use flate2::read::ZlibDecoder;
-use crate::assets::{FGuid, Newable, ReaderCursor, read_string, read_tarray, ParserResult, ParserError};
-use crate::decompress::oodle;
+use rayon::prelude::*;
+use crate::assets::{FGuid, Newable, ReaderCursor, read_string, read_tarray, ParserResult, ParserError, ParserType};
+use crate::decompress;
+use crate::keys::is_mount_point;
+use crate::source::{ContainerSource, SourceMode};
 
 const PAK_MAGIC: u32 = 0x5A6F12E1;
-const PAK_SIZE: u32 = 8 + 16 + 20 + 1 + 16 + (32 * 5);
+
+/// How much of an uncompressed entry a `PakEntryReader` reads from disk at once
+const UNCOMPRESSED_READ_SIZE: u64 = 64 * 1024;
+
+/// How much of an entry is read at once while it is being hashed
+const HASH_READ_SIZE: u64 = 1024 * 1024;
+
+const PAK_SIGNATURE_MAGIC: u32 = 0x73832DAA;
+
+/// The size of the chunks a `.sig` file holds a CRC32 for
+pub const PAK_SIGNATURE_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// The layouts a pak footer has had, newest first: (footer size, first version, last version, compression method names, frozen index flag)
+///
+/// Version 8 appears twice, as the number of compression method names changed without a version bump.
+const PAK_FOOTER_LAYOUTS: [(u64, u32, u32, u32, bool); 6] = [
+    (16 + 1 + 44 + 32 * 5, 8, 11, 5, false),
+    (16 + 1 + 44 + 1 + 32 * 5, 9, 9, 5, true),
+    (16 + 1 + 44 + 32 * 4, 8, 8, 4, false),
+    (16 + 1 + 44, 7, 7, 0, false),
+    (1 + 44, 4, 6, 0, false),
+    (44, 1, 3, 0, false),
+];
+
+/// Compression methods were flags before version 8, these are mapped onto the names used after it
+const LEGACY_COMPRESSION_METHODS: [&str; 3] = ["Zlib", "Gzip", "Oodle"];
+
+/// The versions of the pak format, from the footer
+#[allow(dead_code)]
+pub mod pak_version {
+    pub const INITIAL: u32 = 1;
+    pub const NO_TIMESTAMPS: u32 = 2;
+    pub const COMPRESSION_ENCRYPTION: u32 = 3;
+    pub const INDEX_ENCRYPTION: u32 = 4;
+    pub const RELATIVE_CHUNK_OFFSETS: u32 = 5;
+    pub const DELETE_RECORDS: u32 = 6;
+    pub const ENCRYPTION_KEY_GUID: u32 = 7;
+    pub const FNAME_BASED_COMPRESSION_METHOD: u32 = 8;
+    pub const FROZEN_INDEX: u32 = 9;
+    pub const PATH_HASH_INDEX: u32 = 10;
+    pub const FNV64_BUGFIX: u32 = 11;
+    pub const LATEST: u32 = FNV64_BUGFIX;
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -19,52 +64,78 @@ pub struct FPakInfo {
     index_offset: u64,
     index_size: u64,
     index_hash: [u8; 20],
+    index_frozen: bool,
     compression_methods: Vec<String>,
+    /// Version 8 paks from 4.22, which have 4 compression method names, store the method of each entry in a byte
+    byte_compression_index: bool,
 }
 
 #[allow(dead_code)]
 impl FPakInfo {
-    pub fn get_key_guid(&self) -> &FGuid {
-        &self.encryption_key_guid
-    }
+    /// Reads the footer at the end of a pak file, working out which layout it has from the version
+    pub fn read(source: &ContainerSource) -> ParserResult<Self> {
+        let file_size = source.get_size()?;
+        let tail_size = std::cmp::min(file_size, PAK_FOOTER_LAYOUTS.iter().map(|v| v.0).max().unwrap_or(0));
+        let tail = source.read_vec(file_size - tail_size, tail_size as usize)?;
+
+        for (footer_size, first_version, last_version, method_count, has_frozen) in PAK_FOOTER_LAYOUTS.iter() {
+            if *footer_size > tail_size {
+                continue;
+            }
+            let footer = &tail[((tail_size - footer_size) as usize)..];
+            let magic_offset = match *first_version {
+                v if v >= pak_version::ENCRYPTION_KEY_GUID => 17,
+                v if v >= pak_version::INDEX_ENCRYPTION => 1,
+                _ => 0,
+            };
+            let mut reader = Cursor::new(&footer[magic_offset..]);
+            let magic = reader.read_u32::<LittleEndian>()?;
+            let version = reader.read_u32::<LittleEndian>()?;
+            if magic != PAK_MAGIC || version < *first_version || version > *last_version {
+                continue;
+            }
 
-    pub fn get_index_sizes(&self) -> (u64, u64) {
-        (self.index_offset, self.index_size)
-    }
-}
+            let mut reader = Cursor::new(footer);
+            return Self::read_footer(&mut reader, version, *method_count, *has_frozen);
+        }
 
-impl Newable for FPakInfo {
-    fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
-        let encryption_key_guid = FGuid::new(reader)?;
-        let encrypted_index = reader.read_u8()? != 0;
-        let magic = reader.read_u32::<LittleEndian>()?;
+        Err(ParserError::typed("Could not find a pak footer, or the pak version is not supported".to_owned(), ParserType::Malformed))
+    }
 
-        if magic != PAK_MAGIC {
-            panic!("Invalid pak file");
-        }
-        let version = reader.read_u32::<LittleEndian>()?;
+    fn read_footer(reader: &mut ReaderCursor, version: u32, method_count: u32, has_frozen: bool) -> ParserResult<Self> {
+        let encryption_key_guid = match version >= pak_version::ENCRYPTION_KEY_GUID {
+            true => FGuid::new(reader)?,
+            false => FGuid::default(),
+        };
+        let encrypted_index = match version >= pak_version::INDEX_ENCRYPTION {
+            true => reader.read_u8()? != 0,
+            false => false,
+        };
+        let _magic = reader.read_u32::<LittleEndian>()?;
+        let _version = reader.read_u32::<LittleEndian>()?;
         let index_offset = reader.read_u64::<LittleEndian>()?;
         let index_size = reader.read_u64::<LittleEndian>()?;
 
         let mut index_hash = [0u8; 20];
         reader.read_exact(&mut index_hash)?;
 
-        let mut compression_methods = Vec::new();
+        let index_frozen = match has_frozen {
+            true => reader.read_u8()? != 0,
+            false => false,
+        };
 
-        for _i in 0..5 {
+        let mut compression_methods = Vec::new();
+        for _i in 0..method_count {
             let mut bytes = [0u8; 32];
             reader.read_exact(&mut bytes)?;
-            let mut length = 32;
-            for p in 0..32 {
-                if bytes[p] == 0 {
-                    length = p;
-                    break;
-                }
-            };
+            let length = bytes.iter().position(|v| *v == 0).unwrap_or(bytes.len());
 
             let fstr = std::str::from_utf8(&bytes[0..length])?.to_owned();
             compression_methods.push(fstr);
         }
+        if version < pak_version::FNAME_BASED_COMPRESSION_METHOD {
+            compression_methods = LEGACY_COMPRESSION_METHODS.iter().map(|v| (*v).to_owned()).collect();
+        }
 
         Ok(Self {
             encryption_key_guid,
@@ -73,23 +144,67 @@ impl Newable for FPakInfo {
             index_offset,
             index_size,
             index_hash,
+            index_frozen,
             compression_methods,
+            byte_compression_index: version == pak_version::FNAME_BASED_COMPRESSION_METHOD && method_count == 4,
         })
     }
+
+    pub fn get_key_guid(&self) -> &FGuid {
+        &self.encryption_key_guid
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted_index
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_index_sizes(&self) -> (u64, u64) {
+        (self.index_offset, self.index_size)
+    }
+
+    pub fn get_compression_methods(&self) -> &Vec<String> {
+        &self.compression_methods
+    }
+}
+
+/// Parses a hex AES key, with or without a leading `0x`
+pub(crate) fn decode_key(key: &str) -> ParserResult<Vec<u8>> {
+    let key = key.trim_start_matches("0x");
+    match hex::decode(key) {
+        Ok(key) => Ok(key),
+        Err(e) => Err(ParserError::typed(format!("Invalid AES key: {}", e), ParserType::Decryption)),
+    }
+}
+
+/// Decrypts a buffer in place with AES-256 in ECB mode, as used by both pak and IoStore containers
+pub(crate) fn decrypt_buffer(key: &[u8], data: &mut [u8]) -> ParserResult<()> {
+    let decrypt = match Ecb::<Aes256, ZeroPadding>::new_from_slices(key, Default::default()) {
+        Ok(decrypt) => decrypt,
+        Err(_) => return Err(ParserError::typed(format!("Invalid AES key length: {}", key.len()), ParserType::Decryption)),
+    };
+    match decrypt.decrypt(data) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ParserError::typed(format!("Could not decrypt buffer of length {}", data.len()), ParserType::Decryption)),
+    }
 }
 
-fn get_index(header: &FPakInfo, reader: &mut BufReader<File>, key: &str) -> Vec<u8> {
-    let mut ciphertext = vec![0u8; header.index_size as usize];
-    reader.seek(SeekFrom::Start(header.index_offset)).unwrap();
-    reader.read_exact(&mut ciphertext).unwrap();
-    if !header.encrypted_index {
-        return ciphertext;
+fn get_index(header: &FPakInfo, reader: &ContainerSource, key: &[u8]) -> ParserResult<Vec<u8>> {
+    let mut ciphertext = reader.read_vec(header.index_offset, header.index_size as usize)?;
+    if header.encrypted_index {
+        decrypt_buffer(key, &mut ciphertext)?;
     }
-    let key = hex::decode(key).expect("Hex error");
+    Ok(ciphertext)
+}
 
-    let decrypt = Ecb::<Aes256, ZeroPadding>::new_from_slices(&key, Default::default()).unwrap();
-    decrypt.decrypt(&mut ciphertext).unwrap();
-    ciphertext
+fn align_size(size: u64) -> u64 {
+    match size % 16 {
+        0 => size,
+        r => size + (16 - r),
+    }
 }
 
 #[allow(dead_code)]
@@ -126,24 +241,64 @@ pub struct FPakEntry {
 
 #[allow(dead_code)]
 impl FPakEntry {
-    fn new(reader: &mut ReaderCursor, filename: String) -> ParserResult<Self> {
+    fn new(reader: &mut ReaderCursor, filename: String, header: &FPakInfo) -> ParserResult<Self> {
+        let version = header.version;
         let seek_point = reader.position();
         let position = reader.read_i64::<LittleEndian>()?;
         let size = reader.read_u64::<LittleEndian>()?;
         let uncompressed_size = reader.read_u64::<LittleEndian>()?;
-        let compression_method = reader.read_u32::<LittleEndian>()?;
+        let compression_method = match version < pak_version::FNAME_BASED_COMPRESSION_METHOD {
+            // Map the old flags onto LEGACY_COMPRESSION_METHODS
+            true => match reader.read_u32::<LittleEndian>()? & 0x0f {
+                0 => 0,
+                0x01 => 1,
+                0x02 => 2,
+                0x04 => 3,
+                v => return Err(ParserError::typed(format!("Unknown legacy compression flags: {:#x}", v), ParserType::Decompression)),
+            },
+            false if header.byte_compression_index => reader.read_u8()? as u32,
+            false => reader.read_u32::<LittleEndian>()?,
+        };
+        if version <= pak_version::INITIAL {
+            let _timestamp = reader.read_u64::<LittleEndian>()?;
+        }
         let mut hash = [0u8; 20];
         reader.read_exact(&mut hash)?;
+
         let mut compression_blocks: Vec<FPakCompressedBlock> = Vec::new();
-        if compression_method != 0 {
-            compression_blocks = read_tarray(reader)?;
+        let mut encrypted = false;
+        let mut compression_block_size = 0;
+        if version >= pak_version::COMPRESSION_ENCRYPTION {
+            if compression_method != 0 {
+                compression_blocks = read_tarray(reader)?;
+            }
+            encrypted = reader.read_u8()? != 0;
+            compression_block_size = reader.read_u32::<LittleEndian>()?;
+        }
+        let struct_size = reader.position() - seek_point;
+
+        // Block offsets were from the start of the file, rather than the start of the entry
+        if version < pak_version::RELATIVE_CHUNK_OFFSETS {
+            for block in compression_blocks.iter_mut() {
+                block.compressed_start -= position;
+                block.compressed_end -= position;
+            }
+        }
+
+        // Before blocks were recorded, compressed entries were a single block
+        if compression_method != 0 && compression_blocks.is_empty() {
+            compression_blocks.push(FPakCompressedBlock {
+                compressed_start: struct_size as i64,
+                compressed_end: (struct_size + size) as i64,
+            });
+            compression_block_size = uncompressed_size as u32;
         }
 
         Ok(Self {
             filename, position, size, uncompressed_size, compression_method, compression_blocks, hash,
-            encrypted: reader.read_u8()? != 0,
-            compression_block_size: reader.read_u32::<LittleEndian>()?,
-            struct_size: reader.position() - seek_point,
+            encrypted,
+            compression_block_size,
+            struct_size,
         })
     }
 
@@ -152,8 +307,8 @@ impl FPakEntry {
         &self.filename[..]
     }
 
-    fn from_encoded(reader: &mut ReaderCursor, dir_name: &str, index_data: &FPathHashIndexEntry) -> ParserResult<Self> {
-        reader.seek(SeekFrom::Start(index_data.location as u64))?;
+    fn from_encoded(reader: &mut ReaderCursor, filename: String, location: u64) -> ParserResult<Self> {
+        reader.seek(SeekFrom::Start(location))?;
         let flags = reader.read_u32::<LittleEndian>()?;
 
         let offset_safe = (flags & (1 << 31)) != 0;
@@ -210,18 +365,21 @@ impl FPakEntry {
                     compressed_start: compressed_block_offset,
                     compressed_end: compressed_block_offset + size_data,
                 });
-                compressed_block_offset += size_data;
+                // Encrypted blocks each start on an AES block boundary
+                compressed_block_offset += match encrypted {
+                    true => align_size(size_data as u64) as i64,
+                    false => size_data,
+                };
             }
         }
 
         Ok(Self {
-            filename: dir_name.to_owned() + &index_data.key,
+            filename,
             position,
             size,
             uncompressed_size,
             compression_method,
             encrypted,
-            // Not gonna support this straight away
             compression_blocks,
             hash: [0u8; 20],
             compression_block_size,
@@ -236,19 +394,42 @@ pub struct FPakIndex {
     file_count: u32,
     index_entries: Vec<FPakEntry>,
     encoded_pak: Vec<u8>,
-    path_index: (i64, i64),
-    dir_index: (i64, i64),
+    unencoded_entries: Vec<FPakEntry>,
+    path_hash_seed: u64,
+    path_index: Option<(i64, i64)>,
+    dir_index: Option<(i64, i64)>,
 }
 
-impl Newable for FPakIndex {
-    fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
+impl FPakIndex {
+    fn new(reader: &mut ReaderCursor, header: &FPakInfo) -> ParserResult<Self> {
+        let version = header.version;
         let mount_point = read_string(reader)?;
         if mount_point.len() > 1024 {
-            return Err(ParserError::new(format!("Could not read Pak Archive")));
+            return Err(ParserError::typed("Could not read Pak Archive".to_owned(), ParserType::Malformed));
         }
         let file_count = reader.read_u32::<LittleEndian>()?;
 
-        let _hash_seed = reader.read_u64::<LittleEndian>()?;
+        // Before the path hash index, every entry was listed along with its filename
+        if version < pak_version::PATH_HASH_INDEX {
+            let mut index_entries = Vec::new();
+            for _i in 0..file_count {
+                let filename = read_string(reader)?;
+                index_entries.push(FPakEntry::new(reader, filename, header)?);
+            }
+
+            return Ok(Self {
+                mount_point,
+                file_count,
+                index_entries,
+                encoded_pak: Vec::new(),
+                unencoded_entries: Vec::new(),
+                path_hash_seed: 0,
+                path_index: None,
+                dir_index: None,
+            });
+        }
+
+        let path_hash_seed = reader.read_u64::<LittleEndian>()?;
         let has_path_index = reader.read_i32::<LittleEndian>()? != 0;
         let path_index = match has_path_index {
             true => {
@@ -256,9 +437,9 @@ impl Newable for FPakIndex {
                 let path_index_size = reader.read_i64::<LittleEndian>()?;
                 let mut hash = [0u8; 20];
                 reader.read_exact(&mut hash)?;
-                (path_index_offset, path_index_size)
+                Some((path_index_offset, path_index_size))
             },
-            false => return Err(ParserError::new(format!("No path index present"))),
+            false => None,
         };
 
         let has_dir_index = reader.read_i32::<LittleEndian>()? != 0;
@@ -268,41 +449,205 @@ impl Newable for FPakIndex {
                 let dir_index_size = reader.read_i64::<LittleEndian>()?;
                 let mut hash = [0u8; 20];
                 reader.read_exact(&mut hash)?;
-                (dir_index_offset, dir_index_size)
+                Some((dir_index_offset, dir_index_size))
             },
-            false => return Err(ParserError::new(format!("No directory index present"))),
+            false => None,
         };
 
         let encoded_pak: Vec<u8> = read_tarray(reader)?;
         let new_file_count = reader.read_u32::<LittleEndian>()?;
 
-        let mut index_entries = Vec::new();
+        // Entries that couldn't be encoded, referred to by a negative location
+        let mut unencoded_entries = Vec::new();
         for _i in 0..new_file_count {
-            index_entries.push(FPakEntry::new(reader, "test".to_owned())?);
+            unencoded_entries.push(FPakEntry::new(reader, "".to_owned(), header)?);
         }
 
         Ok(Self {
             mount_point,
             file_count,
-            index_entries,
+            index_entries: Vec::new(),
             encoded_pak,
+            unencoded_entries,
+            path_hash_seed,
             path_index,
             dir_index,
         })
     }
 }
 
+/// Sizes in the 64 bit memory image layout of a frozen index
+const FROZEN_PAK_ENTRY_SIZE: usize = 80;
+const FROZEN_COMPRESSED_BLOCK_SIZE: usize = 16;
+/// A `TSetElement` of a directory name and the `TMap` of the files in it
+const FROZEN_DIRECTORY_ELEMENT_SIZE: usize = 80;
+/// A `TSetElement` of a filename and the position of its entry
+const FROZEN_FILE_ELEMENT_SIZE: usize = 32;
+
+/// Reads the memory image a frozen pak index is stored as, which is the engine's in-memory index written out as is
+///
+/// Pointers are stored as an offset from the pointer itself, in bits 1 to 40. Only the 64 bit layout is read.
+struct FrozenReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> FrozenReader<'a> {
+    fn get(&self, pos: usize, length: usize) -> ParserResult<&'a [u8]> {
+        match self.data.get(pos..(pos + length)) {
+            Some(bytes) => Ok(bytes),
+            None => Err(ParserError::typed(format!("Frozen index read out of range: {} + {}", pos, length), ParserType::Malformed)),
+        }
+    }
+
+    fn read_u32(&self, pos: usize) -> ParserResult<u32> {
+        Ok(u32::from_le_bytes(self.get(pos, 4)?.try_into().unwrap()))
+    }
+
+    fn read_i32(&self, pos: usize) -> ParserResult<i32> {
+        Ok(i32::from_le_bytes(self.get(pos, 4)?.try_into().unwrap()))
+    }
+
+    fn read_i64(&self, pos: usize) -> ParserResult<i64> {
+        Ok(i64::from_le_bytes(self.get(pos, 8)?.try_into().unwrap()))
+    }
+
+    /// Reads a `TArray` with the memory image allocator, giving the position of its data and its length
+    fn read_array(&self, pos: usize, element_size: usize) -> ParserResult<(usize, usize)> {
+        let packed = self.read_i64(pos)?;
+        let num = self.read_i32(pos + 8)?;
+        if num < 0 {
+            return Err(ParserError::typed(format!("Invalid frozen array size: {}", num), ParserType::Malformed));
+        }
+        if num == 0 {
+            return Ok((0, 0));
+        }
+
+        let offset = (packed << 23) >> 24;
+        let data = pos as i64 + offset;
+        if data < 0 {
+            return Err(ParserError::typed(format!("Frozen array out of range: {}", data), ParserType::Malformed));
+        }
+        self.get(data as usize, num as usize * element_size)?;
+        Ok((data as usize, num as usize))
+    }
+
+    /// Reads an `FMemoryImageString`, which is a `TArray` of UTF-16 characters ending in a null
+    fn read_string(&self, pos: usize) -> ParserResult<String> {
+        let (data, num) = self.read_array(pos, 2)?;
+        let units: Vec<u16> = self.get(data, num * 2)?.chunks(2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]))
+            .take_while(|v| *v != 0)
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// Reads a `TBitArray` with the memory image allocator, giving the position of its words and its number of bits
+    fn read_bit_array(&self, pos: usize) -> ParserResult<(usize, usize)> {
+        let (data, num_bits) = self.read_array(pos, 0)?;
+        self.get(data, num_bits.div_ceil(32) * 4)?;
+        Ok((data, num_bits))
+    }
+
+    /// Reads a `TMap` with the memory image allocator, giving the position of each element that is in use
+    fn read_map(&self, pos: usize, element_size: usize) -> ParserResult<Vec<usize>> {
+        let (data, num) = self.read_array(pos, element_size)?;
+        let (flags, num_bits) = self.read_bit_array(pos + 16)?;
+
+        let mut elements = Vec::new();
+        for i in 0..num.min(num_bits) {
+            if self.read_u32(flags + (i / 32) * 4)? & (1 << (i % 32)) != 0 {
+                elements.push(data + i * element_size);
+            }
+        }
+        Ok(elements)
+    }
+
+    fn read_entry(&self, pos: usize, filename: String) -> ParserResult<FPakEntry> {
+        let position = self.read_i64(pos)?;
+        let size = self.read_i64(pos + 8)? as u64;
+        let uncompressed_size = self.read_i64(pos + 16)? as u64;
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(self.get(pos + 24, 20)?);
+
+        let (blocks, block_count) = self.read_array(pos + 48, FROZEN_COMPRESSED_BLOCK_SIZE)?;
+        let mut compression_blocks = Vec::new();
+        for i in 0..block_count {
+            let block = blocks + i * FROZEN_COMPRESSED_BLOCK_SIZE;
+            compression_blocks.push(FPakCompressedBlock {
+                compressed_start: self.read_i64(block)?,
+                compressed_end: self.read_i64(block + 8)?,
+            });
+        }
+        let compression_block_size = self.read_u32(pos + 64)?;
+        let compression_method = self.read_u32(pos + 68)?;
+        let flags = self.get(pos + 72, 1)?[0];
+
+        // The entry is still serialized in front of its data, in the same layout as a version 9 index
+        let mut struct_size: u64 = 8 + 8 + 8 + 4 + 20 + 1 + 4;
+        if compression_method != 0 {
+            struct_size += 4 + block_count as u64 * 16;
+        }
+
+        Ok(FPakEntry {
+            filename,
+            position,
+            size,
+            uncompressed_size,
+            compression_method,
+            compression_blocks,
+            hash,
+            encrypted: flags & 0x01 != 0,
+            compression_block_size,
+            struct_size,
+        })
+    }
+}
+
+impl FPakIndex {
+    /// Reads a frozen index, from version 9 paks made with the index frozen
+    ///
+    /// The index is an `FPakFileData`: the mount point, an array of every entry, and a map of directory to filename to entry.
+    fn new_frozen(data: &[u8]) -> ParserResult<Self> {
+        let reader = FrozenReader { data };
+        let mount_point = reader.read_string(0)?;
+        let (files, file_count) = reader.read_array(16, FROZEN_PAK_ENTRY_SIZE)?;
+
+        let mut index_entries = Vec::new();
+        for directory in reader.read_map(32, FROZEN_DIRECTORY_ELEMENT_SIZE)? {
+            let directory_name = reader.read_string(directory)?;
+            for file in reader.read_map(directory + 16, FROZEN_FILE_ELEMENT_SIZE)? {
+                let filename = directory_name.clone() + &reader.read_string(file)?;
+                let idx = reader.read_i32(file + 16)?;
+                if idx < 0 || idx as usize >= file_count {
+                    return Err(ParserError::typed(format!("Frozen entry index out of range: {}", idx), ParserType::Malformed));
+                }
+                index_entries.push(reader.read_entry(files + idx as usize * FROZEN_PAK_ENTRY_SIZE, filename)?);
+            }
+        }
+        Ok(Self {
+            mount_point,
+            file_count: file_count as u32,
+            index_entries,
+            encoded_pak: Vec::new(),
+            unencoded_entries: Vec::new(),
+            path_hash_seed: 0,
+            path_index: None,
+            dir_index: None,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FPathHashIndexEntry {
     key: String,
-    location: u32,
+    location: i32,
 }
 
 impl Newable for FPathHashIndexEntry {
     fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
         Ok(Self {
             key: read_string(reader)?,
-            location: reader.read_u32::<LittleEndian>()?,
+            location: reader.read_i32::<LittleEndian>()?,
         })
     }
 }
@@ -331,17 +676,33 @@ impl FPakIndex {
         &self.mount_point
     }
 
-    pub fn get_dir_index(&self) -> (i64, i64) {
+    pub fn get_dir_index(&self) -> Option<(i64, i64)> {
         self.dir_index
     }
 
+    /// Reads an entry from the encoded entries, or the list of entries that couldn't be encoded
+    fn get_encoded_entry(&self, encoded_cursor: &mut ReaderCursor, filename: String, location: i32) -> ParserResult<FPakEntry> {
+        match location {
+            v if v >= 0 => FPakEntry::from_encoded(encoded_cursor, filename, v as u64),
+            v => match self.unencoded_entries.get((-(v as i64) - 1) as usize) {
+                Some(entry) => {
+                    let mut entry = entry.clone();
+                    entry.filename = filename;
+                    Ok(entry)
+                },
+                None => Err(ParserError::typed(format!("Entry location out of range: {}", v), ParserType::Malformed)),
+            },
+        }
+    }
+
     pub fn update_from_index(&mut self, reader: &mut ReaderCursor) -> ParserResult<()> {
         let directory_index: Vec<FPakDirectoryEntry> = read_tarray(reader)?;
         let mut encoded_cursor = Cursor::new(self.encoded_pak.as_slice());
         let mut pak_entries = Vec::new();
         for dir_entry in &directory_index {
             for pak_entry in &dir_entry.value {
-                pak_entries.push(FPakEntry::from_encoded(&mut encoded_cursor, &dir_entry.key, &pak_entry)?);
+                let filename = dir_entry.key.clone() + &pak_entry.key;
+                pak_entries.push(self.get_encoded_entry(&mut encoded_cursor, filename, pak_entry.location)?);
             }
         }
 
@@ -349,6 +710,62 @@ impl FPakIndex {
 
         Ok(())
     }
+
+    /// Reads the path hash index, which maps `hash_path` of each filename to the location of its entry
+    fn read_path_hash_index(reader: &mut ReaderCursor) -> ParserResult<HashMap<u64, i32>> {
+        let count = reader.read_i32::<LittleEndian>()?;
+        if count < 0 {
+            return Err(ParserError::typed(format!("Invalid path hash index size: {}", count), ParserType::Malformed));
+        }
+
+        let mut path_hash_index = HashMap::with_capacity(count as usize);
+        for _i in 0..count {
+            let hash = reader.read_u64::<LittleEndian>()?;
+            let location = reader.read_i32::<LittleEndian>()?;
+            path_hash_index.insert(hash, location);
+        }
+
+        Ok(path_hash_index)
+    }
+}
+
+/// The `.sig` file that sits next to a signed `.pak`, holding a CRC32 of each 64KB chunk of the pak
+///
+/// The RSA signed hash of the chunk list isn't checked, as that needs the game's public key.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct FPakSignatureFile {
+    version: i32,
+    encrypted_hash: Vec<u8>,
+    chunk_hashes: Vec<u32>,
+}
+
+impl FPakSignatureFile {
+    pub fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        if magic != PAK_SIGNATURE_MAGIC {
+            return Err(ParserError::typed(format!("Signature file magic mismatch: {:#x}", magic), ParserType::Malformed));
+        }
+        let version = reader.read_i32::<LittleEndian>()?;
+        let encrypted_hash = read_tarray(reader)?;
+        let chunk_hashes = read_tarray(reader)?;
+
+        Ok(Self {
+            version,
+            encrypted_hash,
+            chunk_hashes,
+        })
+    }
+
+    pub fn from_file(path: &str) -> ParserResult<Self> {
+        let data = std::fs::read(path)?;
+        let mut reader = Cursor::new(data.as_slice());
+        Self::new(&mut reader).map_err(|v| ParserError::add(v, format!("Signature file {}", path)))
+    }
+
+    pub fn get_chunk_hashes(&self) -> &[u32] {
+        &self.chunk_hashes
+    }
 }
 
 /// PakExtractor can read the contents of a `.pak` file
@@ -356,73 +773,137 @@ impl FPakIndex {
 pub struct PakExtractor {
     header: FPakInfo,
     index: FPakIndex,
+    entry_index: HashMap<String, usize>,
+    path_hash_index: HashMap<u64, i32>,
     key: Vec<u8>,
-    reader: BufReader<File>,
+    reader: ContainerSource,
 }
 
-fn decompress_block(input: &[u8], output_size: u64, compression_method: &str) -> Vec<u8> {
-    match compression_method {
-        "Oodle" => oodle::decompress_stream(output_size, input).unwrap(),
-        "Zlib" => decompress_zlib(output_size, input),
-        _ => Vec::new(),
+/// Hashes a filename (relative to the mount point) the way the path hash index of a pak `version` is keyed
+///
+/// This is FNV-64 over the lowercased UTF-16 filename, with the offset basis shifted by the index's seed.
+/// Before `FNV64_BUGFIX`, the engine's FNV-64 used the 32 bit offset basis and prime, so older paks are hashed with those.
+pub fn hash_path(filename: &str, seed: u64, version: u32) -> u64 {
+    let (offset, prime) = match version >= pak_version::FNV64_BUGFIX {
+        true => (0xcbf29ce484222325u64, 0x100000001b3u64),
+        false => (0x811c9dc5, 0x01000193),
+    };
+    let mut hash = offset.wrapping_add(seed);
+    for unit in filename.to_lowercase().encode_utf16() {
+        for byte in unit.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(prime);
+        }
     }
-}
-
-fn decompress_zlib(output_size: u64, input: &[u8]) -> Vec<u8> {
-    let mut data = vec![0u8; output_size as usize];
-    let mut z = ZlibDecoder::new(input);
-    z.read_exact(&mut data).unwrap();
-    data
+    hash
 }
 
 #[allow(dead_code)]
 impl PakExtractor {
     /// Create a `PakExtractor` by specifying the path to the pak file on disk, and the encryption key to the file index
     pub fn new(path: &str, key: &str) -> ParserResult<Self> {
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::End(-(PAK_SIZE as i64)))?;
-        let mut header_b = vec![0u8; PAK_SIZE as usize];
-        reader.read_exact(&mut header_b)?;
-
-        let mut header_reader = Cursor::new(header_b.as_slice());
-        let header = FPakInfo::new(&mut header_reader)?;
-
-        let index_data = get_index(&header, &mut reader, key);
-        let mut index_reader = Cursor::new(index_data.as_slice());
-        let mut index = FPakIndex::new(&mut index_reader)?;
-
-        // read directory index
-        reader.seek(SeekFrom::Start(index.dir_index.0 as u64))?;
-        let mut dir_index_b = vec![0u8; index.dir_index.1 as usize];
-        reader.read_exact(&mut dir_index_b)?;
-
-        let key = hex::decode(key).expect("Hex error");
-        let decrypt = Ecb::<Aes256, ZeroPadding>::new_from_slices(&key, Default::default()).unwrap();
-        decrypt.decrypt(&mut dir_index_b).unwrap();
-        let mut directory_reader = Cursor::new(dir_index_b.as_slice());
+        Self::new_with_mode(path, key, SourceMode::File)
+    }
 
-        index.update_from_index(&mut directory_reader)?;
+    /// Create a `PakExtractor`, choosing whether the pak file is read through a file handle or memory mapped
+    pub fn new_with_mode(path: &str, key: &str, mode: SourceMode) -> ParserResult<Self> {
+        let mut archive = Self::new_hashed(path, key, mode)?;
+        archive.load_entries().map_err(|v| ParserError::add(v, format!("Pak index of {}", path)))?;
+        Ok(archive)
+    }
 
-        Ok(Self {
+    /// Create a `PakExtractor` without reading the full directory index, which is the slow part of opening a large pak
+    ///
+    /// Files can still be found by filename with `find_path`. `get_entries` is empty until `load_entries` is called,
+    /// unless the pak has no path hash index, in which case every entry is read up front anyway.
+    pub fn new_hashed(path: &str, key: &str, mode: SourceMode) -> ParserResult<Self> {
+        let reader = ContainerSource::open(path, mode)?;
+        let header = FPakInfo::read(&reader).map_err(|v| ParserError::add(v, format!("Pak footer of {}", path)))?;
+        let key = match header.encrypted_index {
+            true => decode_key(key)?,
+            false => decode_key(key).unwrap_or_default(),
+        };
+
+        let err = |v| ParserError::add(v, format!("Pak index of {}", path));
+        let index_data = get_index(&header, &reader, &key).map_err(err)?;
+        let index = match header.index_frozen {
+            true => FPakIndex::new_frozen(&index_data).map_err(err)?,
+            false => {
+                if header.encrypted_index && !is_mount_point(&index_data) {
+                    return Err(err(ParserError::typed("AES key does not unlock the index".to_owned(), ParserType::Decryption)));
+                }
+                let mut index_reader = Cursor::new(index_data.as_slice());
+                FPakIndex::new(&mut index_reader, &header).map_err(err)?
+            },
+        };
+
+        let path_hash_index = match index.path_index {
+            Some(path_index) => {
+                let mut path_index_b = reader.read_vec(path_index.0 as u64, path_index.1 as usize).map_err(err)?;
+                if header.encrypted_index {
+                    decrypt_buffer(&key, &mut path_index_b).map_err(err)?;
+                }
+                let mut path_index_reader = Cursor::new(path_index_b.as_slice());
+                FPakIndex::read_path_hash_index(&mut path_index_reader).map_err(err)?
+            },
+            _ => HashMap::new(),
+        };
+
+        let mut archive = Self {
             header,
             index,
+            entry_index: HashMap::new(),
+            path_hash_index,
             key,
             reader,
-        })
+        };
+        archive.update_entry_index();
+
+        // Without a path hash index, `find_path` can only look files up in the full directory index
+        if archive.path_hash_index.is_empty() {
+            archive.load_entries().map_err(err)?;
+        }
+
+        Ok(archive)
     }
 
-    pub fn new_header(path: &str) -> ParserResult<FPakInfo> {
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::End(-(PAK_SIZE as i64)))?;
-        let mut header_b = vec![0u8; PAK_SIZE as usize];
-        reader.read_exact(&mut header_b)?;
+    /// Reads the full directory index, listing every entry in `get_entries`
+    pub fn load_entries(&mut self) -> ParserResult<()> {
+        if self.header.version < pak_version::PATH_HASH_INDEX || !self.index.index_entries.is_empty() {
+            return Ok(());
+        }
+
+        let dir_index = match self.index.dir_index {
+            Some(dir_index) => dir_index,
+            None => return Err(ParserError::typed("No directory index present".to_owned(), ParserType::Malformed)),
+        };
+        let mut dir_index_b = self.reader.read_vec(dir_index.0 as u64, dir_index.1 as usize)?;
+        if self.header.encrypted_index {
+            decrypt_buffer(&self.key, &mut dir_index_b)?;
+        }
+        let mut directory_reader = Cursor::new(dir_index_b.as_slice());
+
+        self.index.update_from_index(&mut directory_reader)?;
+        self.update_entry_index();
 
-        let mut header_reader = Cursor::new(header_b.as_slice());
-        let header = FPakInfo::new(&mut header_reader)?;
+        Ok(())
+    }
+
+    fn update_entry_index(&mut self) {
+        // When a path is listed more than once, the first entry is used, as a linear search would find
+        self.entry_index = HashMap::new();
+        for (i, entry) in self.index.index_entries.iter().enumerate() {
+            self.entry_index.entry(entry.filename.clone()).or_insert(i);
+        }
+    }
 
-        Ok(header)
+    pub fn new_header(path: &str) -> ParserResult<FPakInfo> {
+        let reader = ContainerSource::open(path, SourceMode::File)?;
+        FPakInfo::read(&reader)
+    }
+
+    pub fn get_header(&self) -> &FPakInfo {
+        &self.header
     }
 
     /// Get a list of `FPakEntry` that can be used with `get_file` to extract files.
@@ -430,49 +911,414 @@ impl PakExtractor {
         &self.index.index_entries
     }
 
+    /// Finds the `FPakEntry` for a filename, relative to the mount point
+    pub fn find_entry(&self, filename: &str) -> Option<&FPakEntry> {
+        self.entry_index.get(filename).map(|v| &self.index.index_entries[*v])
+    }
+
+    /// Finds the `FPakEntry` for a filename (relative to the mount point) through the path hash index
+    ///
+    /// Unlike `find_entry`, this works without the full directory index having been read, and matches case-insensitively.
+    /// Paks without a path hash index fall back to searching the entries, since every entry was read when the pak was opened.
+    pub fn find_path(&self, filename: &str) -> ParserResult<Option<FPakEntry>> {
+        if self.path_hash_index.is_empty() {
+            if let Some(entry) = self.find_entry(filename) {
+                return Ok(Some(entry.clone()));
+            }
+            let filename = filename.to_lowercase();
+            return Ok(self.index.index_entries.iter().find(|v| v.filename.to_lowercase() == filename).cloned());
+        }
+
+        let location = match self.path_hash_index.get(&hash_path(filename, self.index.path_hash_seed, self.header.version)) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+        let mut encoded_cursor = Cursor::new(self.index.encoded_pak.as_slice());
+        self.index.get_encoded_entry(&mut encoded_cursor, filename.to_owned(), location).map(Some)
+    }
+
+    /// Opens a `Read` + `Seek` stream over a single entry, which only decrypts and decompresses the blocks that are read
+    ///
+    /// Note that the `FPakEntry` must come from the same `PakExtractor`
+    pub fn open(&self, file: &FPakEntry) -> ParserResult<PakEntryReader<'_>> {
+        if file.compression_method != 0 {
+            self.get_compression_method(file)?;
+        }
+
+        Ok(PakEntryReader {
+            archive: self,
+            entry: file.clone(),
+            position: 0,
+            current_block: None,
+        })
+    }
+
     /// Uses an `FPakEntry` to seek to and extract a file from a `.pak` file
     ///
-    /// Note that the `FPakEntry` must come from the same `PakExtractor`, using a mismatched one will panic
-    pub fn get_file(&mut self, file: &FPakEntry) -> Vec<u8> {
+    /// Note that the `FPakEntry` must come from the same `PakExtractor`
+    pub fn get_file(&self, file: &FPakEntry) -> ParserResult<Vec<u8>> {
+        self.read_entry(file).map_err(|v| ParserError::add(v, format!("Entry: {}", file.filename)))
+    }
+
+    fn read_entry(&self, file: &FPakEntry) -> ParserResult<Vec<u8>> {
         let start_pos = file.position as u64 + file.struct_size;
-        self.reader.seek(SeekFrom::Start(start_pos)).unwrap();
 
-        let mut buffer = vec![0u8; file.size as usize];
+        // Encrypted, compressed entries pad each block, so can take up more than their size
+        let data_size = match file.compression_blocks.last() {
+            Some(block) if file.compression_method != 0 => std::cmp::max(file.size, (block.compressed_end - file.struct_size as i64) as u64),
+            _ => file.size,
+        };
+        let read_size = match file.encrypted {
+            true => align_size(data_size),
+            false => data_size,
+        };
 
+        let mut buffer = self.reader.read_vec(start_pos, read_size as usize)?;
         if file.encrypted {
-            let enc_size = match file.size % 16 {
-                0 => file.size,
-                _ => ((file.size / 16) + 1) * 16,
-            };
-            let mut enc_buffer = vec![0u8; enc_size as usize];
-            self.reader.read_exact(&mut enc_buffer).unwrap();
-
-            let decrypt = Ecb::<Aes256, ZeroPadding>::new_from_slices(&self.key, Default::default()).unwrap();
-            decrypt.decrypt(&mut enc_buffer).unwrap();
+            decrypt_buffer(&self.key, &mut buffer)?;
+        }
 
-            buffer.copy_from_slice(&enc_buffer[..file.size as usize]);
+        if file.compression_method == 0 {
+            buffer.truncate(file.size as usize);
         } else {
-            self.reader.read_exact(&mut buffer).unwrap();
+            let compression_method = self.get_compression_method(file)?;
+
+            // Blocks are independent of each other, so they're decompressed in parallel then joined back in order
+            let decompressed_blocks: ParserResult<Vec<Vec<u8>>> = file.compression_blocks.par_iter().enumerate().map(|(i, block)| {
+                let block_start = (block.compressed_start - file.struct_size as i64) as usize;
+                let block_end = (block.compressed_end - file.struct_size as i64) as usize;
+                let block_buffer = match buffer.get(block_start..block_end) {
+                    Some(data) => data,
+                    None => return Err(ParserError::typed(format!("Block {} is outside of the entry: {}..{}", i, block_start, block_end), ParserType::Malformed)),
+                };
+                let uncompressed_start = i as u64 * file.compression_block_size as u64;
+                let result_size = std::cmp::min(file.compression_block_size as u64, file.uncompressed_size.saturating_sub(uncompressed_start));
+                decompress::decompress(compression_method, block_buffer, result_size as usize)
+                    .map_err(|v| ParserError::add(v, format!("Block {}", i)))
+            }).collect();
+
+            buffer = decompressed_blocks?.concat();
         }
 
-        if file.compression_method != 0 {
-            let mut decompressed_buffer = Vec::new();
-            let compression_method = &self.header.compression_methods[(file.compression_method - 1) as usize];
-
-            for block in &file.compression_blocks {
-                let block_buffer = &buffer[((block.compressed_start - file.struct_size as i64) as usize)..((block.compressed_end - file.struct_size as i64) as usize)];
-                let result_size = std::cmp::min(file.compression_block_size as u64, file.uncompressed_size - decompressed_buffer.len() as u64);
-                let decompressed_block = decompress_block(block_buffer, result_size, compression_method);
-                decompressed_buffer.extend_from_slice(&decompressed_block);
-            }
+        Ok(buffer)
+    }
+
+    fn get_compression_method(&self, file: &FPakEntry) -> ParserResult<&str> {
+        match self.header.compression_methods.get((file.compression_method as usize).wrapping_sub(1)) {
+            Some(method) => Ok(method),
+            None => Err(ParserError::typed(format!("Unknown compression method index: {}", file.compression_method), ParserType::Decompression)),
+        }
+    }
+
+    /// The uncompressed size of each block a `PakEntryReader` reads from an entry
+    fn get_read_block_size(file: &FPakEntry) -> u64 {
+        match file.compression_method {
+            0 => UNCOMPRESSED_READ_SIZE,
+            _ => std::cmp::max(file.compression_block_size as u64, 1),
+        }
+    }
 
-            buffer = decompressed_buffer;
+    /// Reads, decrypts and decompresses a single block of an entry, see `get_read_block_size`
+    fn read_block(&self, file: &FPakEntry, idx: usize) -> ParserResult<Vec<u8>> {
+        let (start, length) = match file.compression_method {
+            0 => {
+                let start = idx as u64 * UNCOMPRESSED_READ_SIZE;
+                (file.struct_size + start, std::cmp::min(UNCOMPRESSED_READ_SIZE, file.size.saturating_sub(start)))
+            },
+            _ => match file.compression_blocks.get(idx) {
+                Some(block) if block.compressed_end >= block.compressed_start && block.compressed_start >= 0 => {
+                    (block.compressed_start as u64, (block.compressed_end - block.compressed_start) as u64)
+                },
+                _ => return Err(ParserError::typed(format!("Block {} is outside of the entry", idx), ParserType::Malformed)),
+            },
+        };
+
+        let read_size = match file.encrypted {
+            true => align_size(length),
+            false => length,
+        };
+        let mut buffer = self.reader.read_vec(file.position as u64 + start, read_size as usize)?;
+        if file.encrypted {
+            decrypt_buffer(&self.key, &mut buffer)?;
+            buffer.truncate(length as usize);
+        }
+
+        if file.compression_method == 0 {
+            return Ok(buffer);
+        }
+
+        let uncompressed_start = idx as u64 * file.compression_block_size as u64;
+        let result_size = std::cmp::min(file.compression_block_size as u64, file.uncompressed_size.saturating_sub(uncompressed_start));
+        decompress::decompress(self.get_compression_method(file)?, &buffer, result_size as usize)
+    }
+
+    /// Checks the index against the SHA1 hash in the footer, or `None` if the footer has no hash
+    pub fn check_index_hash(&self) -> ParserResult<Option<bool>> {
+        if self.header.index_hash == [0u8; 20] {
+            return Ok(None);
+        }
+        let index = get_index(&self.header, &self.reader, &self.key)?;
+        Ok(Some(sha1_smol::Sha1::from(&index).digest().bytes() == self.header.index_hash))
+    }
+
+    /// Checks an entry against its SHA1 hash, or `None` if it has no hash
+    ///
+    /// The hash covers the entry's data as it is stored, so nothing is decrypted or decompressed.
+    pub fn check_entry_hash(&self, file: &FPakEntry) -> ParserResult<Option<bool>> {
+        // Entries from an encoded index have no hash, but the copy of the entry stored before its data does
+        let expected = match file.hash == [0u8; 20] {
+            false => file.hash,
+            true => {
+                let header = self.reader.read_vec(file.position as u64, file.struct_size as usize)?;
+                let mut reader = Cursor::new(header.as_slice());
+                FPakEntry::new(&mut reader, file.filename.clone(), &self.header)?.hash
+            },
+        };
+        if expected == [0u8; 20] {
+            return Ok(None);
+        }
+
+        // The hash covers the data as stored, so encrypted uncompressed entries include their padding
+        let hash_size = match file.encrypted && file.compression_method == 0 {
+            true => align_size(file.size),
+            false => file.size,
+        };
+
+        let mut hasher = sha1_smol::Sha1::new();
+        let start = file.position as u64 + file.struct_size;
+        let mut offset = 0;
+        while offset < hash_size {
+            let length = std::cmp::min(HASH_READ_SIZE, hash_size - offset);
+            hasher.update(&self.reader.read_vec(start + offset, length as usize)?);
+            offset += length;
+        }
+        Ok(Some(hasher.digest().bytes() == expected))
+    }
+
+    /// Checks every 64KB chunk of the pak file against a signature file, returning the indexes of the chunks that don't match
+    pub fn check_signature(&self, signature: &FPakSignatureFile) -> ParserResult<Vec<usize>> {
+        let size = self.reader.get_size()?;
+        let chunk_count = size.div_ceil(PAK_SIGNATURE_CHUNK_SIZE) as usize;
+        if signature.chunk_hashes.len() != chunk_count {
+            return Err(ParserError::typed(format!("Signature file has {} chunks, pak has {}", signature.chunk_hashes.len(), chunk_count), ParserType::Malformed));
         }
 
-        buffer
+        let mut mismatched = Vec::new();
+        for (i, expected) in signature.chunk_hashes.iter().enumerate() {
+            let start = i as u64 * PAK_SIGNATURE_CHUNK_SIZE;
+            let length = std::cmp::min(PAK_SIGNATURE_CHUNK_SIZE, size - start);
+            let chunk = self.reader.read_vec(start, length as usize)?;
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&chunk);
+            if hasher.finalize() != *expected {
+                mismatched.push(i);
+            }
+        }
+        Ok(mismatched)
+    }
+
+    /// The entries with stored data in a 64KB chunk of the pak file, see `check_signature`
+    pub fn get_chunk_entries(&self, chunk: usize) -> Vec<&FPakEntry> {
+        let start = chunk as u64 * PAK_SIGNATURE_CHUNK_SIZE;
+        let end = start + PAK_SIGNATURE_CHUNK_SIZE;
+        self.index.index_entries.iter().filter(|v| {
+            let entry_start = v.position as u64;
+            let entry_end = entry_start + v.struct_size + v.size;
+            entry_start < end && entry_end > start
+        }).collect()
     }
 
     pub fn get_mount_point(&self) -> &str {
         &self.index.mount_point
     }
 }
+
+/// PakEntryReader is a `Read` + `Seek` stream over the uncompressed contents of one entry in a `.pak` file
+///
+/// Blocks are decoded as they are read, and only the most recent block is kept.
+pub struct PakEntryReader<'a> {
+    archive: &'a PakExtractor,
+    entry: FPakEntry,
+    position: u64,
+    current_block: Option<(usize, Vec<u8>)>,
+}
+
+impl<'a> PakEntryReader<'a> {
+    pub fn get_entry(&self) -> &FPakEntry {
+        &self.entry
+    }
+
+    /// The uncompressed size of the entry
+    pub fn get_size(&self) -> u64 {
+        match self.entry.compression_method {
+            0 => self.entry.size,
+            _ => self.entry.uncompressed_size,
+        }
+    }
+
+    fn load_block(&mut self, idx: usize) -> ParserResult<&[u8]> {
+        let loaded = matches!(&self.current_block, Some((current, _)) if *current == idx);
+        if !loaded {
+            let block = self.archive.read_block(&self.entry, idx)
+                .map_err(|v| ParserError::add(v, format!("Block {} of {}", idx, self.entry.filename)))?;
+            self.current_block = Some((idx, block));
+        }
+        match &self.current_block {
+            Some((_, block)) => Ok(block),
+            None => Ok(&[]),
+        }
+    }
+}
+
+impl<'a> Read for PakEntryReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.get_size();
+        if self.position >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = PakExtractor::get_read_block_size(&self.entry);
+        let idx = (self.position / block_size) as usize;
+        let block_offset = (self.position % block_size) as usize;
+        let block = match self.load_block(idx) {
+            Ok(block) => block,
+            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        };
+        if block_offset >= block.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "block is shorter than the entry says"));
+        }
+
+        let length = std::cmp::min(buf.len(), block.len() - block_offset);
+        buf[..length].copy_from_slice(&block[block_offset..(block_offset + length)]);
+        self.position += length as u64;
+
+        Ok(length)
+    }
+}
+
+impl<'a> Seek for PakEntryReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(p) => self.position.checked_add_signed(p),
+            SeekFrom::End(p) => self.get_size().checked_add_signed(p),
+        };
+
+        // Blocks are only decoded once they're read from, so seeking is free
+        match target {
+            Some(target) => {
+                self.position = target;
+                Ok(target)
+            },
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds memory images, with arrays written after the structs that point to them
+    struct FrozenWriter {
+        data: Vec<u8>,
+    }
+
+    impl FrozenWriter {
+        fn reserve(&mut self, size: usize) -> usize {
+            let pos = self.data.len();
+            self.data.resize(pos + size, 0);
+            pos
+        }
+
+        fn put(&mut self, pos: usize, bytes: &[u8]) {
+            self.data[pos..(pos + bytes.len())].copy_from_slice(bytes);
+        }
+
+        /// Points the array at `pos` to `num` elements at `target`
+        fn array(&mut self, pos: usize, target: usize, num: usize) {
+            self.put(pos, &((((target as i64 - pos as i64) << 1) | 1) as u64).to_le_bytes());
+            self.put(pos + 8, &(num as i32).to_le_bytes());
+            self.put(pos + 12, &(num as i32).to_le_bytes());
+        }
+
+        fn string(&mut self, pos: usize, value: &str) {
+            let units: Vec<u16> = value.encode_utf16().chain(std::iter::once(0)).collect();
+            let target = self.reserve(units.len() * 2);
+            for (i, unit) in units.iter().enumerate() {
+                self.put(target + i * 2, &unit.to_le_bytes());
+            }
+            self.array(pos, target, units.len());
+        }
+
+        /// Fills in the map at `pos` with `count` elements, all in use but `free`, returning where the elements start
+        fn map(&mut self, pos: usize, count: usize, element_size: usize, free: &[usize]) -> usize {
+            let elements = self.reserve(count * element_size);
+            self.array(pos, elements, count);
+            let flags = self.reserve(count.div_ceil(32) * 4);
+            for i in (0..count).filter(|v| !free.contains(v)) {
+                self.data[flags + i / 8] |= 1 << (i % 8);
+            }
+            self.array(pos + 16, flags, count);
+            elements
+        }
+    }
+
+    #[test]
+    fn frozen_map_flags() {
+        let mut writer = FrozenWriter { data: Vec::new() };
+        writer.reserve(32);
+        let elements = writer.map(0, 40, 4, &[1, 35]);
+
+        // The flags are the last thing in the image, so reading them as 40 words would leave it
+        assert_eq!(writer.data.len(), elements + 40 * 4 + 8);
+        let reader = FrozenReader { data: &writer.data };
+        let expected: Vec<usize> = (0..40).filter(|v| *v != 1 && *v != 35).map(|v| elements + v * 4).collect();
+        assert_eq!(reader.read_map(0, 4).unwrap(), expected);
+
+        // Flags that don't fit in the image are an error
+        writer.data.truncate(writer.data.len() - 1);
+        let reader = FrozenReader { data: &writer.data };
+        assert!(reader.read_map(0, 4).is_err());
+    }
+
+    #[test]
+    fn frozen_index() {
+        let mut writer = FrozenWriter { data: Vec::new() };
+        writer.reserve(88);
+        writer.string(0, "../../../");
+
+        let files = writer.reserve(2 * FROZEN_PAK_ENTRY_SIZE);
+        writer.array(16, files, 2);
+        for (i, (position, size)) in [(0i64, 100i64), (200, 50)].iter().enumerate() {
+            let entry = files + i * FROZEN_PAK_ENTRY_SIZE;
+            writer.put(entry, &position.to_le_bytes());
+            writer.put(entry + 8, &size.to_le_bytes());
+            writer.put(entry + 16, &size.to_le_bytes());
+            writer.put(entry + 24, &[i as u8 + 1; 20]);
+        }
+        writer.put(files + FROZEN_PAK_ENTRY_SIZE + 72, &[1]);
+
+        let directories = writer.map(32, 1, FROZEN_DIRECTORY_ELEMENT_SIZE, &[]);
+        writer.string(directories, "Game/Content/");
+        let dir_files = writer.map(directories + 16, 2, FROZEN_FILE_ELEMENT_SIZE, &[]);
+        writer.string(dir_files, "B.uasset");
+        writer.put(dir_files + 16, &1i32.to_le_bytes());
+        writer.string(dir_files + FROZEN_FILE_ELEMENT_SIZE, "A.uasset");
+        writer.put(dir_files + FROZEN_FILE_ELEMENT_SIZE + 16, &0i32.to_le_bytes());
+
+        let index = FPakIndex::new_frozen(&writer.data).unwrap();
+        assert_eq!(index.get_mount_point(), "../../../");
+        let entries = index.get_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].get_filename(), "Game/Content/B.uasset");
+        assert_eq!((entries[0].position, entries[0].size, entries[0].encrypted), (200, 50, true));
+        assert_eq!(entries[0].hash, [2; 20]);
+        assert_eq!(entries[1].get_filename(), "Game/Content/A.uasset");
+        assert_eq!((entries[1].position, entries[1].size, entries[1].encrypted), (0, 100, false));
+
+        // Pointers that leave the image are errors, not panics
+        writer.put(16, &(1u64 << 40 | 1).to_le_bytes());
+        assert!(FPakIndex::new_frozen(&writer.data).is_err());
+    }
+}
@@ -10,9 +10,11 @@ use serde_json::error::Error as JSONError;
 use erased_serde::{serialize_trait_object, Serialize as TraitSerialize};
 use byteorder::{LittleEndian, ReadBytesExt};
 use bit_vec::BitVec;
-use lazy_static::lazy_static;
 use crate::mapping::{MappingStore, PropertyMapping, TagMapping};
-use crate::dispatch::{LoaderGlobalData, InitialLoadMetaData, FNameMap};
+use crate::decompress::oodle::OodleError;
+use crate::context::GameContext;
+use crate::dispatch::{Extractor, InitialLoadMetaData, FNameMap};
+use crate::packages::PackageIdResolver;
 
 pub mod locale;
 // mod material_instance;
@@ -25,24 +27,65 @@ mod sound;
 //     FSkelMeshRenderSection, FSkeletalMaterial, FSkinWeightVertexBuffer, FMeshBoneInfo, FStaticMeshVertexDataUV, FReferenceSkeleton};
 pub use sound::USoundWave;
 
-lazy_static! {
-    static ref MAPPINGS: MappingStore = MappingStore::build_mappings().unwrap();
-}
-
 pub type ReaderCursor<'c> = Cursor<&'c[u8]>;
 
-#[derive(Debug, Serialize)]
+/// The kind of failure a `ParserError` represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParserType {
     Unknown,
+    /// Reading from a file or container failed
+    Io,
+    /// A key was missing, malformed or did not decrypt the data
+    Decryption,
+    /// A compressed block could not be decompressed
+    Decompression,
+    /// A class, enum or property index had no entry in the mappings
+    MissingMapping,
+    /// A property type the parser can not read
+    UnsupportedProperty,
+    /// An export type the parser can not read
+    UnsupportedExport,
+    /// The data did not match the expected layout, eg. bad magic, a length out of range or an invalid string
+    Malformed,
+    #[deprecated(note = "no longer returned, missing class mappings are reported as `MissingMapping`")]
     ClassMappingMissing,
+    #[deprecated(note = "no longer returned, missing property indexes are reported as `MissingMapping`")]
     PropertyIndexMissing,
 }
 
+// Written out rather than derived, as the derived impl would warn on the deprecated variants
+impl Serialize for ParserType {
+    #[allow(deprecated)]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let (index, name) = match self {
+            ParserType::Unknown => (0, "Unknown"),
+            ParserType::Io => (1, "Io"),
+            ParserType::Decryption => (2, "Decryption"),
+            ParserType::Decompression => (3, "Decompression"),
+            ParserType::MissingMapping => (4, "MissingMapping"),
+            ParserType::UnsupportedProperty => (5, "UnsupportedProperty"),
+            ParserType::UnsupportedExport => (6, "UnsupportedExport"),
+            ParserType::Malformed => (7, "Malformed"),
+            ParserType::ClassMappingMissing => (8, "ClassMappingMissing"),
+            ParserType::PropertyIndexMissing => (9, "PropertyIndexMissing"),
+        };
+        serializer.serialize_unit_variant("ParserType", index, name)
+    }
+}
+
 /// ParserError contains a list of error messages that wind down to where the parser was not able to parse a property
+///
+/// Along with the messages, it records what kind of error it is, the byte offset it happened at, the export being read
+/// and the path of properties leading to it, where they are known.
 #[derive(Debug, Serialize)]
 pub struct ParserError {
     property_list: Vec<String>,
     error_type: ParserType,
+    offset: Option<u64>,
+    export_index: Option<usize>,
+    property_path: Vec<String>,
+    #[serde(skip)]
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
 }
 
 impl fmt::Display for ParserError {
@@ -53,10 +96,7 @@ impl fmt::Display for ParserError {
 
 impl ParserError {
     pub fn new(start: String) -> Self {
-        Self {
-            property_list: vec![start],
-            error_type: ParserType::Unknown,
-        }
+        Self::typed(start, ParserType::Unknown)
     }
 
     pub fn add(mut error: ParserError, property: String) -> Self {
@@ -64,6 +104,20 @@ impl ParserError {
         error
     }
 
+    /// Adds a property to the error's property path, along with the offset it started at
+    pub fn property(mut error: ParserError, name: &str, offset: u64) -> Self {
+        error.property_list.push(format!("Property: {} at {}", name, offset));
+        error.property_path.push(name.to_owned());
+        error.offset.get_or_insert(offset);
+        error
+    }
+
+    /// Records the export being read when the error happened
+    pub fn export(mut error: ParserError, export_index: usize) -> Self {
+        error.export_index.get_or_insert(export_index);
+        error
+    }
+
     pub fn get_properties(&self) -> &Vec<String> {
         &self.property_list
     }
@@ -72,39 +126,80 @@ impl ParserError {
         Self {
             property_list: vec![start],
             error_type,
+            offset: None,
+            export_index: None,
+            property_path: Vec::new(),
+            source: None,
         }
     }
 
+    /// Sets the byte offset the error happened at, if it hasn't been set by a more specific error already
+    pub fn at_offset(mut self, offset: u64) -> Self {
+        self.offset.get_or_insert(offset);
+        self
+    }
+
+    pub fn with_source<E: std::error::Error + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
     pub fn get_type(&self) -> &ParserType {
         &self.error_type
     }
+
+    pub fn get_offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    pub fn get_export_index(&self) -> Option<usize> {
+        self.export_index
+    }
+
+    /// The properties leading to the error, outermost first, eg. `["Materials", "MaterialInterface"]`
+    pub fn get_property_path(&self) -> Vec<&str> {
+        self.property_path.iter().rev().map(|v| v.as_ref()).collect()
+    }
 }
 
 impl From<std::io::Error> for ParserError {
     fn from(error: std::io::Error) -> ParserError {
-        ParserError::new(format!("File Error: {}", error))
+        ParserError::typed(format!("File Error: {}", error), ParserType::Io).with_source(error)
     }
 }
 
 impl From<std::str::Utf8Error> for ParserError {
-    fn from(_error: std::str::Utf8Error) -> ParserError {
-        ParserError::new("UTF8 Error".to_owned())
+    fn from(error: std::str::Utf8Error) -> ParserError {
+        ParserError::typed("UTF8 Error".to_owned(), ParserType::Malformed).with_source(error)
     }
 }
 
 impl From<std::string::FromUtf16Error> for ParserError {
-    fn from(_error: std::string::FromUtf16Error) -> ParserError {
-        ParserError::new("UTF16 Error".to_owned())
+    fn from(error: std::string::FromUtf16Error) -> ParserError {
+        ParserError::typed("UTF16 Error".to_owned(), ParserType::Malformed).with_source(error)
     }
 }
 
 impl From<JSONError> for ParserError {
     fn from(error: JSONError) -> ParserError {
-        ParserError::new(format!("JSON Error: {}", error))
+        ParserError::typed(format!("JSON Error: {}", error), ParserType::Malformed).with_source(error)
+    }
+}
+
+impl From<OodleError> for ParserError {
+    fn from(error: OodleError) -> ParserError {
+        ParserError::typed(format!("Oodle error: {}", error), ParserType::Decompression).with_source(error)
     }
 }
 
-impl std::error::Error for ParserError { }
+impl std::error::Error for ParserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.source {
+            Some(source) => Some(source.as_ref()),
+            None => None,
+        }
+    }
+}
 
 pub type ParserResult<T> = Result<T, ParserError>;
 
@@ -112,7 +207,7 @@ pub trait Newable {
     fn new(reader: &mut ReaderCursor) -> ParserResult<Self> where Self: Sized;
 }
 
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
 pub struct FGuid {
     a: u32,
     b: u32,
@@ -143,6 +238,27 @@ impl fmt::Display for FGuid {
     }
 }
 
+impl std::str::FromStr for FGuid {
+    type Err = ParserError;
+
+    /// Parses a GUID written as 32 hex digits, as printed by `Display`. Dashes and braces are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: String = s.chars().filter(|v| !matches!(v, '-' | '{' | '}')).collect();
+        let invalid = || ParserError::typed(format!("Invalid GUID: {}", s), ParserType::Malformed);
+        if digits.len() != 32 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+
+        let part = |i: usize| u32::from_str_radix(&digits[(i * 8)..((i + 1) * 8)], 16).map_err(|_| invalid());
+        Ok(Self {
+            a: part(0)?,
+            b: part(1)?,
+            c: part(2)?,
+            d: part(3)?,
+        })
+    }
+}
+
 impl Serialize for FGuid {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
         serializer.serialize_str(&self.to_string())
@@ -192,7 +308,7 @@ pub fn read_short_string(reader: &mut ReaderCursor) -> ParserResult<String> {
 pub fn read_string(reader: &mut ReaderCursor) -> ParserResult<String> {
     let mut length = reader.read_i32::<LittleEndian>()?;
     if length > 65536 || length < -65536 {
-        return Err(ParserError::new(format!("String length too large ({}), likely a read error.", length)));
+        return Err(ParserError::typed(format!("String length too large ({}), likely a read error.", length), ParserType::Malformed).at_offset(reader.position() - 4));
     }
 
     if length == 0 {
@@ -715,9 +831,11 @@ struct NameMap {
     global: Arc<FNameMap>,
 }
 
+/// The import map also carries the mappings for unversioned properties, as it is passed to everything that reads properties
 struct ImportMap {
     imports: Vec<FPackageObjectIndex>,
     global: Arc<InitialLoadMetaData>,
+    mappings: Arc<MappingStore>,
 }
 
 trait NewableWithNameMap: std::fmt::Debug + TraitSerialize {
@@ -861,7 +979,7 @@ impl FExportMapEntry {
         match self.class_index.index_type {
             FPackageObjectIndex_Type::ScriptImport => self.class_index.get_export_name(name_map, import_map),
             FPackageObjectIndex_Type::PackageImport => self.get_object_name(name_map),
-            _ => Err(ParserError::new(format!("Unknown Export Map Type"))),
+            _ => Err(ParserError::typed("Unknown Export Map Type".to_owned(), ParserType::UnsupportedExport)),
         }
     }
 
@@ -1857,8 +1975,8 @@ impl UScriptArray {
         let mut data = Vec::new();
         for i in 0..element_count {
             let cpos = reader.position();
-            let err = |v| ParserError::add(v, format!("Array Item: {} of {} at {}", i, element_count, cpos));
-            data.push(read_unversioned_tag(reader, name_map, import_map, mapping).map_err(err)?);
+            let err = |v: ParserError| ParserError::add(v, format!("Array Item: {} of {} at {}", i, element_count, cpos));
+            data.push(read_unversioned_tag(reader, name_map, import_map, mapping).map_err(|v| err(v.at_offset(reader.position())))?);
         }
         
         Ok(Self {
@@ -2025,7 +2143,7 @@ fn read_unversioned_tag(reader: &mut ReaderCursor, name_map: &NameMap, import_ma
         TagMapping::SoftObjectProperty => FPropertyTagType::SoftObjectProperty(FSoftObjectPath::new_n(reader, name_map, import_map)?),
         TagMapping::EnumProperty { enum_name } => {
             let val = reader.read_u8()?;
-            let data = match MAPPINGS.get_enum_mapping(enum_name, val as usize) {
+            let data = match import_map.mappings.get_enum_mapping(enum_name, val as usize) {
                 Some(d) => d.to_owned(),
                 None => val.to_string(),
             };
@@ -2038,16 +2156,16 @@ fn read_unversioned_tag(reader: &mut ReaderCursor, name_map: &NameMap, import_ma
         TagMapping::ByteProperty => FPropertyTagType::ByteProperty(reader.read_u8()?),
         TagMapping::IntProperty => FPropertyTagType::IntProperty(reader.read_i32::<LittleEndian>()?),
         TagMapping::FloatProperty => FPropertyTagType::FloatProperty(reader.read_f32::<LittleEndian>()?),
-        TagMapping::DebugProperty => return Err(ParserError::new(format!("Encountered DebugProperty - Stopping"))),
-        _ => return Err(ParserError::new(format!("Unsupported Property Type: {:#?}", mapping))),
+        TagMapping::DebugProperty => return Err(ParserError::typed("Encountered DebugProperty - Stopping".to_owned(), ParserType::UnsupportedProperty)),
+        _ => return Err(ParserError::typed(format!("Unsupported Property Type: {:#?}", mapping), ParserType::UnsupportedProperty)),
     })
 }
 
 fn read_unversioned_property(reader: &mut ReaderCursor, name_map: &NameMap, import_map: &ImportMap, mapping: &PropertyMapping) -> ParserResult<FPropertyTag> {
     let start_pos = reader.position();
 
-    let err = |v| ParserError::add(v, format!("Property: {} at {}", mapping.get_name(), start_pos));
-    let tag = read_unversioned_tag(reader, name_map, import_map, &mapping.get_type()).map_err(err)?;
+    let err = |v: ParserError| ParserError::property(v, mapping.get_name(), start_pos);
+    let tag = read_unversioned_tag(reader, name_map, import_map, &mapping.get_type()).map_err(|v| err(v.at_offset(reader.position())))?;
 
     // println!("Tag: {} {} {:#?}", start_pos, mapping.get_name(), tag);
 
@@ -2452,6 +2570,7 @@ impl Newable for FArc {
 struct FImportedPackage {
     index: u64,
     arcs: Vec<FArc>,
+    name: Option<String>,
 }
 
 impl Newable for FImportedPackage {
@@ -2459,13 +2578,18 @@ impl Newable for FImportedPackage {
         Ok(Self {
             index: reader.read_u64::<LittleEndian>()?,
             arcs: read_tarray(reader)?,
+            name: None,
         })
     }
 }
 
+/// Imported packages are written by name once resolved, or by id otherwise
 impl Serialize for FImportedPackage {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        serializer.serialize_str(&self.index.to_string())
+        match &self.name {
+            Some(name) => serializer.serialize_str(name),
+            None => serializer.serialize_str(&self.index.to_string()),
+        }
     }
 }
 
@@ -2484,7 +2608,7 @@ impl UObject {
         let prop_indices = header.get_indices();
         let indices = prop_indices.iter().map(|v| v.index).collect();
 
-        let mappings = MAPPINGS.get_mappings(export_type, indices)?;
+        let mappings = import_map.mappings.get_mappings(export_type, indices)?;
 
         let mut properties = Vec::new();
         for i in 0..prop_indices.len() {
@@ -2776,15 +2900,20 @@ pub struct Package {
 
 #[allow(dead_code)]
 impl Package {
-    pub fn from_buffer(uasset: &[u8], ubulk: Option<&[u8]>, global_map: &LoaderGlobalData) -> ParserResult<Self> {
+    pub fn from_buffer(uasset: &[u8], ubulk: Option<&[u8]>, context: &GameContext) -> ParserResult<Self> {
+        // Reading stops at the first error, so the cursor is left where it happened
         let mut cursor = ReaderCursor::new(uasset);
-        let summary = FPackageSummary::new(&mut cursor)?;
+        Self::from_cursor(&mut cursor, ubulk, context).map_err(|v| v.at_offset(cursor.position()))
+    }
+
+    fn from_cursor(cursor: &mut ReaderCursor, ubulk: Option<&[u8]>, context: &GameContext) -> ParserResult<Self> {
+        let summary = FPackageSummary::new(cursor)?;
 
         let mut name_map = Vec::new();
         cursor.seek(SeekFrom::Start(summary.name_map_offset as u64))?;
         
         while cursor.position() <= (summary.name_map_offset + summary.name_map_size) as u64 {
-            name_map.push(read_short_string(&mut cursor)?);
+            name_map.push(read_short_string(cursor)?);
         }
 
         let name_map = FNameMap::from_strings(name_map);
@@ -2793,19 +2922,19 @@ impl Package {
         let import_length = (summary.export_map_offset - summary.import_map_offset) / 8;
         let mut import_map = Vec::new();
         for _i in 0..import_length {
-            import_map.push(FPackageObjectIndex::new(&mut cursor)?);
+            import_map.push(FPackageObjectIndex::new(cursor)?);
         }
         
         let mut export_map = Vec::new();
         while cursor.position() < summary.export_bundle_offset as u64 {
-            export_map.push(FExportMapEntry::new(&mut cursor)?);
+            export_map.push(FExportMapEntry::new(cursor)?);
         }
 
-        let export_bundle = FExportBundle::new(&mut cursor)?;
+        let export_bundle = FExportBundle::new(cursor)?;
         let export_order = export_bundle.get_export_order();
 
         cursor.seek(SeekFrom::Start(summary.graph_data_offset as u64))?;
-        let graph_data: Vec<FImportedPackage> = read_tarray(&mut cursor)?;
+        let graph_data: Vec<FImportedPackage> = read_tarray(cursor)?;
 
         cursor.seek(SeekFrom::Start((summary.graph_data_offset + summary.graph_data_size) as u64))?;
 
@@ -2821,21 +2950,26 @@ impl Package {
 
         let import_map = ImportMap {
             imports: import_map,
-            global: global_map.get_load_data(),
+            global: context.get_global_data().get_load_data(),
+            mappings: context.get_mappings(),
         };
 
         let name_map = NameMap {
             names: name_map,
-            global: global_map.get_name_map(),
+            global: context.get_global_data().get_name_map(),
         };
 
         let mut export_start = cursor.position();
 
         for export_idx in &export_order {
-            let export = &export_map[*export_idx as usize];
-            let export_name = export.get_export_name(&name_map, &import_map)?;
-            let mut export_data = select_export(&export_name, &mut cursor, &name_map, &import_map, &export, &mut ubulk_cursor)
-                .map_err(|v| ParserError::add(v, format!("Export Type: {}", export_name)))?;
+            let export = match export_map.get(*export_idx as usize) {
+                Some(export) => export,
+                None => return Err(ParserError::typed(format!("Export index out of range: {}", export_idx), ParserType::Malformed)),
+            };
+            let export_err = |v| ParserError::export(v, *export_idx as usize);
+            let export_name = export.get_export_name(&name_map, &import_map).map_err(export_err)?;
+            let mut export_data = select_export(&export_name, cursor, &name_map, &import_map, &export, &mut ubulk_cursor)
+                .map_err(|v| ParserError::add(export_err(v), format!("Export Type: {}", export_name)))?;
             std::mem::swap(&mut export_data, &mut exports[*export_idx as usize]);
 
             export_start += export.serial_size;
@@ -2849,7 +2983,7 @@ impl Package {
         })
     }
 
-    pub fn from_file(file_path: &str, global_map: &LoaderGlobalData) -> ParserResult<Self> {
+    pub fn from_file(file_path: &str, context: &GameContext) -> ParserResult<Self> {
         let asset_file = file_path.to_owned() + ".uasset";
         let ubulk_file = file_path.to_owned() + ".ubulk";
 
@@ -2890,8 +3024,39 @@ impl Package {
 
         // ??
         match ubulk_buf {
-            Some(data) => Self::from_buffer(&uasset_buf, Some(&data), global_map),
-            None => Self::from_buffer(&uasset_buf, None, global_map),
+            Some(data) => Self::from_buffer(&uasset_buf, Some(&data), context),
+            None => Self::from_buffer(&uasset_buf, None, context),
+        }
+    }
+
+    /// Reads a package straight out of an IoStore container, without extracting it to disk first
+    ///
+    /// `container_path` is the path to the `.utoc`/`.ucas` pair without an extension, and `file_path` is the path of the package inside the container, also without an extension.
+    /// The container's key is taken from the context's keychain.
+    pub fn from_container(container_path: &str, file_path: &str, context: &GameContext) -> ParserResult<Self> {
+        let key = context.get_keychain().get_container_key(&(container_path.to_owned() + ".utoc"))?;
+        let dispatch = Extractor::new(container_path, key)?;
+        Self::from_extractor(&dispatch, file_path, context)
+    }
+
+    /// Reads a package from an already opened IoStore container
+    pub fn from_extractor(dispatch: &Extractor, file_path: &str, context: &GameContext) -> ParserResult<Self> {
+        let (uasset_buf, ubulk_buf) = dispatch.get_package_data(file_path)?;
+        match ubulk_buf {
+            Some(data) => Self::from_buffer(&uasset_buf, Some(&data), context),
+            None => Self::from_buffer(&uasset_buf, None, context),
+        }
+    }
+
+    /// The ids of the packages this package imports from
+    pub fn get_imported_packages(&self) -> Vec<u64> {
+        self.graph_data.iter().map(|v| v.index).collect()
+    }
+
+    /// Looks up the names of the packages this package imports from, so that they are written by name rather than id
+    pub fn resolve_package_names(&mut self, resolver: &PackageIdResolver) {
+        for package in &mut self.graph_data {
+            package.name = resolver.resolve(package.index).map(|v| v.to_owned());
         }
     }
 
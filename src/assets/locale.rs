@@ -56,7 +56,11 @@ pub struct FTextLocalizationResource {
 impl FTextLocalizationResource {
     pub fn from_buffer(locres: &[u8]) -> ParserResult<Self> {
         let mut reader = ReaderCursor::new(locres);
-        let magic = FGuid::new(&mut reader)?;
+        Self::from_reader(&mut reader).map_err(|v| v.at_offset(reader.position()))
+    }
+
+    fn from_reader(reader: &mut ReaderCursor) -> ParserResult<Self> {
+        let magic = FGuid::new(reader)?;
 
         if magic != LOCRES_MAGIC {
             return Err(ParserError::new(format!("Wrong magic Guid")));
@@ -72,20 +76,20 @@ impl FTextLocalizationResource {
         // only works for version: optimized
         let current_offset = reader.position();
         reader.seek(SeekFrom::Start(str_array_offset as u64))?;
-        let localized_strings: Vec<FTextLocalizationResourceString> = read_tarray(&mut reader)?;
+        let localized_strings: Vec<FTextLocalizationResourceString> = read_tarray(reader)?;
         reader.seek(SeekFrom::Start(current_offset))?;
 
         let _entry_count = reader.read_u32::<LittleEndian>()?;
         let namespace_count = reader.read_u32::<LittleEndian>()?;
         let mut string_data = Vec::new();
         for _i in 0..namespace_count {
-            let namespace = FTextKey::new(&mut reader)?;
+            let namespace = FTextKey::new(reader)?;
             let key_count = reader.read_u32::<LittleEndian>()?;
 
             let mut strings = Vec::new();
 
             for _j in 0..key_count {
-                let text_key = FTextKey::new(&mut reader)?;
+                let text_key = FTextKey::new(reader)?;
                 let _source_hash = reader.read_u32::<LittleEndian>()?;
                 let string_index = reader.read_i32::<LittleEndian>()?;
                 if string_index >= 0 && string_index < localized_strings.len() as i32 {
@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The default size of a `BlockCache`, in bytes
+pub const DEFAULT_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+static NEXT_CONTAINER_ID: AtomicU64 = AtomicU64::new(0);
+
+type BlockKey = (u64, usize);
+
+struct BlockCacheState {
+    blocks: HashMap<BlockKey, (Arc<Vec<u8>>, u64)>,
+    usage: BTreeMap<u64, BlockKey>,
+    tick: u64,
+    size: usize,
+}
+
+/// BlockCache keeps recently decoded (decrypted and decompressed) container blocks in memory
+///
+/// Blocks are evicted least recently used first, once the total size of the cached blocks goes over the capacity.
+/// A single cache can be shared between any number of readers and containers.
+pub struct BlockCache {
+    capacity: usize,
+    state: Mutex<BlockCacheState>,
+}
+
+impl BlockCache {
+    /// Creates a cache holding up to `capacity` bytes of decoded blocks. A capacity of 0 disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(BlockCacheState {
+                blocks: HashMap::new(),
+                usage: BTreeMap::new(),
+                tick: 0,
+                size: 0,
+            }),
+        }
+    }
+
+    /// Gets a unique id for a container, to keep its blocks apart from other containers sharing the cache
+    pub fn next_container_id() -> u64 {
+        NEXT_CONTAINER_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn get(&self, container: u64, block: usize) -> Option<Arc<Vec<u8>>> {
+        let mut state = self.state.lock().ok()?;
+        state.tick += 1;
+        let tick = state.tick;
+
+        let (data, last_used) = state.blocks.get_mut(&(container, block))?;
+        let data = Arc::clone(data);
+        let previous = std::mem::replace(last_used, tick);
+        state.usage.remove(&previous);
+        state.usage.insert(tick, (container, block));
+
+        Some(data)
+    }
+
+    pub fn insert(&self, container: u64, block: usize, data: Arc<Vec<u8>>) {
+        if data.len() > self.capacity {
+            return;
+        }
+
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        state.tick += 1;
+        let tick = state.tick;
+
+        state.size += data.len();
+        if let Some((previous, last_used)) = state.blocks.insert((container, block), (data, tick)) {
+            state.size -= previous.len();
+            state.usage.remove(&last_used);
+        }
+        state.usage.insert(tick, (container, block));
+
+        while state.size > self.capacity {
+            let oldest = match state.usage.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            if let Some(key) = state.usage.remove(&oldest) {
+                if let Some((evicted, _)) = state.blocks.remove(&key) {
+                    state.size -= evicted.len();
+                }
+            }
+        }
+    }
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,194 @@
+//! The CLI's project configuration, read from a JSON file with command-line overrides
+//!
+//! Settings are taken from `john-wick-parse.json` in the working directory, or the file given with `--config`.
+//! Relative paths in the file are relative to the file itself, so one install can be pointed at any number of projects.
+//! Any setting can then be overridden on the command line, for example `--game-dir D:/Fortnite/Paks`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::CommandError;
+
+const DEFAULT_CONFIG: &str = "john-wick-parse.json";
+
+/// The format each kind of export is written in, when a command isn't told otherwise
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExportFormats {
+    /// `json` or `pretty-json`, for serialized packages and locres files
+    package: String,
+    /// `dot`, `graphml` or `json`, for dependency graphs
+    graph: String,
+}
+
+impl Default for ExportFormats {
+    fn default() -> Self {
+        Self {
+            package: "json".to_owned(),
+            graph: "json".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The folder holding the game's containers, including `global.utoc`
+    game_dir: String,
+    /// Where the AES keys come from: a `keys.json` keychain, a file holding a single key, or a hex key
+    ///
+    /// When unset, `keys.json` and then `key.txt` are read from the working directory.
+    keys: Option<String>,
+    /// The folder holding `.usmap` files and json mappings
+    mappings: String,
+    /// An Oodle library to decompress with, in place of the built in decoder
+    oodle: Option<String>,
+    /// Where output is written. When unset, output is written next to the input.
+    output_dir: Option<String>,
+    formats: ExportFormats,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            game_dir: "paks".to_owned(),
+            keys: None,
+            mappings: "mappings".to_owned(),
+            oodle: None,
+            output_dir: None,
+            formats: ExportFormats::default(),
+        }
+    }
+}
+
+fn config_err<T>(message: String) -> Result<T, CommandError> {
+    Err(CommandError {
+        message,
+    })
+}
+
+/// Makes a path from the config file relative to the folder the file is in
+fn resolve_path(base: &Path, path: &str) -> String {
+    match Path::new(path).is_relative() {
+        true => base.join(path).to_string_lossy().into_owned(),
+        false => path.to_owned(),
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> Result<Self, CommandError> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) => return config_err(format!("Could not read config {}: {}", path, e)),
+        };
+        let mut config: Self = match serde_json::from_str(&data) {
+            Ok(config) => config,
+            Err(e) => return config_err(format!("Could not parse config {}: {}", path, e)),
+        };
+
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        config.game_dir = resolve_path(base, &config.game_dir);
+        config.mappings = resolve_path(base, &config.mappings);
+        // A key source that isn't a file is a hex key, so it's only made relative when the file exists
+        config.keys = config.keys.map(|v| match base.join(&v).exists() {
+            true => resolve_path(base, &v),
+            false => v,
+        });
+        config.oodle = config.oodle.map(|v| resolve_path(base, &v));
+        config.output_dir = config.output_dir.map(|v| resolve_path(base, &v));
+
+        Ok(config)
+    }
+
+    /// Reads the config, and applies any overrides in the arguments
+    ///
+    /// Returns the config and the arguments that are left over, which are the command and its parameters.
+    pub fn from_args(args: &[String]) -> Result<(Self, Vec<String>), CommandError> {
+        let config_path = args.iter().position(|v| v == "--config").map(|i| args.get(i + 1));
+        let mut config = match config_path {
+            Some(Some(path)) => Self::from_file(path)?,
+            Some(None) => return config_err("No value for --config".to_owned()),
+            None if Path::new(DEFAULT_CONFIG).exists() => Self::from_file(DEFAULT_CONFIG)?,
+            None => Self::default(),
+        };
+
+        let mut remaining = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                remaining.push(arg.clone());
+                continue;
+            }
+            let value = match iter.next() {
+                Some(value) => value.clone(),
+                None => return config_err(format!("No value for {}", arg)),
+            };
+            match arg.as_ref() {
+                "--config" => {},
+                "--game-dir" => config.game_dir = value,
+                "--keys" => config.keys = Some(value),
+                "--mappings" => config.mappings = value,
+                "--oodle" => config.oodle = Some(value),
+                "--output" => config.output_dir = Some(value),
+                "--package-format" => config.formats.package = value,
+                "--graph-format" => config.formats.graph = value,
+                _ => return config_err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        Ok((config, remaining))
+    }
+
+    pub fn get_game_dir(&self) -> &str {
+        &self.game_dir
+    }
+
+    /// The path to the global container, without an extension
+    pub fn get_global_path(&self) -> String {
+        Path::new(&self.game_dir).join("global").to_string_lossy().into_owned()
+    }
+
+    pub fn get_keys(&self) -> Option<&str> {
+        self.keys.as_deref()
+    }
+
+    pub fn get_mappings(&self) -> &str {
+        &self.mappings
+    }
+
+    pub fn get_oodle(&self) -> Option<&str> {
+        self.oodle.as_deref()
+    }
+
+    pub fn get_package_format(&self) -> &str {
+        &self.formats.package
+    }
+
+    pub fn get_graph_format(&self) -> &str {
+        &self.formats.graph
+    }
+
+    /// Where to write the output for a file on disk, with `extension` appended to its name
+    pub fn get_output_file(&self, input: &str, extension: &str) -> Result<PathBuf, CommandError> {
+        let output_dir = match &self.output_dir {
+            Some(dir) => dir,
+            None => return Ok(PathBuf::from(input.to_owned() + extension)),
+        };
+        let name = match Path::new(input).file_name().and_then(|v| v.to_str()) {
+            Some(name) => name.to_owned() + extension,
+            None => return config_err(format!("Not a file: {}", input)),
+        };
+        if let Err(e) = fs::create_dir_all(output_dir) {
+            return config_err(format!("Could not create {}: {}", output_dir, e));
+        }
+        Ok(Path::new(output_dir).join(name))
+    }
+
+    /// Where to write a file given by a relative path, such as a file extracted from a container
+    pub fn get_output_path(&self, file: &str) -> PathBuf {
+        match &self.output_dir {
+            Some(dir) => Path::new(dir).join(file.trim_start_matches('/')),
+            None => PathBuf::from(file),
+        }
+    }
+}
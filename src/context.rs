@@ -0,0 +1,70 @@
+//! The per-game state needed to parse packages
+//!
+//! A `GameContext` owns everything that used to be loaded from fixed paths: the global name and script object data,
+//! the property mappings and the AES keys. Several contexts can be used at once, for different games or game versions.
+
+use std::sync::Arc;
+use crate::assets::ParserResult;
+use crate::cache::BlockCache;
+use crate::dispatch::{Extractor, LoaderGlobalData};
+use crate::keys::Keychain;
+use crate::mapping::MappingStore;
+use crate::source::SourceMode;
+
+pub struct GameContext {
+    global_data: LoaderGlobalData,
+    mappings: Arc<MappingStore>,
+    keychain: Keychain,
+}
+
+impl GameContext {
+    pub fn new(global_data: LoaderGlobalData, mappings: MappingStore, keychain: Keychain) -> Self {
+        Self {
+            global_data,
+            mappings: Arc::new(mappings),
+            keychain,
+        }
+    }
+
+    /// A context with no global data, mappings or keys, for packages that don't need them
+    pub fn empty() -> Self {
+        Self::new(LoaderGlobalData::empty(), MappingStore::empty(), Keychain::new())
+    }
+
+    /// Loads a context from a game's files
+    ///
+    /// `global_path` is the path to the `global.utoc`/`global.ucas` pair without an extension, and `mappings_dir` is a folder
+    /// as read by `MappingStore::from_dir`. The global container is opened with its key from the keychain.
+    pub fn load(global_path: &str, mappings_dir: &str, keychain: Keychain) -> ParserResult<Self> {
+        let key = keychain.get_container_key(&(global_path.to_owned() + ".utoc"))?;
+        let dispatch = Extractor::new_with_options(global_path, key, Arc::new(BlockCache::default()), SourceMode::File)?;
+        let global_data = dispatch.read_global()?;
+        let mappings = MappingStore::from_dir(mappings_dir)?;
+
+        Ok(Self::new(global_data, mappings, keychain))
+    }
+
+    pub fn get_global_data(&self) -> &LoaderGlobalData {
+        &self.global_data
+    }
+
+    pub fn set_global_data(&mut self, global_data: LoaderGlobalData) {
+        self.global_data = global_data;
+    }
+
+    pub fn get_mappings(&self) -> Arc<MappingStore> {
+        Arc::clone(&self.mappings)
+    }
+
+    pub fn set_mappings(&mut self, mappings: MappingStore) {
+        self.mappings = Arc::new(mappings);
+    }
+
+    pub fn get_keychain(&self) -> &Keychain {
+        &self.keychain
+    }
+
+    pub fn get_keychain_mut(&mut self) -> &mut Keychain {
+        &mut self.keychain
+    }
+}
@@ -1 +1,108 @@
-pub mod oodle;
\ No newline at end of file
+//! Decompression of container blocks and mapping files, through a registry of codecs keyed by method name
+//!
+//! Zlib, Gzip, LZ4, Brotli and Oodle are registered by default. Method names are matched case-insensitively.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use lazy_static::lazy_static;
+use crate::assets::{ParserResult, ParserError, ParserType};
+
+pub mod oodle;
+
+/// A codec that can decompress a buffer, given the size of its output
+pub trait Decompressor: Send + Sync {
+    fn decompress(&self, input: &[u8], output_size: usize) -> ParserResult<Vec<u8>>;
+}
+
+impl<F> Decompressor for F where F: Fn(&[u8], usize) -> ParserResult<Vec<u8>> + Send + Sync {
+    fn decompress(&self, input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+        self(input, output_size)
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<HashMap<String, Arc<dyn Decompressor>>> = {
+        let mut codecs: HashMap<String, Arc<dyn Decompressor>> = HashMap::new();
+        codecs.insert("zlib".to_owned(), Arc::new(decompress_zlib));
+        codecs.insert("gzip".to_owned(), Arc::new(decompress_gzip));
+        codecs.insert("lz4".to_owned(), Arc::new(decompress_lz4));
+        codecs.insert("brotli".to_owned(), Arc::new(decompress_brotli));
+        codecs.insert("oodle".to_owned(), Arc::new(decompress_oodle));
+        RwLock::new(codecs)
+    };
+}
+
+/// Registers a codec for a compression method, replacing any codec already registered under that name
+pub fn register<D: Decompressor + 'static>(method: &str, codec: D) {
+    REGISTRY.write().unwrap().insert(method.to_lowercase(), Arc::new(codec));
+}
+
+pub fn is_registered(method: &str) -> bool {
+    REGISTRY.read().unwrap().contains_key(&method.to_lowercase())
+}
+
+/// Decompresses `input` with the codec registered for `method`
+pub fn decompress(method: &str, input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+    // Codecs are called outside of the lock, so they can be slow or register codecs themselves
+    let codec = match REGISTRY.read().unwrap().get(&method.to_lowercase()) {
+        Some(codec) => Arc::clone(codec),
+        None => return Err(ParserError::typed(format!("Unsupported compression method: {}", method), ParserType::Decompression)),
+    };
+    codec.decompress(input, output_size)
+}
+
+fn decompress_zlib(input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+    let mut data = vec![0u8; output_size];
+    let mut z = ZlibDecoder::new(input);
+    z.read_exact(&mut data).map_err(|v| ParserError::typed(format!("Zlib error: {}", v), ParserType::Decompression).with_source(v))?;
+    Ok(data)
+}
+
+fn decompress_gzip(input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+    let mut data = vec![0u8; output_size];
+    let mut z = GzDecoder::new(input);
+    z.read_exact(&mut data).map_err(|v| ParserError::typed(format!("Gzip error: {}", v), ParserType::Decompression).with_source(v))?;
+    Ok(data)
+}
+
+/// LZ4 blocks are stored raw, without the LZ4 frame format
+fn decompress_lz4(input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+    let mut data = vec![0u8; output_size];
+    let size = lz4_flex::block::decompress_into(input, &mut data)
+        .map_err(|v| ParserError::typed(format!("LZ4 error: {}", v), ParserType::Decompression).with_source(v))?;
+    if size != output_size {
+        return Err(ParserError::typed(format!("LZ4 error: decompressed {} bytes, expected {}", size, output_size), ParserType::Decompression));
+    }
+    Ok(data)
+}
+
+/// Brotli is used by usmap files rather than containers
+fn decompress_brotli(input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+    let mut data = vec![0u8; output_size];
+    let mut z = brotli_decompressor::Decompressor::new(input, 4096);
+    z.read_exact(&mut data).map_err(|v| ParserError::typed(format!("Brotli error: {}", v), ParserType::Decompression).with_source(v))?;
+    Ok(data)
+}
+
+fn decompress_oodle(input: &[u8], output_size: usize) -> ParserResult<Vec<u8>> {
+    Ok(oodle::decompress_stream(output_size as u64, input)?)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brotli() {
+        // "XXXXXXXXXXYYYYYYYYYY", from the brotli test data
+        let input = [0x1b, 0x13, 0x00, 0x00, 0xa4, 0xb0, 0xb2, 0xea, 0x81, 0x47, 0x02, 0x8a];
+        let expected: Vec<u8> = [b'X'; 10].iter().chain([b'Y'; 10].iter()).cloned().collect();
+        assert_eq!(decompress("Brotli", &input, 20).unwrap(), expected);
+
+        // Asking for more than the stream holds is an error
+        let error = decompress("brotli", &input, 21).unwrap_err();
+        assert_eq!(*error.get_type(), ParserType::Decompression);
+        assert!(decompress("brotli", &input[..8], 20).is_err());
+    }
+}
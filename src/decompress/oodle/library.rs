@@ -0,0 +1,43 @@
+//! The proprietary Oodle library, used instead of the native decoder when it is loaded
+
+use libloading as lib;
+use super::{OodleError, OodleResult};
+
+type DecompressFunc = unsafe extern "C" fn(*const u8, u64, *mut u8, u64, u32, u32, u32, u64, u64, u64, u64, u64, u64, u32) -> i32;
+
+pub(super) struct OodleLibrary {
+    library: lib::Library,
+}
+
+impl OodleLibrary {
+    /// Loads the library at `path`, checking that it exports `OodleLZ_Decompress`
+    pub fn new(path: &str) -> OodleResult<Self> {
+        let library = match lib::Library::new(path) {
+            Ok(data) => data,
+            Err(e) => return Err(OodleError::new(format!("Could not load Oodle library {}: {}", path, e))),
+        };
+        unsafe {
+            if let Err(e) = library.get::<DecompressFunc>(b"OodleLZ_Decompress") {
+                return Err(OodleError::new(format!("Oodle library {} is missing OodleLZ_Decompress: {}", path, e)));
+            }
+        }
+        Ok(Self {
+            library,
+        })
+    }
+
+    pub fn decompress(&self, uncompressed_size: u64, bytes: &[u8]) -> OodleResult<Vec<u8>> {
+        let mut output = vec![0u8; uncompressed_size as usize];
+        let check = unsafe {
+            let func: lib::Symbol<DecompressFunc> = match self.library.get(b"OodleLZ_Decompress") {
+                Ok(data) => data,
+                Err(e) => return Err(OodleError::new(format!("Could not find OodleLZ_Decompress: {}", e))),
+            };
+            func(bytes.as_ptr(), bytes.len() as u64, output.as_mut_ptr(), uncompressed_size, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0)
+        };
+        if check < 0 || check as u64 != uncompressed_size {
+            return Err(OodleError::new(format!("Oodle library decompressed {} bytes, expected {}", check, uncompressed_size)));
+        }
+        Ok(output)
+    }
+}
\ No newline at end of file
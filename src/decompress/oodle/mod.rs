@@ -0,0 +1,83 @@
+//! Oodle decompression, for Kraken, Mermaid, Selkie and Leviathan streams
+//!
+//! Streams are decoded with the Oodle library once it has been loaded with `load_library`. Without it, they are
+//! decoded natively if the crate was built with the `native-oodle` feature. The native decoder is based on ooz, so
+//! it is GPL-3 licensed, and building with it makes the whole binary GPL-3. See `native/mod.rs`.
+
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+
+mod library;
+#[cfg(feature = "native-oodle")]
+mod native;
+
+use library::OodleLibrary;
+#[cfg(feature = "native-oodle")]
+pub use native::decompress_native;
+
+#[derive(Debug)]
+pub struct OodleError {
+    message: String,
+}
+
+impl OodleError {
+    fn new(message: String) -> Self {
+        Self {
+            message,
+        }
+    }
+}
+
+impl std::error::Error for OodleError {
+
+}
+
+impl std::fmt::Display for OodleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub type OodleResult<T> = Result<T, OodleError>;
+
+fn oodle_err<T>(message: &str) -> OodleResult<T> {
+    Err(OodleError::new(message.to_owned()))
+}
+
+lazy_static! {
+    static ref LIBRARY: RwLock<Option<OodleLibrary>> = RwLock::new(None);
+}
+
+/// Uses the Oodle library at `path` for all further decompression
+pub fn load_library(path: &str) -> OodleResult<()> {
+    let library = OodleLibrary::new(path)?;
+    *LIBRARY.write().unwrap() = Some(library);
+    Ok(())
+}
+
+/// Goes back to the native decoder, if there is one
+pub fn unload_library() {
+    *LIBRARY.write().unwrap() = None;
+}
+
+pub fn is_library_loaded() -> bool {
+    LIBRARY.read().unwrap().is_some()
+}
+
+pub fn decompress_stream(uncompressed_size: u64, bytes: &[u8]) -> OodleResult<Vec<u8>> {
+    if let Some(library) = LIBRARY.read().unwrap().as_ref() {
+        return library.decompress(uncompressed_size, bytes);
+    }
+    decompress_unloaded(uncompressed_size, bytes)
+}
+
+#[cfg(feature = "native-oodle")]
+fn decompress_unloaded(uncompressed_size: u64, bytes: &[u8]) -> OodleResult<Vec<u8>> {
+    decompress_native(uncompressed_size, bytes)
+}
+
+#[cfg(not(feature = "native-oodle"))]
+fn decompress_unloaded(_uncompressed_size: u64, _bytes: &[u8]) -> OodleResult<Vec<u8>> {
+    oodle_err("The Oodle library is not loaded, and the native decoder needs the native-oodle feature")
+}
+
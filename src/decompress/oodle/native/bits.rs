@@ -0,0 +1,313 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Bit readers shared by the entropy decoders and the LZ offset/length streams
+
+pub(super) fn shl(v: u32, n: u32) -> u32 {
+    v.checked_shl(n).unwrap_or(0)
+}
+
+pub(super) fn shr(v: u32, n: u32) -> u32 {
+    v.checked_shr(n).unwrap_or(0)
+}
+
+/// Reads a byte, with anything outside of the buffer reading as zero
+pub(super) fn get_u8(data: &[u8], pos: isize) -> u32 {
+    match pos >= 0 {
+        true => data.get(pos as usize).map_or(0, |v| *v as u32),
+        false => 0,
+    }
+}
+
+pub(super) fn get_u16_le(data: &[u8], pos: isize) -> u32 {
+    get_u8(data, pos) | get_u8(data, pos + 1) << 8
+}
+
+pub(super) fn get_u32_le(data: &[u8], pos: isize) -> u32 {
+    get_u16_le(data, pos) | get_u16_le(data, pos + 2) << 16
+}
+
+pub(super) fn get_u32_be(data: &[u8], pos: isize) -> u32 {
+    get_u32_le(data, pos).swap_bytes()
+}
+
+/// An MSB-first bit reader over a buffer, refilled a byte at a time from the front or from the back
+///
+/// `bits` holds up to 31 unread bits at its top. `bitpos` counts down as bytes are loaded, so 24 - `bitpos` bits are held.
+pub(super) struct BitReader<'a> {
+    data: &'a [u8],
+    pub p: isize,
+    pub bits: u32,
+    pub bitpos: i32,
+}
+
+impl<'a> BitReader<'a> {
+    /// Creates a reader starting at `start`, reading towards the end of `data`
+    pub fn new(data: &'a [u8], start: usize) -> Self {
+        let mut reader = Self {
+            data,
+            p: start as isize,
+            bits: 0,
+            bitpos: 24,
+        };
+        reader.refill();
+        reader
+    }
+
+    /// Creates a reader starting at the end of `data`, reading towards the start
+    pub fn new_backwards(data: &'a [u8]) -> Self {
+        let mut reader = Self {
+            data,
+            p: data.len() as isize,
+            bits: 0,
+            bitpos: 24,
+        };
+        reader.refill_backwards();
+        reader
+    }
+
+    pub fn refill(&mut self) {
+        while self.bitpos > 0 {
+            self.bits |= shl(get_u8(self.data, self.p), self.bitpos as u32);
+            self.bitpos -= 8;
+            self.p += 1;
+        }
+    }
+
+    pub fn refill_backwards(&mut self) {
+        while self.bitpos > 0 {
+            self.p -= 1;
+            self.bits |= shl(get_u8(self.data, self.p), self.bitpos as u32);
+            self.bitpos -= 8;
+        }
+    }
+
+    /// The position of the first byte that hasn't been fully read, for a forwards reader
+    pub fn get_position(&self) -> isize {
+        self.p - ((24 - self.bitpos) / 8) as isize
+    }
+
+    pub fn read_bit(&mut self) -> u32 {
+        self.refill();
+        self.read_bit_no_refill()
+    }
+
+    pub fn read_bit_no_refill(&mut self) -> u32 {
+        self.read_bits_no_refill(1)
+    }
+
+    pub fn read_bits_no_refill(&mut self, n: u32) -> u32 {
+        let r = shr(self.bits, 32 - n);
+        self.bits = shl(self.bits, n);
+        self.bitpos += n as i32;
+        r
+    }
+
+    /// As `read_bits_no_refill`, but also well defined for `n` = 0
+    pub fn read_bits_no_refill_zero(&mut self, n: u32) -> u32 {
+        let r = shr(self.bits >> 1, 31 - n);
+        self.bits = shl(self.bits, n);
+        self.bitpos += n as i32;
+        r
+    }
+
+    pub fn read_more_than_24_bits(&mut self, n: u32) -> u32 {
+        let rv = match n <= 24 {
+            true => self.read_bits_no_refill_zero(n),
+            false => {
+                let high = self.read_bits_no_refill(24) << (n - 24);
+                self.refill();
+                high + self.read_bits_no_refill(n - 24)
+            },
+        };
+        self.refill();
+        rv
+    }
+
+    pub fn read_more_than_24_bits_backwards(&mut self, n: u32) -> u32 {
+        let rv = match n <= 24 {
+            true => self.read_bits_no_refill_zero(n),
+            false => {
+                let high = self.read_bits_no_refill(24) << (n - 24);
+                self.refill_backwards();
+                high + self.read_bits_no_refill(n - 24)
+            },
+        };
+        self.refill_backwards();
+        rv
+    }
+
+    /// Reads a length from the length stream, an Elias gamma style prefix then at least 7 bits
+    fn read_length_with(&mut self, refill: fn(&mut Self)) -> Option<u32> {
+        let n = self.bits.leading_zeros();
+        if n > 12 {
+            return None;
+        }
+        self.bitpos += n as i32;
+        self.bits <<= n;
+        refill(self);
+        let n = n + 7;
+        self.bitpos += n as i32;
+        let rv = (self.bits >> (32 - n)).wrapping_sub(64);
+        self.bits <<= n;
+        refill(self);
+        Some(rv)
+    }
+
+    pub fn read_length(&mut self) -> Option<u32> {
+        self.read_length_with(Self::refill)
+    }
+
+    pub fn read_length_backwards(&mut self) -> Option<u32> {
+        self.read_length_with(Self::refill_backwards)
+    }
+
+    /// Reads a match distance given its packed high bits
+    fn read_distance_with(&mut self, v: u32, refill: fn(&mut Self)) -> u32 {
+        let rv = if v < 0xf0 {
+            let n = (v >> 4) + 4;
+            let w = (self.bits | 1).rotate_left(n);
+            self.bitpos += n as i32;
+            let m = (2u32 << n) - 1;
+            self.bits = w & !m;
+            ((w & m) << 4).wrapping_add(v & 0xf).wrapping_sub(248)
+        } else {
+            let n = v - 0xf0 + 4;
+            let w = (self.bits | 1).rotate_left(n);
+            self.bitpos += n as i32;
+            let m = (2u32 << n) - 1;
+            self.bits = w & !m;
+            let rv = 8322816u32.wrapping_add((w & m) << 12);
+            refill(self);
+            let rv = rv.wrapping_add(self.bits >> 20);
+            self.bitpos += 12;
+            self.bits <<= 12;
+            rv
+        };
+        refill(self);
+        rv
+    }
+
+    pub fn read_distance(&mut self, v: u32) -> u32 {
+        self.read_distance_with(v, Self::refill)
+    }
+
+    pub fn read_distance_backwards(&mut self, v: u32) -> u32 {
+        self.read_distance_with(v, Self::refill_backwards)
+    }
+
+    /// Reads the number of extra symbol slots ("fluff") stored alongside a code length table
+    pub fn read_fluff(&mut self, num_symbols: u32) -> u32 {
+        if num_symbols == 256 {
+            return 0;
+        }
+        let x = std::cmp::min(257 - num_symbols, num_symbols) * 2;
+        let y = 32 - (x - 1).leading_zeros();
+        let v = shr(self.bits, 32 - y);
+        let z = (1u32 << y) - x;
+        match (v >> 1) >= z {
+            true => {
+                self.bits = shl(self.bits, y);
+                self.bitpos += y as i32;
+                v - z
+            },
+            false => {
+                self.bits = shl(self.bits, y - 1);
+                self.bitpos += y as i32 - 1;
+                v >> 1
+            },
+        }
+    }
+
+    /// Hands the remaining input over to a `BitReader2`, for the Golomb-Rice coded tables
+    pub fn to_bit_reader2(&self) -> BitReader2<'a> {
+        BitReader2 {
+            data: self.data,
+            p: self.p - ((24 - self.bitpos + 7) >> 3) as isize,
+            bitpos: ((self.bitpos - 24) & 7) as u32,
+        }
+    }
+
+    /// Picks up reading from where a `BitReader2` finished
+    pub fn from_bit_reader2(br2: &BitReader2<'a>) -> Self {
+        let mut reader = Self {
+            data: br2.data,
+            p: br2.p,
+            bits: 0,
+            bitpos: 24,
+        };
+        reader.refill();
+        reader.bits = shl(reader.bits, br2.bitpos);
+        reader.bitpos += br2.bitpos as i32;
+        reader
+    }
+}
+
+/// A plain MSB-first bit position in a buffer, used to read Golomb-Rice codes
+pub(super) struct BitReader2<'a> {
+    data: &'a [u8],
+    pub p: isize,
+    pub bitpos: u32,
+}
+
+impl<'a> BitReader2<'a> {
+    fn get_bit(&self, bit: usize) -> u32 {
+        let byte = get_u8(self.data, self.p + (bit / 8) as isize);
+        (byte >> (7 - (bit % 8))) & 1
+    }
+
+    /// Reads `dst.len()` unary coded values: each is the number of zero bits before a one bit
+    pub fn decode_golomb_rice_lengths(&mut self, dst: &mut [u8]) -> bool {
+        if self.p < 0 || self.p as usize >= self.data.len() {
+            return false;
+        }
+        let total_bits = (self.data.len() - self.p as usize) * 8;
+
+        let mut bit = self.bitpos as usize;
+        for value in dst.iter_mut() {
+            let mut count = 0u32;
+            loop {
+                if bit >= total_bits {
+                    return false;
+                }
+                let set = self.get_bit(bit);
+                bit += 1;
+                if set == 1 {
+                    break;
+                }
+                count += 1;
+            }
+            *value = count as u8;
+        }
+
+        self.p += (bit / 8) as isize;
+        self.bitpos = (bit % 8) as u32;
+        true
+    }
+
+    /// Appends `bitcount` low bits to each value in `dst`
+    pub fn decode_golomb_rice_bits(&mut self, dst: &mut [u8], bitcount: u32) -> bool {
+        if bitcount == 0 {
+            return true;
+        }
+        let bits_required = self.bitpos as usize + bitcount as usize * dst.len();
+        if self.p < 0 || ((bits_required + 7) >> 3) as isize > self.data.len() as isize - self.p {
+            return false;
+        }
+
+        let mut bit = self.bitpos as usize;
+        for value in dst.iter_mut() {
+            let mut extra = 0u32;
+            for _i in 0..bitcount {
+                extra = (extra << 1) | self.get_bit(bit);
+                bit += 1;
+            }
+            *value = ((*value as u32) << bitcount | extra) as u8;
+        }
+
+        self.p += (bits_required >> 3) as isize;
+        self.bitpos = (bits_required & 7) as u32;
+        true
+    }
+}
\ No newline at end of file
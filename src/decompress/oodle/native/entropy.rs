@@ -0,0 +1,355 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Entropy coded byte arrays, shared by all of the LZ decoders
+
+use super::bits::{BitReader, get_u16_le};
+use super::huffman::decode_bytes_huffman;
+use super::tans::decode_bytes_tans;
+use super::{OodleResult, oodle_err};
+
+/// The sizes at the start of an entropy coded array
+struct BlockHeader {
+    chunk_type: u8,
+    header_size: usize,
+    src_size: usize,
+    dst_size: usize,
+}
+
+fn read_header(src: &[u8]) -> OodleResult<BlockHeader> {
+    if src.len() < 2 {
+        return oodle_err("Entropy block is too short");
+    }
+    let chunk_type = (src[0] >> 4) & 7;
+
+    let header = match (chunk_type, src[0] >= 0x80) {
+        (0, true) => {
+            let src_size = (((src[0] as usize) << 8) | src[1] as usize) & 0xfff;
+            BlockHeader { chunk_type, header_size: 2, src_size, dst_size: src_size }
+        },
+        (0, false) => {
+            if src.len() < 3 {
+                return oodle_err("Entropy block is too short");
+            }
+            let src_size = ((src[0] as usize) << 16) | ((src[1] as usize) << 8) | src[2] as usize;
+            if src_size & !0x3ffff != 0 {
+                return oodle_err("Invalid stored block size");
+            }
+            BlockHeader { chunk_type, header_size: 3, src_size, dst_size: src_size }
+        },
+        (_, true) => {
+            if src.len() < 3 {
+                return oodle_err("Entropy block is too short");
+            }
+            let bits = ((src[0] as usize) << 16) | ((src[1] as usize) << 8) | src[2] as usize;
+            let src_size = bits & 0x3ff;
+            let dst_size = src_size + ((bits >> 10) & 0x3ff) + 1;
+            BlockHeader { chunk_type, header_size: 3, src_size, dst_size }
+        },
+        (_, false) => {
+            if src.len() < 5 {
+                return oodle_err("Entropy block is too short");
+            }
+            let bits = u32::from_be_bytes([src[1], src[2], src[3], src[4]]) as usize;
+            let src_size = bits & 0x3ffff;
+            let dst_size = (((bits >> 18) | ((src[0] as usize) << 14)) & 0x3ffff) + 1;
+            if src_size >= dst_size {
+                return oodle_err("Entropy block is larger than its output");
+            }
+            BlockHeader { chunk_type, header_size: 5, src_size, dst_size }
+        },
+    };
+
+    if src.len() - header.header_size < header.src_size {
+        return oodle_err("Entropy block runs past the end of its input");
+    }
+    Ok(header)
+}
+
+/// Gets the decoded size of the entropy coded array at the start of `src`, without decoding it
+pub(super) fn get_block_size(src: &[u8]) -> OodleResult<usize> {
+    Ok(read_header(src)?.dst_size)
+}
+
+/// Decodes the entropy coded array at the start of `src`, returning it and the number of input bytes used
+pub(super) fn decode_bytes(src: &[u8], max_size: usize) -> OodleResult<(Vec<u8>, usize)> {
+    let header = read_header(src)?;
+    if header.dst_size > max_size {
+        return oodle_err("Entropy block is larger than expected");
+    }
+    let data = &src[header.header_size..(header.header_size + header.src_size)];
+    let used = header.header_size + header.src_size;
+
+    if header.chunk_type == 0 {
+        return Ok((data.to_vec(), used));
+    }
+
+    let mut output = vec![0u8; header.dst_size];
+    match header.chunk_type {
+        1 => decode_bytes_tans(data, &mut output)?,
+        2 => decode_bytes_huffman(data, &mut output, false)?,
+        4 => decode_bytes_huffman(data, &mut output, true)?,
+        3 => decode_rle(data, &mut output)?,
+        5 => decode_recursive(data, &mut output)?,
+        _ => return oodle_err("Unknown entropy block type"),
+    }
+    Ok((output, used))
+}
+
+/// Decodes a run length encoded array. Commands are read from the end of the input, and literals from the start.
+fn decode_rle(src: &[u8], output: &mut [u8]) -> OodleResult<()> {
+    if src.len() <= 1 {
+        if src.len() != 1 {
+            return oodle_err("Empty RLE block");
+        }
+        output.iter_mut().for_each(|v| *v = src[0]);
+        return Ok(());
+    }
+
+    // The command buffer can itself be entropy coded, with any remaining bytes following it as they are
+    let commands = match src[0] {
+        0 => src[1..].to_vec(),
+        _ => {
+            let (mut decoded, used) = decode_bytes(src, 0x40000)?;
+            decoded.extend_from_slice(&src[used..]);
+            decoded
+        },
+    };
+
+    let mut cmd_ptr = 0;
+    let mut cmd_end = commands.len();
+    let mut dst = 0;
+    let mut rle_byte = 0u8;
+    let dst_end = output.len();
+
+    while cmd_ptr < cmd_end {
+        let cmd = commands[cmd_end - 1] as u32;
+        let (bytes_to_copy, bytes_to_rle) = if cmd.wrapping_sub(1) >= 0x2f {
+            cmd_end -= 1;
+            ((!cmd & 0xf) as usize, (cmd >> 4) as usize)
+        } else if cmd >= 0x10 {
+            if cmd_end < cmd_ptr + 2 {
+                return oodle_err("RLE commands overlap");
+            }
+            let data = get_u16_le(&commands, cmd_end as isize - 2).wrapping_sub(4096);
+            cmd_end -= 2;
+            ((data & 0x3f) as usize, (data >> 6) as usize)
+        } else if cmd == 1 {
+            rle_byte = commands[cmd_ptr];
+            cmd_ptr += 1;
+            cmd_end -= 1;
+            (0, 0)
+        } else if cmd >= 9 {
+            if cmd_end < cmd_ptr + 2 {
+                return oodle_err("RLE commands overlap");
+            }
+            let data = get_u16_le(&commands, cmd_end as isize - 2).wrapping_sub(0x8ff);
+            cmd_end -= 2;
+            (0, data as usize * 128)
+        } else {
+            if cmd_end < cmd_ptr + 2 {
+                return oodle_err("RLE commands overlap");
+            }
+            let data = get_u16_le(&commands, cmd_end as isize - 2).wrapping_sub(511);
+            cmd_end -= 2;
+            (data as usize * 64, 0)
+        };
+
+        if dst_end - dst < bytes_to_copy + bytes_to_rle || cmd_end < cmd_ptr + bytes_to_copy {
+            return oodle_err("RLE run overflows its output");
+        }
+        output[dst..(dst + bytes_to_copy)].copy_from_slice(&commands[cmd_ptr..(cmd_ptr + bytes_to_copy)]);
+        cmd_ptr += bytes_to_copy;
+        dst += bytes_to_copy;
+        output[dst..(dst + bytes_to_rle)].iter_mut().for_each(|v| *v = rle_byte);
+        dst += bytes_to_rle;
+    }
+
+    if cmd_ptr != cmd_end || dst != dst_end {
+        return oodle_err("RLE block did not fill its output");
+    }
+    Ok(())
+}
+
+/// Decodes an array split into several entropy coded parts
+fn decode_recursive(src: &[u8], output: &mut [u8]) -> OodleResult<()> {
+    if src.len() < 6 {
+        return oodle_err("Recursive block is too short");
+    }
+    let n = src[0] & 0x7f;
+    if n < 2 {
+        return oodle_err("Recursive block has too few parts");
+    }
+
+    if src[0] & 0x80 == 0 {
+        let mut pos = 1;
+        let mut dst = 0;
+        for _i in 0..n {
+            let (part, used) = decode_bytes(&src[pos..], output.len() - dst)?;
+            output[dst..(dst + part.len())].copy_from_slice(&part);
+            dst += part.len();
+            pos += used;
+        }
+        if dst != output.len() || pos != src.len() {
+            return oodle_err("Recursive block did not fill its output");
+        }
+    } else {
+        let (arrays, used) = decode_multi_array(src, output.len(), 1)?;
+        if arrays[0].len() != output.len() || used != src.len() {
+            return oodle_err("Recursive block did not fill its output");
+        }
+        output.copy_from_slice(&arrays[0]);
+    }
+    Ok(())
+}
+
+/// Decodes `array_count` arrays that are built from runs taken out of a shared set of entropy coded arrays
+pub(super) fn decode_multi_array(src: &[u8], max_size: usize, array_count: usize) -> OodleResult<(Vec<Vec<u8>>, usize)> {
+    if src.len() < 4 {
+        return oodle_err("Multi-array block is too short");
+    }
+    if src[0] & 0x80 == 0 {
+        return oodle_err("Invalid multi-array header");
+    }
+    let num_arrays_in_file = (src[0] & 0x3f) as usize;
+    let mut pos = 1;
+
+    // Without any shared arrays, each output array is stored on its own
+    if num_arrays_in_file == 0 {
+        let mut arrays = Vec::with_capacity(array_count);
+        let mut total_size = 0;
+        for _i in 0..array_count {
+            let (array, used) = decode_bytes(&src[pos..], max_size - total_size)?;
+            total_size += array.len();
+            pos += used;
+            arrays.push(array);
+        }
+        return Ok((arrays, pos));
+    }
+
+    let mut entropy_arrays = Vec::with_capacity(num_arrays_in_file);
+    let mut entropy_total = 0;
+    for _i in 0..num_arrays_in_file {
+        let (array, used) = decode_bytes(&src[pos..], max_size - entropy_total)?;
+        entropy_total += array.len();
+        pos += used;
+        entropy_arrays.push(array);
+    }
+
+    if src.len() - pos < 2 {
+        return oodle_err("Multi-array block is too short");
+    }
+    let q = get_u16_le(src, pos as isize);
+    pos += 2;
+
+    let num_indexes = get_block_size(&src[pos..])?;
+    if num_indexes <= array_count {
+        return oodle_err("Multi-array block has no intervals");
+    }
+    let num_lens;
+    let interval_indexes;
+    let interval_lenlog2;
+    if q & 0x8000 != 0 {
+        // Source arrays and length sizes are packed into one byte per interval
+        let (packed, used) = decode_bytes(&src[pos..], num_indexes)?;
+        if packed.len() != num_indexes {
+            return oodle_err("Wrong number of multi-array intervals");
+        }
+        pos += used;
+        interval_indexes = packed.iter().map(|v| v & 0xf).collect::<Vec<u8>>();
+        interval_lenlog2 = packed.iter().map(|v| v >> 4).collect::<Vec<u8>>();
+        num_lens = num_indexes;
+    } else {
+        num_lens = num_indexes - array_count;
+        let (indexes, used) = decode_bytes(&src[pos..], num_indexes)?;
+        if indexes.len() != num_indexes {
+            return oodle_err("Wrong number of multi-array intervals");
+        }
+        pos += used;
+        let (lenlog2, used) = decode_bytes(&src[pos..], num_lens)?;
+        if lenlog2.len() != num_lens {
+            return oodle_err("Wrong number of multi-array interval lengths");
+        }
+        pos += used;
+        if lenlog2.iter().any(|v| *v > 16) {
+            return oodle_err("Invalid multi-array interval length");
+        }
+        interval_indexes = indexes;
+        interval_lenlog2 = lenlog2;
+    }
+
+    // Interval lengths are read alternately from the front and the back of their own bit stream
+    let varbits_complen = (q & 0x3fff) as usize;
+    if src.len() - pos < varbits_complen {
+        return oodle_err("Multi-array block is too short");
+    }
+    let varbits = &src[pos..(pos + varbits_complen)];
+    let mut forward = BitReader::new(varbits, 0);
+    let mut backward = BitReader::new_backwards(varbits);
+    let mut bits_used = 0;
+    let mut decoded_intervals = Vec::with_capacity(num_lens);
+    for (i, n) in interval_lenlog2.iter().enumerate() {
+        let n = *n as u32;
+        let reader = match i & 1 {
+            0 => &mut forward,
+            _ => &mut backward,
+        };
+        let v = (1u32 << n) | reader.read_bits_no_refill_zero(n);
+        match i & 1 {
+            0 => reader.refill(),
+            _ => reader.refill_backwards(),
+        }
+        bits_used += n as usize;
+        decoded_intervals.push(v as usize);
+    }
+    if bits_used > varbits_complen * 8 {
+        return oodle_err("Multi-array interval lengths run past their stream");
+    }
+    pos += varbits_complen;
+
+    let mut entropy_pos = vec![0usize; num_arrays_in_file];
+    let mut arrays = Vec::with_capacity(array_count);
+    let mut total_size = 0;
+    let mut indi = 0;
+    let mut leni = 0;
+    for _arr in 0..array_count {
+        let mut array = Vec::new();
+        loop {
+            let source = match interval_indexes.get(indi) {
+                Some(v) => *v as usize,
+                None => return oodle_err("Multi-array intervals ran out"),
+            };
+            indi += 1;
+            if source == 0 {
+                break;
+            }
+            if source > num_arrays_in_file || leni >= num_lens {
+                return oodle_err("Invalid multi-array interval");
+            }
+            let cur_len = decoded_intervals[leni];
+            leni += 1;
+            let entropy_array = &entropy_arrays[source - 1];
+            let start = entropy_pos[source - 1];
+            if cur_len > entropy_array.len() - start || cur_len > max_size - total_size - array.len() {
+                return oodle_err("Multi-array interval is too long");
+            }
+            array.extend_from_slice(&entropy_array[start..(start + cur_len)]);
+            entropy_pos[source - 1] += cur_len;
+        }
+        if q & 0x8000 != 0 {
+            leni += 1;
+        }
+        total_size += array.len();
+        arrays.push(array);
+    }
+
+    if indi != num_indexes || leni != num_lens {
+        return oodle_err("Multi-array intervals were not all used");
+    }
+    if entropy_arrays.iter().zip(entropy_pos.iter()).any(|(array, used)| array.len() != *used) {
+        return oodle_err("Multi-array source data was not all used");
+    }
+
+    Ok((arrays, pos))
+}
\ No newline at end of file
@@ -0,0 +1,394 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Huffman coded byte arrays, in the two-way and four-way split forms
+
+use super::bits::{BitReader, get_u8, get_u16_le, get_u32_le};
+use super::{OodleResult, oodle_err};
+
+/// Where each code length starts in the sorted symbol list: up to 2 symbols of length 1, 4 of length 2, and so on
+const CODE_PREFIX_ORG: [u32; 12] = [0x0, 0x0, 0x2, 0x6, 0xe, 0x1e, 0x3e, 0x7e, 0xfe, 0x1fe, 0x2fe, 0x3fe];
+
+/// A run of consecutive symbols in a code length table
+pub(super) struct HuffRange {
+    pub symbol: u32,
+    pub num: u32,
+}
+
+/// A lookup from the next 11 input bits (LSB-first) to a code's length and symbol
+struct HuffRevLut {
+    bits2len: [u8; 2048],
+    bits2sym: [u8; 2048],
+}
+
+/// Reads the code lengths, and returns the symbols sorted by code length in `syms`, with `code_prefix` advanced past each length's symbols
+fn read_code_lengths_old(bits: &mut BitReader, syms: &mut [u8; 1280], code_prefix: &mut [u32; 12]) -> OodleResult<u32> {
+    if bits.read_bit_no_refill() != 0 {
+        let mut sym = 0u32;
+        let mut num_symbols = 0u32;
+        let mut avg_bits_x4 = 32i32;
+        let forced_bits = bits.read_bits_no_refill(2);
+
+        let thres_for_valid_gamma_bits = 1u32 << (31 - (20 >> forced_bits));
+        let mut skip_initial_zeros = bits.read_bit() != 0;
+        loop {
+            if !skip_initial_zeros {
+                // Run of zeros
+                if bits.bits & 0xff000000 == 0 {
+                    return oodle_err("Invalid Huffman code lengths");
+                }
+                sym += bits.read_bits_no_refill(2 * (bits.bits.leading_zeros() + 1)) - 2 + 1;
+                if sym >= 256 {
+                    break;
+                }
+            }
+            skip_initial_zeros = false;
+            bits.refill();
+
+            // Gamma coded number of symbols
+            if bits.bits & 0xff000000 == 0 {
+                return oodle_err("Invalid Huffman code lengths");
+            }
+            let mut n = bits.read_bits_no_refill(2 * (bits.bits.leading_zeros() + 1)) - 2 + 1;
+            if sym + n > 256 {
+                return oodle_err("Invalid Huffman code lengths");
+            }
+            bits.refill();
+            num_symbols += n;
+            loop {
+                if bits.bits < thres_for_valid_gamma_bits {
+                    return oodle_err("Invalid Huffman code lengths");
+                }
+
+                let lz = bits.bits.leading_zeros();
+                let v = bits.read_bits_no_refill(lz + forced_bits + 1) as i32 + ((lz as i32 - 1) << forced_bits);
+                let codelen = (-(v & 1) ^ (v >> 1)) + ((avg_bits_x4 + 2) >> 2);
+                if !(1..=11).contains(&codelen) {
+                    return oodle_err("Invalid Huffman code length");
+                }
+                avg_bits_x4 = codelen + ((3 * avg_bits_x4 + 2) >> 2);
+                bits.refill();
+                syms[code_prefix[codelen as usize] as usize] = sym as u8;
+                code_prefix[codelen as usize] += 1;
+                sym += 1;
+                n -= 1;
+                if n == 0 {
+                    break;
+                }
+            }
+            if sym == 256 {
+                break;
+            }
+        }
+        if sym != 256 || num_symbols < 2 {
+            return oodle_err("Invalid Huffman code lengths");
+        }
+        Ok(num_symbols)
+    } else {
+        // Sparse symbol list
+        let num_symbols = bits.read_bits_no_refill(8);
+        if num_symbols == 0 {
+            return oodle_err("Invalid Huffman code lengths");
+        }
+        if num_symbols == 1 {
+            syms[0] = bits.read_bits_no_refill(8) as u8;
+        } else {
+            let codelen_bits = bits.read_bits_no_refill(3);
+            if codelen_bits > 4 {
+                return oodle_err("Invalid Huffman code lengths");
+            }
+            for _i in 0..num_symbols {
+                bits.refill();
+                let sym = bits.read_bits_no_refill(8);
+                let codelen = bits.read_bits_no_refill_zero(codelen_bits) + 1;
+                if codelen > 11 {
+                    return oodle_err("Invalid Huffman code length");
+                }
+                syms[code_prefix[codelen as usize] as usize] = sym as u8;
+                code_prefix[codelen as usize] += 1;
+            }
+        }
+        Ok(num_symbols)
+    }
+}
+
+/// Reads the ranges of symbols that are present, with the gaps between them. `symlen` holds the Golomb-Rice coded sizes of each.
+pub(super) fn convert_to_ranges(num_symbols: u32, fluff: u32, symlen: &[u8], bits: &mut BitReader) -> OodleResult<Vec<HuffRange>> {
+    let num_ranges = fluff >> 1;
+    let mut sym_idx = 0u32;
+    let mut symlen_idx = 0;
+    let mut range = Vec::with_capacity(num_ranges as usize + 1);
+
+    // Starts with a gap
+    if fluff & 1 != 0 {
+        bits.refill();
+        let v = symlen[symlen_idx] as u32;
+        symlen_idx += 1;
+        if v >= 8 {
+            return oodle_err("Invalid symbol range");
+        }
+        sym_idx = bits.read_bits_no_refill(v + 1) + (1 << (v + 1)) - 1;
+    }
+
+    let mut syms_used = 0u32;
+    for _i in 0..num_ranges {
+        bits.refill();
+        let v = symlen[symlen_idx] as u32;
+        if v >= 9 {
+            return oodle_err("Invalid symbol range");
+        }
+        let num = bits.read_bits_no_refill_zero(v) + (1 << v);
+        let v = symlen[symlen_idx + 1] as u32;
+        if v >= 8 {
+            return oodle_err("Invalid symbol range");
+        }
+        let space = bits.read_bits_no_refill(v + 1) + (1 << (v + 1)) - 1;
+        range.push(HuffRange {
+            symbol: sym_idx,
+            num,
+        });
+        syms_used += num;
+        sym_idx += num + space;
+        symlen_idx += 2;
+    }
+
+    if sym_idx >= 256 || syms_used >= num_symbols || sym_idx + num_symbols - syms_used > 256 {
+        return oodle_err("Invalid symbol range");
+    }
+
+    range.push(HuffRange {
+        symbol: sym_idx,
+        num: num_symbols - syms_used,
+    });
+
+    Ok(range)
+}
+
+fn read_code_lengths_new(bits: &mut BitReader, syms: &mut [u8; 1280], code_prefix: &mut [u32; 12]) -> OodleResult<u32> {
+    let forced_bits = bits.read_bits_no_refill(2);
+    let num_symbols = bits.read_bits_no_refill(8) + 1;
+    let fluff = bits.read_fluff(num_symbols);
+
+    let mut code_len = vec![0u8; (num_symbols + fluff) as usize + 16];
+    let mut br2 = bits.to_bit_reader2();
+    if !br2.decode_golomb_rice_lengths(&mut code_len[..(num_symbols + fluff) as usize]) {
+        return oodle_err("Invalid Huffman code lengths");
+    }
+    if !br2.decode_golomb_rice_bits(&mut code_len[..num_symbols as usize], forced_bits) {
+        return oodle_err("Invalid Huffman code lengths");
+    }
+    *bits = BitReader::from_bit_reader2(&br2);
+
+    // Code lengths are stored as the zigzag coded difference from a running average
+    let mut running_sum = 0x1ei32;
+    for len in code_len.iter_mut().take(num_symbols as usize) {
+        let v = *len as i32;
+        let v = -(v & 1) ^ (v >> 1);
+        let codelen = v + (running_sum >> 2) + 1;
+        if !(1..=11).contains(&codelen) {
+            return oodle_err("Invalid Huffman code length");
+        }
+        *len = codelen as u8;
+        running_sum += v;
+    }
+
+    let ranges = convert_to_ranges(num_symbols, fluff, &code_len[(num_symbols as usize)..], bits)?;
+
+    let mut cp = 0;
+    for range in ranges {
+        for sym in range.symbol..(range.symbol + range.num) {
+            let len = code_len[cp] as usize;
+            cp += 1;
+            match syms.get_mut(code_prefix[len] as usize) {
+                Some(v) => *v = sym as u8,
+                None => return oodle_err("Invalid Huffman code lengths"),
+            }
+            code_prefix[len] += 1;
+        }
+    }
+
+    Ok(num_symbols)
+}
+
+/// Builds the decoding table from the sorted symbols, failing if the code lengths don't form a complete code
+fn make_lut(code_prefix: &[u32; 12], syms: &[u8; 1280]) -> OodleResult<HuffRevLut> {
+    let mut bits2len = [0u8; 2048];
+    let mut bits2sym = [0u8; 2048];
+    let mut currslot = 0usize;
+    for i in 1..12 {
+        let start = CODE_PREFIX_ORG[i] as usize;
+        let count = (code_prefix[i] as usize).saturating_sub(start);
+        let stepsize = 1usize << (11 - i);
+        let num_to_set = count << (11 - i);
+        if currslot + num_to_set > 2048 {
+            return oodle_err("Invalid Huffman code");
+        }
+        bits2len[currslot..(currslot + num_to_set)].fill(i as u8);
+        for j in 0..count {
+            let slot = currslot + j * stepsize;
+            bits2sym[slot..(slot + stepsize)].fill(syms[start + j]);
+        }
+        currslot += num_to_set;
+    }
+    if currslot != 2048 {
+        return oodle_err("Incomplete Huffman code");
+    }
+
+    // The table above is indexed MSB-first, but the streams are read LSB-first
+    let mut lut = HuffRevLut {
+        bits2len: [0u8; 2048],
+        bits2sym: [0u8; 2048],
+    };
+    for i in 0..2048 {
+        let reversed = ((i as u32).reverse_bits() >> 21) as usize;
+        lut.bits2len[i] = bits2len[reversed];
+        lut.bits2sym[i] = bits2sym[reversed];
+    }
+    Ok(lut)
+}
+
+/// A LSB-first bit stream, read forwards or backwards from `pos`
+struct HuffStream {
+    pos: isize,
+    bits: u32,
+    bitpos: i32,
+}
+
+impl HuffStream {
+    fn new(pos: usize) -> Self {
+        Self {
+            pos: pos as isize,
+            bits: 0,
+            bitpos: 0,
+        }
+    }
+
+    fn refill(&mut self, src: &[u8]) {
+        while self.bitpos <= 24 {
+            self.bits |= get_u8(src, self.pos) << self.bitpos;
+            self.pos += 1;
+            self.bitpos += 8;
+        }
+    }
+
+    fn refill_backwards(&mut self, src: &[u8]) {
+        while self.bitpos <= 24 {
+            self.pos -= 1;
+            self.bits |= get_u8(src, self.pos) << self.bitpos;
+            self.bitpos += 8;
+        }
+    }
+
+    fn decode(&mut self, lut: &HuffRevLut) -> u8 {
+        let k = (self.bits & 0x7ff) as usize;
+        let n = lut.bits2len[k];
+        self.bits >>= n;
+        self.bitpos -= n as i32;
+        lut.bits2sym[k]
+    }
+}
+
+/// Decodes three interleaved streams: forwards from `start`, backwards from `end`, and forwards from `mid`
+///
+/// The first stream must end exactly at `mid`, and the other two must meet.
+fn decode_bytes_core(src: &[u8], start: usize, mid: usize, end: usize, output: &mut [u8], lut: &HuffRevLut) -> OodleResult<()> {
+    if start > mid || mid > end {
+        return oodle_err("Invalid Huffman stream split");
+    }
+
+    let mut a = HuffStream::new(start);
+    let mut b = HuffStream::new(end);
+    let mut c = HuffStream::new(mid);
+
+    // Streams read a few bytes ahead of what they decode, which is accounted for at the end
+    let mut chunks = output.chunks_mut(3);
+    for chunk in &mut chunks {
+        a.refill(src);
+        chunk[0] = a.decode(lut);
+        if chunk.len() > 1 {
+            b.refill_backwards(src);
+            chunk[1] = b.decode(lut);
+        }
+        if chunk.len() > 2 {
+            c.refill(src);
+            chunk[2] = c.decode(lut);
+        }
+    }
+
+    if a.bitpos < 0 || b.bitpos < 0 || c.bitpos < 0 {
+        return oodle_err("Huffman stream ran out of bits");
+    }
+    a.pos -= (a.bitpos >> 3) as isize;
+    b.pos += (b.bitpos >> 3) as isize;
+    c.pos -= (c.bitpos >> 3) as isize;
+    if a.pos != mid as isize || b.pos != c.pos {
+        return oodle_err("Huffman streams did not end where expected");
+    }
+    Ok(())
+}
+
+/// Decodes a Huffman coded array, split into 3 streams (`way4` = false) or 6 streams (`way4` = true)
+pub(super) fn decode_bytes_huffman(src: &[u8], output: &mut [u8], way4: bool) -> OodleResult<()> {
+    let mut br = BitReader::new(src, 0);
+
+    let mut code_prefix = CODE_PREFIX_ORG;
+    let mut syms = [0u8; 1280];
+    let num_syms = if br.read_bit_no_refill() == 0 {
+        read_code_lengths_old(&mut br, &mut syms, &mut code_prefix)?
+    } else if br.read_bit_no_refill() == 0 {
+        read_code_lengths_new(&mut br, &mut syms, &mut code_prefix)?
+    } else {
+        return oodle_err("Unknown Huffman table format");
+    };
+    if num_syms < 1 {
+        return oodle_err("Empty Huffman table");
+    }
+    let pos = br.get_position();
+    if pos < 0 || pos as usize > src.len() {
+        return oodle_err("Huffman table runs past the end of its block");
+    }
+    let pos = pos as usize;
+
+    if num_syms == 1 {
+        output.fill(syms[0]);
+        return Ok(());
+    }
+
+    let lut = make_lut(&code_prefix, &syms)?;
+
+    if !way4 {
+        if pos + 3 > src.len() {
+            return oodle_err("Huffman block is too short");
+        }
+        let split_mid = get_u16_le(src, pos as isize) as usize;
+        let start = pos + 2;
+        decode_bytes_core(src, start, start + split_mid, src.len(), output, &lut)?;
+    } else {
+        if pos + 6 > src.len() {
+            return oodle_err("Huffman block is too short");
+        }
+        let half_output_size = (output.len() + 1) >> 1;
+        let split_mid = (get_u32_le(src, pos as isize) & 0xffffff) as usize;
+        let start = pos + 3;
+        if split_mid > src.len() - start {
+            return oodle_err("Invalid Huffman stream split");
+        }
+        let src_mid = start + split_mid;
+        let split_left = get_u16_le(src, start as isize) as usize;
+        let start = start + 2;
+        if src_mid < start || src_mid - start < split_left + 2 || src.len() - src_mid < 3 {
+            return oodle_err("Invalid Huffman stream split");
+        }
+        let split_right = get_u16_le(src, src_mid as isize) as usize;
+        if src.len() - (src_mid + 2) < split_right + 2 {
+            return oodle_err("Invalid Huffman stream split");
+        }
+
+        let (left, right) = output.split_at_mut(half_output_size);
+        decode_bytes_core(&src[..src_mid], start, start + split_left, src_mid, left, &lut)?;
+        decode_bytes_core(src, src_mid + 2, src_mid + 2 + split_right, src.len(), right, &lut)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,252 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Kraken, the LZ decoder with the best balance of ratio and speed
+
+use super::bits::BitReader;
+use super::entropy::decode_bytes;
+use super::{OodleResult, oodle_err, copy_literals, copy_match};
+
+/// The streams that drive the LZ runs of a chunk
+struct LzTable {
+    cmd_stream: Vec<u8>,
+    lit_stream: Vec<u8>,
+    offs_stream: Vec<i32>,
+    len_stream: Vec<u32>,
+}
+
+/// Expands packed offsets and lengths using the extra bits stored at the end of a chunk
+///
+/// The extra bits are read from two streams, one from each end of `src`, which must meet in the middle.
+pub(super) fn unpack_offsets(src: &[u8], packed_offs: &[u8], packed_offs_extra: &[u8], scale: u32, packed_lens: &[u8]) -> OodleResult<(Vec<i32>, Vec<u32>)> {
+    let mut a = BitReader::new(src, 0);
+    let mut b = BitReader::new_backwards(src);
+
+    // The number of lengths that didn't fit in a byte
+    if b.bits < 0x2000 {
+        return oodle_err("Invalid long length count");
+    }
+    let n = b.bits.leading_zeros();
+    b.bitpos += n as i32;
+    b.bits <<= n;
+    b.refill_backwards();
+    let n = n + 1;
+    let u32_len_count = (b.bits >> (32 - n)) - 1;
+    b.bitpos += n as i32;
+    b.bits <<= n;
+    b.refill_backwards();
+
+    let mut offs_stream = Vec::with_capacity(packed_offs.len());
+    for (i, cmd) in packed_offs.iter().enumerate() {
+        let reader = match i & 1 {
+            0 => &mut a,
+            _ => &mut b,
+        };
+        let cmd = *cmd as u32;
+        let offs = match scale {
+            0 => match i & 1 {
+                0 => -(reader.read_distance(cmd) as i32),
+                _ => -(reader.read_distance_backwards(cmd) as i32),
+            },
+            _ => {
+                if (cmd >> 3) > 26 {
+                    return oodle_err("Invalid offset");
+                }
+                let extra = match i & 1 {
+                    0 => reader.read_more_than_24_bits(cmd >> 3),
+                    _ => reader.read_more_than_24_bits_backwards(cmd >> 3),
+                };
+                8 - (((8 + (cmd & 7)) << (cmd >> 3)) | extra) as i32
+            },
+        };
+        offs_stream.push(offs);
+    }
+
+    // Scaled offsets have their low bits stored separately
+    if scale > 1 {
+        if packed_offs_extra.len() != offs_stream.len() {
+            return oodle_err("Offset arrays have different lengths");
+        }
+        for (offs, low_bits) in offs_stream.iter_mut().zip(packed_offs_extra.iter()) {
+            *offs = (scale as i32).wrapping_mul(*offs).wrapping_sub(*low_bits as i32);
+        }
+    }
+
+    if u32_len_count > 512 {
+        return oodle_err("Too many long lengths");
+    }
+    let mut u32_lens = Vec::with_capacity(u32_len_count as usize);
+    for i in 0..u32_len_count {
+        let len = match i & 1 {
+            0 => a.read_length(),
+            _ => b.read_length_backwards(),
+        };
+        match len {
+            Some(len) => u32_lens.push(len),
+            None => return oodle_err("Invalid long length"),
+        }
+    }
+
+    let a_end = a.p - ((24 - a.bitpos) >> 3) as isize;
+    let b_end = b.p + ((24 - b.bitpos) >> 3) as isize;
+    if a_end != b_end {
+        return oodle_err("Offset and length bits did not end where expected");
+    }
+
+    let mut u32_lens = u32_lens.into_iter();
+    let mut len_stream = Vec::with_capacity(packed_lens.len());
+    for v in packed_lens {
+        let v = match *v {
+            255 => match u32_lens.next() {
+                Some(len) => len.wrapping_add(255),
+                None => return oodle_err("Long lengths ran out"),
+            },
+            v => v as u32,
+        };
+        len_stream.push(v.wrapping_add(3));
+    }
+    if u32_lens.next().is_some() {
+        return oodle_err("Long lengths were not all used");
+    }
+
+    Ok((offs_stream, len_stream))
+}
+
+fn read_lz_table(mode: u32, src: &[u8], output: &mut [u8], start: usize, size: usize) -> OodleResult<LzTable> {
+    if mode > 1 {
+        return oodle_err("Invalid Kraken chunk mode");
+    }
+    if src.len() < 13 || size < 8 {
+        return oodle_err("Kraken chunk is too short");
+    }
+
+    let mut pos = 0;
+    if start == 0 {
+        output[..8].copy_from_slice(&src[..8]);
+        pos += 8;
+    }
+    if src[pos] & 0x80 != 0 {
+        return oodle_err("Kraken chunk uses excess bytes, which are not supported");
+    }
+
+    let (lit_stream, used) = decode_bytes(&src[pos..], size)?;
+    pos += used;
+    let (cmd_stream, used) = decode_bytes(&src[pos..], size)?;
+    pos += used;
+
+    if src.len() - pos < 3 {
+        return oodle_err("Kraken chunk is too short");
+    }
+    let mut offs_scaling = 0;
+    let mut packed_offs_extra = Vec::new();
+    let packed_offs = if src[pos] & 0x80 != 0 {
+        offs_scaling = src[pos] as u32 - 127;
+        pos += 1;
+        let (packed_offs, used) = decode_bytes(&src[pos..], cmd_stream.len())?;
+        pos += used;
+        if offs_scaling != 1 {
+            let (extra, used) = decode_bytes(&src[pos..], packed_offs.len())?;
+            if extra.len() != packed_offs.len() {
+                return oodle_err("Offset arrays have different lengths");
+            }
+            pos += used;
+            packed_offs_extra = extra;
+        }
+        packed_offs
+    } else {
+        let (packed_offs, used) = decode_bytes(&src[pos..], cmd_stream.len())?;
+        pos += used;
+        packed_offs
+    };
+
+    let (packed_lens, used) = decode_bytes(&src[pos..], size >> 2)?;
+    pos += used;
+
+    let (offs_stream, len_stream) = unpack_offsets(&src[pos..], &packed_offs, &packed_offs_extra, offs_scaling, &packed_lens)?;
+
+    Ok(LzTable {
+        cmd_stream,
+        lit_stream,
+        offs_stream,
+        len_stream,
+    })
+}
+
+fn process_lz_runs(mode: u32, table: &LzTable, output: &mut [u8], start: usize, end: usize) -> OodleResult<()> {
+    let output = &mut output[..end];
+    let mut dst = match start {
+        0 => 8,
+        _ => start,
+    };
+
+    let mut lit_pos = 0;
+    let mut lens = table.len_stream.iter();
+    let mut offs_pos = 0;
+    let mut recent_offs = [0i32; 7];
+    recent_offs[3..6].copy_from_slice(&[-8, -8, -8]);
+    let mut last_offset = -8isize;
+
+    for f in &table.cmd_stream {
+        let f = *f as usize;
+        let litlen = match f & 3 {
+            3 => match lens.next() {
+                Some(len) => *len as usize,
+                None => return oodle_err("Kraken lengths ran out"),
+            },
+            v => v,
+        };
+        let offs_index = f >> 6;
+        let matchlen = (f >> 2) & 0xf;
+
+        if litlen > table.lit_stream.len() - lit_pos {
+            return oodle_err("Kraken literals ran out");
+        }
+        let lit_offset = match mode {
+            0 => Some(last_offset),
+            _ => None,
+        };
+        copy_literals(output, dst, &table.lit_stream[lit_pos..(lit_pos + litlen)], lit_offset)?;
+        dst += litlen;
+        lit_pos += litlen;
+
+        // Either one of the three most recent offsets, or the next new one
+        recent_offs[6] = table.offs_stream.get(offs_pos).copied().unwrap_or(0);
+        recent_offs[3..=(offs_index + 3)].rotate_right(1);
+        let offset = recent_offs[3] as isize;
+        last_offset = offset;
+        if offs_index == 3 {
+            offs_pos += 1;
+        }
+
+        let matchlen = match matchlen {
+            15 => match lens.next() {
+                Some(len) => 14 + *len as usize,
+                None => return oodle_err("Kraken lengths ran out"),
+            },
+            v => v + 2,
+        };
+        copy_match(output, dst, offset, matchlen)?;
+        dst += matchlen;
+    }
+
+    if offs_pos != table.offs_stream.len() || lens.next().is_some() {
+        return oodle_err("Kraken offsets or lengths were not all used");
+    }
+
+    let final_len = end - dst;
+    if final_len != table.lit_stream.len() - lit_pos {
+        return oodle_err("Kraken literals do not fill the chunk");
+    }
+    let lit_offset = match mode {
+        0 => Some(last_offset),
+        _ => None,
+    };
+    copy_literals(output, dst, &table.lit_stream[lit_pos..], lit_offset)
+}
+
+/// Decodes an LZ chunk of `size` bytes at `start` in the output
+pub(super) fn decode_chunk(mode: u32, src: &[u8], output: &mut [u8], start: usize, size: usize) -> OodleResult<()> {
+    let table = read_lz_table(mode, src, output, start, size)?;
+    process_lz_runs(mode, &table, output, start, start + size)
+}
\ No newline at end of file
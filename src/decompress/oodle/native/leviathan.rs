@@ -0,0 +1,239 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Leviathan, the LZ decoder with the highest ratio
+
+use super::entropy::{decode_bytes, decode_multi_array};
+use super::kraken::unpack_offsets;
+use super::{OodleResult, oodle_err, copy_match};
+
+/// The literal streams of a chunk, and how the chunk mode combines them into output bytes
+struct Literals {
+    mode: u32,
+    streams: Vec<Vec<u8>>,
+    positions: Vec<usize>,
+}
+
+impl Literals {
+    fn take(&mut self, stream: usize) -> OodleResult<u8> {
+        let pos = self.positions[stream];
+        match self.streams[stream].get(pos) {
+            Some(v) => {
+                self.positions[stream] += 1;
+                Ok(*v)
+            },
+            None => oodle_err("Leviathan literals ran out"),
+        }
+    }
+
+    /// Writes `count` literals at `dst`, with `last_offset` giving the bytes that delta coded literals are added to
+    fn copy(&mut self, output: &mut [u8], dst: usize, count: usize, last_offset: isize) -> OodleResult<()> {
+        if count > output.len() - dst {
+            return oodle_err("Leviathan literals overflow the chunk");
+        }
+        if count > 0 && self.mode != 1 && self.mode != 4 && (dst as isize) + last_offset < 0 {
+            return oodle_err("Leviathan literal offset is out of range");
+        }
+
+        for i in 0..count {
+            let d = dst + i;
+            let literal = match self.mode {
+                1 => self.take(0)?,
+                2 => match i {
+                    0 => self.take(1)?,
+                    _ => self.take(0)?,
+                },
+                3 => self.take(d & 3)?,
+                4 => match d {
+                    0 => return oodle_err("Leviathan literal has no context"),
+                    _ => self.take((output[d - 1] >> 4) as usize)?,
+                },
+                5 => self.take(d & 15)?,
+                _ => self.take(0)?,
+            };
+            output[d] = match self.mode {
+                1 | 4 => literal,
+                _ => literal.wrapping_add(output[(d as isize + last_offset) as usize]),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// The streams that drive the LZ runs of a chunk. Commands are either one stream, or eight picked between by output position.
+struct LzTable {
+    cmd_streams: Vec<Vec<u8>>,
+    literals: Literals,
+    offs_stream: Vec<i32>,
+    len_stream: Vec<u32>,
+}
+
+fn read_lz_table(mode: u32, src: &[u8], output: &mut [u8], start: usize, size: usize) -> OodleResult<LzTable> {
+    if mode > 5 {
+        return oodle_err("Invalid Leviathan chunk mode");
+    }
+    if src.len() < 13 || size < 8 {
+        return oodle_err("Leviathan chunk is too short");
+    }
+
+    let mut pos = 0;
+    if start == 0 {
+        output[..8].copy_from_slice(&src[..8]);
+        pos += 8;
+    }
+
+    let mut offs_scaling = 0;
+    let mut packed_offs_extra = Vec::new();
+    if src[pos] & 0x80 != 0 {
+        offs_scaling = src[pos] as u32 - 127;
+        pos += 1;
+    }
+    let (packed_offs, used) = decode_bytes(&src[pos..], size / 3)?;
+    pos += used;
+    if offs_scaling > 1 {
+        let (extra, used) = decode_bytes(&src[pos..], size / 3)?;
+        if extra.len() != packed_offs.len() {
+            return oodle_err("Offset arrays have different lengths");
+        }
+        pos += used;
+        packed_offs_extra = extra;
+    }
+
+    let (packed_lens, used) = decode_bytes(&src[pos..], size / 5)?;
+    pos += used;
+
+    let streams = match mode {
+        0 | 1 => {
+            let (stream, used) = decode_bytes(&src[pos..], size)?;
+            pos += used;
+            vec![stream]
+        },
+        _ => {
+            let array_count = match mode {
+                2 => 2,
+                3 => 4,
+                _ => 16,
+            };
+            let (streams, used) = decode_multi_array(&src[pos..], size, array_count)?;
+            pos += used;
+            streams
+        },
+    };
+    let literals = Literals {
+        mode,
+        positions: vec![0; streams.len()],
+        streams,
+    };
+
+    if pos >= src.len() {
+        return oodle_err("Leviathan chunk is too short");
+    }
+    let cmd_streams = match src[pos] & 0x80 {
+        0 => {
+            let (stream, used) = decode_bytes(&src[pos..], size)?;
+            pos += used;
+            vec![stream]
+        },
+        _ => {
+            if src[pos] != 0x83 {
+                return oodle_err("Invalid Leviathan command streams");
+            }
+            pos += 1;
+            let (streams, used) = decode_multi_array(&src[pos..], size, 8)?;
+            pos += used;
+            streams
+        },
+    };
+
+    let (offs_stream, len_stream) = unpack_offsets(&src[pos..], &packed_offs, &packed_offs_extra, offs_scaling, &packed_lens)?;
+
+    Ok(LzTable {
+        cmd_streams,
+        literals,
+        offs_stream,
+        len_stream,
+    })
+}
+
+fn process_lz_runs(table: &mut LzTable, output: &mut [u8], start: usize, end: usize) -> OodleResult<()> {
+    let output = &mut output[..end];
+    let mut dst = match start {
+        0 => 8,
+        _ => start,
+    };
+
+    let mut cmd_positions = vec![0usize; table.cmd_streams.len()];
+    let mut cmds_left: usize = table.cmd_streams.iter().map(|v| v.len()).sum();
+    let mut len_front = 0;
+    let mut len_back = table.len_stream.len();
+    let mut offs_pos = 0;
+    let mut recent_offs = [0i32; 16];
+    recent_offs[8..15].copy_from_slice(&[-8; 7]);
+    let mut last_offset = -8isize;
+
+    while cmds_left > 0 {
+        cmds_left -= 1;
+        let stream = match table.cmd_streams.len() {
+            1 => 0,
+            _ => dst & 7,
+        };
+        let cmd = match table.cmd_streams[stream].get(cmd_positions[stream]) {
+            Some(v) => *v as usize,
+            None => return oodle_err("Leviathan commands ran out"),
+        };
+        cmd_positions[stream] += 1;
+
+        let offs_index = cmd >> 5;
+        let matchlen = (cmd & 7) + 2;
+
+        let litlen = match (cmd >> 3) & 3 {
+            3 => {
+                if len_front >= len_back {
+                    return oodle_err("Leviathan lengths ran out");
+                }
+                len_front += 1;
+                (table.len_stream[len_front - 1] & 0xffffff) as usize
+            },
+            v => v,
+        };
+        table.literals.copy(output, dst, litlen, last_offset)?;
+        dst += litlen;
+
+        // Either one of the seven most recent offsets, or the next new one
+        recent_offs[15] = table.offs_stream.get(offs_pos).copied().unwrap_or(0);
+        recent_offs[8..=(offs_index + 8)].rotate_right(1);
+        let offset = recent_offs[8] as isize;
+        last_offset = offset;
+        if offs_index == 7 {
+            offs_pos += 1;
+        }
+
+        // Long match lengths are taken from the end of the length stream
+        let matchlen = match matchlen {
+            9 => {
+                if len_front >= len_back {
+                    return oodle_err("Leviathan lengths ran out");
+                }
+                len_back -= 1;
+                table.len_stream[len_back] as usize + 6
+            },
+            v => v,
+        };
+        copy_match(output, dst, offset, matchlen)?;
+        dst += matchlen;
+    }
+
+    if offs_pos != table.offs_stream.len() || len_front != len_back {
+        return oodle_err("Leviathan offsets or lengths were not all used");
+    }
+
+    let final_len = end - dst;
+    table.literals.copy(output, dst, final_len, last_offset)
+}
+
+/// Decodes an LZ chunk of `size` bytes at `start` in the output
+pub(super) fn decode_chunk(mode: u32, src: &[u8], output: &mut [u8], start: usize, size: usize) -> OodleResult<()> {
+    let mut table = read_lz_table(mode, src, output, start, size)?;
+    process_lz_runs(&mut table, output, start, start + size)
+}
\ No newline at end of file
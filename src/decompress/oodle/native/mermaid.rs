@@ -0,0 +1,281 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Mermaid and Selkie, the faster LZ decoders, which share a format
+
+use super::bits::get_u16_le;
+use super::entropy::decode_bytes;
+use super::{OodleResult, oodle_err, copy_literals, copy_match};
+
+/// The streams that drive the LZ runs of a chunk. Far offsets are split between the two 64KB halves of the chunk.
+struct LzTable {
+    lit_stream: Vec<u8>,
+    cmd_stream: Vec<u8>,
+    cmd_stream_2_offs: usize,
+    off16_stream: Vec<u16>,
+    off32_stream_1: Vec<u32>,
+    off32_stream_2: Vec<u32>,
+    length_stream_start: usize,
+}
+
+/// The positions in each stream, carried over from the first half of the chunk into the second
+struct LzState {
+    lit_pos: usize,
+    off16_pos: usize,
+    length_pos: usize,
+    recent_offs: isize,
+}
+
+/// Reads `count` far offsets, each of which must reach no further back than `offset`
+fn decode_far_offsets(src: &[u8], count: usize, offset: usize) -> OodleResult<(Vec<u32>, usize)> {
+    let mut offsets = Vec::with_capacity(count);
+    let mut pos = 0;
+    for _i in 0..count {
+        if src.len() - pos < 3 {
+            return oodle_err("Mermaid far offsets run past the end of the chunk");
+        }
+        let mut off = src[pos] as u32 | (src[pos + 1] as u32) << 8 | (src[pos + 2] as u32) << 16;
+        pos += 3;
+        // Offsets that far into a block need an extra byte
+        if offset >= 0xc00000 - 1 && off >= 0xc00000 {
+            if pos == src.len() {
+                return oodle_err("Mermaid far offsets run past the end of the chunk");
+            }
+            off += (src[pos] as u32) << 22;
+            pos += 1;
+        }
+        if off as usize > offset {
+            return oodle_err("Mermaid far offset is out of range");
+        }
+        offsets.push(off);
+    }
+    Ok((offsets, pos))
+}
+
+fn read_lz_table(mode: u32, src: &[u8], output: &mut [u8], start: usize, size: usize) -> OodleResult<LzTable> {
+    if mode > 1 {
+        return oodle_err("Invalid Mermaid chunk mode");
+    }
+    if src.len() < 10 || size < 8 {
+        return oodle_err("Mermaid chunk is too short");
+    }
+
+    let mut pos = 0;
+    if start == 0 {
+        output[..8].copy_from_slice(&src[..8]);
+        pos += 8;
+    }
+
+    let (lit_stream, used) = decode_bytes(&src[pos..], size)?;
+    pos += used;
+    let (cmd_stream, used) = decode_bytes(&src[pos..], size)?;
+    pos += used;
+
+    let cmd_stream_2_offs = match size <= 0x10000 {
+        true => cmd_stream.len(),
+        false => {
+            if src.len() - pos < 2 {
+                return oodle_err("Mermaid chunk is too short");
+            }
+            let offs = get_u16_le(src, pos as isize) as usize;
+            pos += 2;
+            if offs > cmd_stream.len() {
+                return oodle_err("Invalid Mermaid command split");
+            }
+            offs
+        },
+    };
+
+    if src.len() - pos < 2 {
+        return oodle_err("Mermaid chunk is too short");
+    }
+    let off16_count = get_u16_le(src, pos as isize) as usize;
+    pos += 2;
+    let off16_stream = match off16_count {
+        // Entropy coded as separate high and low bytes
+        0xffff => {
+            let (hi, used) = decode_bytes(&src[pos..], size >> 1)?;
+            pos += used;
+            let (lo, used) = decode_bytes(&src[pos..], size >> 1)?;
+            pos += used;
+            if hi.len() != lo.len() {
+                return oodle_err("Mermaid offset arrays have different lengths");
+            }
+            lo.iter().zip(hi.iter()).map(|(lo, hi)| *lo as u16 + *hi as u16 * 256).collect()
+        },
+        _ => {
+            if src.len() - pos < off16_count * 2 {
+                return oodle_err("Mermaid chunk is too short");
+            }
+            let stream = (0..off16_count).map(|i| get_u16_le(src, (pos + i * 2) as isize) as u16).collect();
+            pos += off16_count * 2;
+            stream
+        },
+    };
+
+    if src.len() - pos < 3 {
+        return oodle_err("Mermaid chunk is too short");
+    }
+    let tmp = src[pos] as usize | (src[pos + 1] as usize) << 8 | (src[pos + 2] as usize) << 16;
+    pos += 3;
+
+    let mut off32_stream_1 = Vec::new();
+    let mut off32_stream_2 = Vec::new();
+    if tmp != 0 {
+        let mut off32_size_1 = tmp >> 12;
+        let mut off32_size_2 = tmp & 0xfff;
+        if off32_size_1 == 4095 {
+            if src.len() - pos < 2 {
+                return oodle_err("Mermaid chunk is too short");
+            }
+            off32_size_1 = get_u16_le(src, pos as isize) as usize;
+            pos += 2;
+        }
+        if off32_size_2 == 4095 {
+            if src.len() - pos < 2 {
+                return oodle_err("Mermaid chunk is too short");
+            }
+            off32_size_2 = get_u16_le(src, pos as isize) as usize;
+            pos += 2;
+        }
+
+        let (offsets, used) = decode_far_offsets(&src[pos..], off32_size_1, start)?;
+        off32_stream_1 = offsets;
+        pos += used;
+        let (offsets, used) = decode_far_offsets(&src[pos..], off32_size_2, start + 0x10000)?;
+        off32_stream_2 = offsets;
+        pos += used;
+    }
+
+    Ok(LzTable {
+        lit_stream,
+        cmd_stream,
+        cmd_stream_2_offs,
+        off16_stream,
+        off32_stream_1,
+        off32_stream_2,
+        length_stream_start: pos,
+    })
+}
+
+/// Reads a length from the length stream: one byte, or a byte and a u16 for longer lengths
+fn read_length(src: &[u8], pos: &mut usize) -> OodleResult<usize> {
+    let mut length = match src.get(*pos) {
+        Some(v) => *v as usize,
+        None => return oodle_err("Mermaid lengths ran out"),
+    };
+    if length > 251 {
+        if src.len() - *pos < 3 {
+            return oodle_err("Mermaid lengths ran out");
+        }
+        length += get_u16_le(src, *pos as isize + 1) as usize * 4;
+        *pos += 2;
+    }
+    *pos += 1;
+    Ok(length)
+}
+
+/// Runs the commands for one 64KB half of a chunk, from `begin` to `end` in the output
+#[allow(clippy::too_many_arguments)]
+fn process_lz_half(mode: u32, src: &[u8], table: &LzTable, commands: &[u8], off32_stream: &[u32], output: &mut [u8], begin: usize, dst: usize, end: usize, state: &mut LzState) -> OodleResult<()> {
+    let output = &mut output[..end];
+    let mut dst = dst;
+    let mut off32 = off32_stream.iter();
+    let lit_offset = |recent_offs| match mode {
+        0 => Some(recent_offs),
+        _ => None,
+    };
+
+    let take_literals = |count: usize, state: &mut LzState| -> OodleResult<&[u8]> {
+        if count > table.lit_stream.len() - state.lit_pos {
+            return oodle_err("Mermaid literals ran out");
+        }
+        let literals = &table.lit_stream[state.lit_pos..(state.lit_pos + count)];
+        state.lit_pos += count;
+        Ok(literals)
+    };
+
+    for cmd in commands {
+        let cmd = *cmd as usize;
+        if cmd >= 24 {
+            // Short literals followed by a short match, at the most recent offset or a new near one
+            let litlen = cmd & 7;
+            copy_literals(output, dst, take_literals(litlen, state)?, lit_offset(state.recent_offs))?;
+            dst += litlen;
+            if cmd < 128 {
+                state.recent_offs = match table.off16_stream.get(state.off16_pos) {
+                    Some(v) => -(*v as isize),
+                    None => return oodle_err("Mermaid near offsets ran out"),
+                };
+                state.off16_pos += 1;
+            }
+            let matchlen = (cmd >> 3) & 0xf;
+            copy_match(output, dst, state.recent_offs, matchlen)?;
+            dst += matchlen;
+        } else if cmd == 0 {
+            let length = read_length(src, &mut state.length_pos)? + 64;
+            copy_literals(output, dst, take_literals(length, state)?, lit_offset(state.recent_offs))?;
+            dst += length;
+        } else if cmd == 1 {
+            let length = read_length(src, &mut state.length_pos)? + 91;
+            state.recent_offs = match table.off16_stream.get(state.off16_pos) {
+                Some(v) => -(*v as isize),
+                None => return oodle_err("Mermaid near offsets ran out"),
+            };
+            state.off16_pos += 1;
+            copy_match(output, dst, state.recent_offs, length)?;
+            dst += length;
+        } else {
+            // Far matches, with offsets relative to the start of this half
+            let length = match cmd {
+                2 => read_length(src, &mut state.length_pos)? + 29,
+                _ => cmd + 5,
+            };
+            let match_pos = match off32.next() {
+                Some(v) => begin as isize - *v as isize,
+                None => return oodle_err("Mermaid far offsets ran out"),
+            };
+            state.recent_offs = match_pos - dst as isize;
+            copy_match(output, dst, state.recent_offs, length)?;
+            dst += length;
+        }
+    }
+
+    let length = end - dst;
+    copy_literals(output, dst, take_literals(length, state)?, lit_offset(state.recent_offs))
+}
+
+/// Decodes an LZ chunk of `size` bytes at `start` in the output
+pub(super) fn decode_chunk(mode: u32, src: &[u8], output: &mut [u8], start: usize, size: usize) -> OodleResult<()> {
+    let table = read_lz_table(mode, src, output, start, size)?;
+    let mut state = LzState {
+        lit_pos: 0,
+        off16_pos: 0,
+        length_pos: table.length_stream_start,
+        recent_offs: -8,
+    };
+
+    let halves = [
+        (&table.cmd_stream[..table.cmd_stream_2_offs], &table.off32_stream_1),
+        (&table.cmd_stream[table.cmd_stream_2_offs..], &table.off32_stream_2),
+    ];
+    let mut begin = start;
+    for (commands, off32_stream) in halves.iter() {
+        let end = std::cmp::min(begin + 0x10000, start + size);
+        let dst = match begin {
+            0 => 8,
+            _ => begin,
+        };
+        process_lz_half(mode, src, &table, commands, off32_stream, output, begin, dst, end, &mut state)?;
+        begin = end;
+        if begin == start + size {
+            break;
+        }
+    }
+
+    if state.length_pos != src.len() {
+        return oodle_err("Mermaid lengths did not end where expected");
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,193 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Unlike the rest of this crate, which is MIT licensed, this module and its submodules are distributed under
+// version 3 of the GNU General Public License, found in COPYING. It is only built with the `native-oodle` feature.
+
+//! The native Oodle decoder, used when the Oodle library has not been loaded
+
+use super::{OodleResult, oodle_err};
+
+mod bits;
+mod huffman;
+mod tans;
+mod entropy;
+mod kraken;
+mod mermaid;
+mod leviathan;
+#[cfg(test)]
+mod tests;
+
+/// The size of each independently headed block of a stream
+const BLOCK_SIZE: usize = 0x40000;
+/// The most output each chunk of a block can hold
+const CHUNK_SIZE: usize = 0x20000;
+
+/// Decodes a chunk of LZ commands: `(mode, src, output, start, size)`
+type ChunkDecoder = fn(u32, &[u8], &mut [u8], usize, usize) -> OodleResult<()>;
+
+/// Decompresses an Oodle stream without the Oodle library
+pub fn decompress_native(uncompressed_size: u64, bytes: &[u8]) -> OodleResult<Vec<u8>> {
+    let mut output = vec![0u8; uncompressed_size as usize];
+    let mut src = 0;
+    let mut dst = 0;
+
+    while dst < output.len() {
+        // Each block starts with the decoder type, and whether it's compressed at all
+        if bytes.len() - src < 2 {
+            return oodle_err("Oodle stream is truncated");
+        }
+        let (flags, decoder_type) = (bytes[src], bytes[src + 1]);
+        if flags & 0xf != 0xc || (flags >> 4) & 3 != 0 {
+            return oodle_err("Invalid Oodle block header");
+        }
+        let uncompressed = (flags >> 6) & 1 == 1;
+        let use_checksums = decoder_type >> 7 == 1;
+        let decoder: ChunkDecoder = match decoder_type & 0x7f {
+            6 => kraken::decode_chunk,
+            10 => mermaid::decode_chunk,
+            12 => leviathan::decode_chunk,
+            5 => return oodle_err("LZNA streams are not supported"),
+            11 => return oodle_err("BitKnit streams are not supported"),
+            _ => return oodle_err("Unknown Oodle decoder type"),
+        };
+        src += 2;
+
+        let block_size = std::cmp::min(BLOCK_SIZE, output.len() - dst);
+        if uncompressed {
+            if bytes.len() - src < block_size {
+                return oodle_err("Oodle stream is truncated");
+            }
+            output[dst..(dst + block_size)].copy_from_slice(&bytes[src..(src + block_size)]);
+            src += block_size;
+            dst += block_size;
+            continue;
+        }
+
+        // Then the size of the compressed block, or a fill byte
+        if bytes.len() - src < 3 {
+            return oodle_err("Oodle stream is truncated");
+        }
+        let v = (bytes[src] as usize) << 16 | (bytes[src + 1] as usize) << 8 | bytes[src + 2] as usize;
+        let size = v & 0x3ffff;
+        let compressed_size = if size != 0x3ffff {
+            src += match use_checksums {
+                true => 6,
+                false => 3,
+            };
+            size + 1
+        } else if v >> 18 == 1 {
+            if bytes.len() - src < 4 {
+                return oodle_err("Oodle stream is truncated");
+            }
+            let fill = bytes[src + 3];
+            output[dst..(dst + block_size)].iter_mut().for_each(|v| *v = fill);
+            src += 4;
+            dst += block_size;
+            continue;
+        } else {
+            return oodle_err("Invalid Oodle quantum header");
+        };
+
+        if src > bytes.len() || bytes.len() - src < compressed_size {
+            return oodle_err("Oodle stream is truncated");
+        }
+        if compressed_size > block_size {
+            return oodle_err("Oodle block is larger than its output");
+        }
+        let block = &bytes[src..(src + compressed_size)];
+        if compressed_size == block_size {
+            output[dst..(dst + block_size)].copy_from_slice(block);
+        } else {
+            let used = decode_quantum(decoder, block, &mut output, dst, dst + block_size)?;
+            if used != compressed_size {
+                return oodle_err("Oodle block did not use all of its input");
+            }
+        }
+        src += compressed_size;
+        dst += block_size;
+    }
+
+    if src != bytes.len() {
+        return oodle_err("Oodle stream has trailing data");
+    }
+    Ok(output)
+}
+
+/// Decodes one block into `output[start..end]`, a chunk at a time, returning the number of bytes used
+fn decode_quantum(decoder: ChunkDecoder, src: &[u8], output: &mut [u8], start: usize, end: usize) -> OodleResult<usize> {
+    let mut pos = 0;
+    let mut dst = start;
+
+    while dst < end {
+        let dst_count = std::cmp::min(CHUNK_SIZE, end - dst);
+        if src.len() - pos < 4 {
+            return oodle_err("Oodle chunk is truncated");
+        }
+        let chunkhdr = (src[pos] as usize) << 16 | (src[pos + 1] as usize) << 8 | src[pos + 2] as usize;
+
+        if chunkhdr & 0x800000 == 0 {
+            // Stored as a single entropy coded array, without any matches
+            let (data, used) = entropy::decode_bytes(&src[pos..], dst_count)?;
+            if data.len() != dst_count {
+                return oodle_err("Oodle chunk is the wrong size");
+            }
+            output[dst..(dst + dst_count)].copy_from_slice(&data);
+            pos += used;
+        } else {
+            pos += 3;
+            let src_used = chunkhdr & 0x7ffff;
+            let mode = ((chunkhdr >> 19) & 0xf) as u32;
+            if src.len() - pos < src_used {
+                return oodle_err("Oodle chunk is truncated");
+            }
+            if src_used < dst_count {
+                decoder(mode, &src[pos..(pos + src_used)], output, dst, dst_count)?;
+            } else if src_used > dst_count || mode != 0 {
+                return oodle_err("Invalid Oodle chunk header");
+            } else {
+                output[dst..(dst + dst_count)].copy_from_slice(&src[pos..(pos + dst_count)]);
+            }
+            pos += src_used;
+        }
+        dst += dst_count;
+    }
+
+    Ok(pos)
+}
+
+/// Copies a match from earlier in the output. Matches can overlap what they write, so this goes a byte at a time.
+fn copy_match(output: &mut [u8], dst: usize, offset: isize, length: usize) -> OodleResult<()> {
+    let from = dst as isize + offset;
+    if offset >= 0 || from < 0 {
+        return oodle_err("Match offset is out of range");
+    }
+    if length > output.len() - dst {
+        return oodle_err("Match overflows its chunk");
+    }
+    let from = from as usize;
+    for i in 0..length {
+        output[dst + i] = output[from + i];
+    }
+    Ok(())
+}
+
+/// Writes literals to the output, adding each to the byte at `last_offset` if given
+fn copy_literals(output: &mut [u8], dst: usize, literals: &[u8], last_offset: Option<isize>) -> OodleResult<()> {
+    if literals.len() > output.len() - dst {
+        return oodle_err("Literals overflow their chunk");
+    }
+    match last_offset {
+        Some(offset) => {
+            if !literals.is_empty() && (dst as isize) + offset < 0 {
+                return oodle_err("Literal offset is out of range");
+            }
+            for (i, literal) in literals.iter().enumerate() {
+                let from = (dst as isize + i as isize + offset) as usize;
+                output[dst + i] = literal.wrapping_add(output[from]);
+            }
+        },
+        None => output[dst..(dst + literals.len())].copy_from_slice(literals),
+    }
+    Ok(())
+}
\ No newline at end of file
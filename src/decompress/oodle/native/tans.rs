@@ -0,0 +1,469 @@
+// Based on ooz, Copyright (C) 2016 Powzix, https://github.com/powzix/ooz
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! tANS coded byte arrays
+
+use super::bits::{BitReader, get_u32_le, get_u32_be, shl};
+use super::huffman::convert_to_ranges;
+use super::{OodleResult, oodle_err};
+
+/// The symbols of a tANS table: those with a weight of 1 in `a`, and the rest in `b` as `symbol << 16 | weight`
+struct TansData {
+    a: Vec<u8>,
+    b: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct TansLutEnt {
+    x: u32,
+    bits_x: u8,
+    symbol: u8,
+    w: u16,
+}
+
+fn decode_table(bits: &mut BitReader, l_bits: u32) -> OodleResult<TansData> {
+    let l = 1u32 << l_bits;
+    let mut data = TansData {
+        a: Vec::new(),
+        b: Vec::new(),
+    };
+
+    bits.refill();
+    if bits.read_bit_no_refill() != 0 {
+        // Golomb-Rice coded weights, relative to a running average
+        let q = bits.read_bits_no_refill(3);
+        let num_symbols = bits.read_bits_no_refill(8) + 1;
+        if num_symbols < 2 {
+            return oodle_err("Invalid tANS table");
+        }
+        let fluff = bits.read_fluff(num_symbols);
+        let total_rice_values = (fluff + num_symbols) as usize;
+        let mut rice = vec![0u8; total_rice_values + 16];
+
+        let mut br2 = bits.to_bit_reader2();
+        if !br2.decode_golomb_rice_lengths(&mut rice[..total_rice_values]) {
+            return oodle_err("Invalid tANS table");
+        }
+        *bits = BitReader::from_bit_reader2(&br2);
+
+        let ranges = convert_to_ranges(num_symbols, fluff, &rice[(num_symbols as usize)..], bits)?;
+        bits.refill();
+
+        let mut cur_rice = 0;
+        let mut average = 6i32;
+        let mut somesum = 0u32;
+        for range in ranges {
+            for symbol in range.symbol..(range.symbol + range.num) {
+                bits.refill();
+
+                let nextra = q + rice[cur_rice] as u32;
+                cur_rice += 1;
+                if nextra > 15 {
+                    return oodle_err("Invalid tANS table");
+                }
+                let mut v = (bits.read_bits_no_refill_zero(nextra) + (1 << nextra) - (1 << q)) as i32;
+
+                let average_div4 = average >> 2;
+                let mut limit = 2 * average_div4;
+                if v <= limit {
+                    v = average_div4 + (-(v & 1) ^ ((v as u32) >> 1) as i32);
+                }
+                if limit > v {
+                    limit = v;
+                }
+                v += 1;
+                average += limit - average_div4;
+                match v {
+                    1 => data.a.push(symbol as u8),
+                    v if v >= 2 => data.b.push((symbol << 16) + v as u32),
+                    _ => return oodle_err("Invalid tANS weight"),
+                }
+                somesum = somesum.wrapping_add(v as u32);
+            }
+        }
+        if somesum != l {
+            return oodle_err("tANS weights do not sum to the table size");
+        }
+    } else {
+        // An explicit list of symbols, with delta coded weights
+        let mut seen = [false; 256];
+        let count = bits.read_bits_no_refill(3) + 1;
+        let bits_per_sym = 32 - l_bits.leading_zeros();
+        let max_delta_bits = bits.read_bits_no_refill(bits_per_sym);
+        if max_delta_bits == 0 || max_delta_bits > l_bits {
+            return oodle_err("Invalid tANS table");
+        }
+
+        let mut weight = 0u32;
+        let mut total_weights = 0u32;
+        for _i in 0..count {
+            bits.refill();
+            let sym = bits.read_bits_no_refill(8);
+            if seen[sym as usize] {
+                return oodle_err("Repeated symbol in tANS table");
+            }
+            let delta = bits.read_bits_no_refill(max_delta_bits);
+            weight += delta;
+            if weight == 0 {
+                return oodle_err("Invalid tANS weight");
+            }
+            seen[sym as usize] = true;
+            match weight {
+                1 => data.a.push(sym as u8),
+                _ => data.b.push((sym << 16) + weight),
+            }
+            total_weights += weight;
+        }
+
+        // The last symbol takes whatever weight is left
+        bits.refill();
+        let sym = bits.read_bits_no_refill(8);
+        if seen[sym as usize] {
+            return oodle_err("Repeated symbol in tANS table");
+        }
+        if total_weights >= l || l - total_weights < weight || l - total_weights <= 1 {
+            return oodle_err("Invalid tANS weight");
+        }
+        data.b.push((sym << 16) + (l - total_weights));
+
+        data.a.sort_unstable();
+        data.b.sort_unstable();
+    }
+
+    Ok(data)
+}
+
+fn init_lut(data: &TansData, l_bits: u32) -> OodleResult<Vec<TansLutEnt>> {
+    let l = 1u32 << l_bits;
+    let mut lut = vec![TansLutEnt::default(); l as usize];
+    let a_used = data.a.len() as u32;
+    if a_used > l {
+        return oodle_err("Invalid tANS table");
+    }
+
+    // Symbols with larger weights are spread over 4 interleaved runs of slots
+    let slots_left_to_alloc = l - a_used;
+    let sa = slots_left_to_alloc >> 2;
+    let mut pointers = [0usize; 4];
+    let mut sb = sa + ((slots_left_to_alloc & 3) > 0) as u32;
+    pointers[1] = sb as usize;
+    sb += sa + ((slots_left_to_alloc & 3) > 1) as u32;
+    pointers[2] = sb as usize;
+    sb += sa + ((slots_left_to_alloc & 3) > 2) as u32;
+    pointers[3] = sb as usize;
+
+    // Symbols with a weight of 1 go at the end
+    for (i, symbol) in data.a.iter().enumerate() {
+        lut[(slots_left_to_alloc as usize) + i] = TansLutEnt {
+            x: l - 1,
+            bits_x: l_bits as u8,
+            symbol: *symbol,
+            w: 0,
+        };
+    }
+
+    let mut set = |idx: usize, ent: TansLutEnt| -> OodleResult<()> {
+        match idx < slots_left_to_alloc as usize {
+            true => {
+                lut[idx] = ent;
+                Ok(())
+            },
+            false => oodle_err("tANS weights overflow the table"),
+        }
+    };
+
+    let mut weights_sum = 0i32;
+    for b in &data.b {
+        let weight = (b & 0xffff) as i32;
+        let symbol = (b >> 16) as u8;
+        if weight > 4 {
+            let sym_bits = 31 - (weight as u32).leading_zeros();
+            let mut z = l_bits as i32 - sym_bits as i32;
+            if z < 0 {
+                return oodle_err("Invalid tANS weight");
+            }
+            let mut le = TansLutEnt {
+                symbol,
+                bits_x: z as u8,
+                x: (1u32 << z) - 1,
+                w: ((l - 1) & ((weight as u32) << z)) as u16,
+            };
+            let mut what_to_add = 1u32 << z;
+            let mut x = (1i32 << (sym_bits + 1)) - weight;
+
+            for (j, pointer) in pointers.iter_mut().enumerate() {
+                let mut dst = *pointer;
+                let y = (weight + ((weights_sum - j as i32 - 1) & 3)) >> 2;
+                if x >= y {
+                    for _n in 0..y {
+                        set(dst, le)?;
+                        dst += 1;
+                        le.w = le.w.wrapping_add(what_to_add as u16);
+                    }
+                    x -= y;
+                } else {
+                    for _n in 0..x {
+                        set(dst, le)?;
+                        dst += 1;
+                        le.w = le.w.wrapping_add(what_to_add as u16);
+                    }
+                    z -= 1;
+                    what_to_add >>= 1;
+                    le.bits_x = z as u8;
+                    le.w = 0;
+                    le.x >>= 1;
+                    for _n in 0..(y - x) {
+                        set(dst, le)?;
+                        dst += 1;
+                        le.w = le.w.wrapping_add(what_to_add as u16);
+                    }
+                    x = weight;
+                }
+                *pointer = dst;
+            }
+        } else if weight > 0 {
+            let mut bits = ((1u32 << weight) - 1) << (weights_sum & 3);
+            bits |= bits >> 4;
+            for ww in (weight as u32)..(2 * weight as u32) {
+                let idx = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let dst = pointers[idx];
+                pointers[idx] += 1;
+                let weight_bits = 31 - ww.leading_zeros();
+                set(dst, TansLutEnt {
+                    symbol,
+                    bits_x: (l_bits - weight_bits) as u8,
+                    x: (1u32 << (l_bits - weight_bits)) - 1,
+                    w: ((l - 1) & (ww << (l_bits - weight_bits))) as u16,
+                })?;
+            }
+        } else {
+            return oodle_err("Invalid tANS weight");
+        }
+        weights_sum += weight;
+    }
+
+    Ok(lut)
+}
+
+/// One of the two bit streams of a tANS array: read LSB-first, forwards from the start or backwards from the end
+struct TansStream {
+    pos: isize,
+    bits: u32,
+    bitpos: u32,
+}
+
+impl TansStream {
+    fn refill(&mut self, src: &[u8]) {
+        self.bits |= shl(get_u32_le(src, self.pos), self.bitpos);
+        self.pos += ((31 - self.bitpos) >> 3) as isize;
+        self.bitpos |= 24;
+    }
+
+    fn refill_backwards(&mut self, src: &[u8]) {
+        self.bits |= shl(get_u32_be(src, self.pos - 4), self.bitpos);
+        self.pos -= ((31 - self.bitpos) >> 3) as isize;
+        self.bitpos |= 24;
+    }
+
+    /// Outputs the symbol for a state, and moves the state on using bits from this stream
+    fn round(&mut self, lut: &[TansLutEnt], state: &mut u32) -> OodleResult<u8> {
+        let e = match lut.get(*state as usize) {
+            Some(e) => e,
+            None => return oodle_err("Invalid tANS state"),
+        };
+        if (e.bits_x as u32) > self.bitpos {
+            return oodle_err("tANS stream ran out of bits");
+        }
+        self.bitpos -= e.bits_x as u32;
+        *state = (self.bits & e.x) + e.w as u32;
+        self.bits = self.bits.checked_shr(e.bits_x as u32).unwrap_or(0);
+        Ok(e.symbol)
+    }
+}
+
+/// Decodes a tANS coded array. Five interleaved states are used, and their final values are the last five bytes.
+pub(super) fn decode_bytes_tans(src: &[u8], output: &mut [u8]) -> OodleResult<()> {
+    if src.len() < 8 || output.len() < 5 {
+        return oodle_err("tANS block is too short");
+    }
+
+    let mut br = BitReader::new(src, 0);
+    if br.read_bit_no_refill() != 0 {
+        return oodle_err("Reserved tANS bit is set");
+    }
+    let l_bits = br.read_bits_no_refill(2) + 8;
+    let data = decode_table(&mut br, l_bits)?;
+    let src_start = br.get_position();
+    if src_start < 0 || src_start as usize >= src.len() {
+        return oodle_err("tANS table runs past the end of its block");
+    }
+
+    let lut = init_lut(&data, l_bits)?;
+
+    // Read the initial states, two from each end, then one more from the front
+    let l_mask = (1u32 << l_bits) - 1;
+    let mut f = TansStream {
+        pos: src_start + 4,
+        bits: get_u32_le(src, src_start),
+        bitpos: 32,
+    };
+    let mut b = TansStream {
+        pos: src.len() as isize - 4,
+        bits: get_u32_be(src, src.len() as isize - 4),
+        bitpos: 32,
+    };
+
+    let mut states = [0u32; 5];
+    states[0] = f.bits & l_mask;
+    states[1] = b.bits & l_mask;
+    f.bits >>= l_bits;
+    f.bitpos -= l_bits;
+    b.bits >>= l_bits;
+    b.bitpos -= l_bits;
+
+    states[2] = f.bits & l_mask;
+    states[3] = b.bits & l_mask;
+    f.bits >>= l_bits;
+    f.bitpos -= l_bits;
+    b.bits >>= l_bits;
+    b.bitpos -= l_bits;
+
+    f.refill(src);
+    states[4] = f.bits & l_mask;
+    f.bits >>= l_bits;
+    f.bitpos -= l_bits;
+
+    // Step back to the first byte not fully held in each register
+    f.pos -= (f.bitpos >> 3) as isize;
+    f.bitpos &= 7;
+    b.pos += (b.bitpos >> 3) as isize;
+    b.bitpos &= 7;
+
+    if f.pos > b.pos {
+        return oodle_err("tANS streams overlap");
+    }
+
+    // The order states are used in, and which stream feeds them
+    const ROUNDS: [(bool, usize); 10] = [
+        (true, 0), (true, 1), (true, 2), (true, 3),
+        (false, 4), (false, 0), (false, 1), (false, 2), (false, 3), (false, 4),
+    ];
+
+    let dst_end = output.len() - 5;
+    let mut dst = 0;
+    'decode: while dst < dst_end {
+        for (i, (forward, state)) in ROUNDS.iter().enumerate() {
+            match (forward, i) {
+                (true, 0) | (true, 2) => f.refill(src),
+                (false, 4) | (false, 6) | (false, 8) => b.refill_backwards(src),
+                _ => {},
+            }
+            output[dst] = match forward {
+                true => f.round(&lut, &mut states[*state])?,
+                false => b.round(&lut, &mut states[*state])?,
+            };
+            dst += 1;
+            if dst >= dst_end {
+                break 'decode;
+            }
+        }
+    }
+
+    if b.pos - f.pos + (f.bitpos >> 3) as isize + (b.bitpos >> 3) as isize != 0 {
+        return oodle_err("tANS streams did not end where expected");
+    }
+
+    for (i, state) in states.iter().enumerate() {
+        if *state > 0xff {
+            return oodle_err("Invalid final tANS state");
+        }
+        output[dst_end + i] = *state as u8;
+    }
+
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::entropy::decode_bytes;
+    use super::super::tests::{MsbWriter, LsbWriter, entropy_block};
+
+    /// Encodes `symbols` in reverse: each step picks the state whose range of next states holds the current one
+    #[test]
+    fn known_vector() {
+        let mut table = MsbWriter::default();
+        table.write(0, 3);
+        table.write(0, 1);
+        table.write(2, 3);
+        table.write(6, 4);
+        for (symbol, delta) in [(b'x', 1), (b'y', 2), (b'z', 57)] {
+            table.write(symbol as u32, 8);
+            table.write(delta, 6);
+        }
+        table.write(b'w' as u32, 8);
+
+        let mut br = BitReader::new(&table.bytes, 0);
+        br.read_bits_no_refill(3);
+        let lut = init_lut(&decode_table(&mut br, 8).unwrap(), 8).unwrap();
+
+        let mut symbols: Vec<u8> = (0..120).map(|v| match v % 13 {
+            0 => b'x',
+            3 | 9 => b'y',
+            5 | 6 | 11 => b'z',
+            _ => b'w',
+        }).collect();
+        symbols.extend_from_slice(&[17, 0, 255, 4, 99]);
+
+        const ROUNDS: [(bool, usize); 10] = [
+            (true, 0), (true, 1), (true, 2), (true, 3),
+            (false, 4), (false, 0), (false, 1), (false, 2), (false, 3), (false, 4),
+        ];
+        let dst_end = symbols.len() - 5;
+        let mut states: Vec<u32> = symbols[dst_end..].iter().map(|v| *v as u32).collect();
+        let mut steps = vec![(true, 0, 0); dst_end];
+        for k in (0..dst_end).rev() {
+            let (forward, i) = ROUNDS[k % 10];
+            let next = states[i];
+            let state = lut.iter().position(|e| {
+                e.symbol == symbols[k] && e.w as u32 <= next && next - (e.w as u32) <= e.x
+            }).unwrap();
+            steps[k] = (forward, next - lut[state].w as u32, lut[state].bits_x as u32);
+            states[i] = state as u32;
+        }
+
+        let mut f = LsbWriter::default();
+        let mut b = LsbWriter::default();
+        for i in [0, 2, 4] {
+            f.write(states[i], 8);
+        }
+        for i in [1, 3] {
+            b.write(states[i], 8);
+        }
+        for (forward, value, n) in steps {
+            match forward {
+                true => f.write(value, n),
+                false => b.write(value, n),
+            }
+        }
+
+        let mut data = table.bytes;
+        data.extend(f.bytes);
+        data.extend(b.bytes.iter().rev());
+        let block = entropy_block(1, &data, symbols.len());
+        assert_eq!(decode_bytes(&block, symbols.len()).unwrap().0, symbols);
+
+        // Every truncation or corruption of the array fails or decodes to something, without panicking
+        for length in 0..block.len() {
+            assert!(decode_bytes(&block[..length], symbols.len()).is_err());
+        }
+        for i in 0..block.len() {
+            for v in [0x00, 0xff, block[i] ^ 0x01, block[i] ^ 0x80] {
+                let mut corrupted = block.clone();
+                corrupted[i] = v;
+                let _ = decode_bytes(&corrupted, symbols.len());
+            }
+        }
+    }
+}
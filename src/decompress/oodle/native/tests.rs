@@ -0,0 +1,457 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// Distributed under version 3 of the GNU General Public License, see the notice in mod.rs
+
+//! Streams are built by hand from the format, since there is no Oodle encoder to make them with
+
+use super::*;
+use super::entropy::decode_bytes;
+
+const KRAKEN: u8 = 6;
+const MERMAID: u8 = 10;
+const LEVIATHAN: u8 = 12;
+
+/// An MSB-first bit writer, as read by `BitReader`
+#[derive(Default)]
+pub(super) struct MsbWriter {
+    pub bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl MsbWriter {
+    pub fn write(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            if self.bits % 8 == 0 {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+}
+
+/// An LSB-first bit writer, as read by the Huffman and tANS streams
+#[derive(Default)]
+pub(super) struct LsbWriter {
+    pub bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl LsbWriter {
+    pub fn write(&mut self, value: u32, n: u32) {
+        for i in 0..n {
+            if self.bits % 8 == 0 {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+}
+
+/// An entropy coded array, with the 5 byte header that can also start a chunk
+pub(super) fn entropy_block(chunk_type: u8, data: &[u8], dst_size: usize) -> Vec<u8> {
+    let mut block = vec![(chunk_type << 4) | ((dst_size - 1) >> 14) as u8];
+    let bits = data.len() as u32 | (((dst_size - 1) & 0x3fff) as u32) << 18;
+    block.extend_from_slice(&bits.to_be_bytes());
+    block.extend_from_slice(data);
+    block
+}
+
+/// An array stored as it is, with the 3 byte header
+fn stored(data: &[u8]) -> Vec<u8> {
+    let mut block = (data.len() as u32).to_be_bytes()[1..].to_vec();
+    block.extend_from_slice(data);
+    block
+}
+
+/// An LZ chunk, with its header
+fn lz_chunk(mode: u32, data: &[u8]) -> Vec<u8> {
+    let mut chunk = (0x800000 | mode << 19 | data.len() as u32).to_be_bytes()[1..].to_vec();
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+/// A compressed block made up of `chunks`
+fn block(decoder: u8, chunks: &[Vec<u8>]) -> Vec<u8> {
+    let body = chunks.concat();
+    let mut block = vec![0x0c, decoder];
+    block.extend_from_slice(&((body.len() - 1) as u32).to_be_bytes()[1..]);
+    block.extend(body);
+    block
+}
+
+/// Two bit streams, the second of which is read backwards from the end
+fn split_streams(forward: MsbWriter, backward: MsbWriter) -> Vec<u8> {
+    let mut data = forward.bytes;
+    data.extend(backward.bytes.iter().rev());
+    data
+}
+
+/// Writes a match distance for `BitReader::read_distance`, returning its packed high bits
+fn write_distance(bits: &mut MsbWriter, distance: u32) -> u8 {
+    let v = distance + 248;
+    let n = 31 - (v >> 4).leading_zeros();
+    bits.write((v >> 4) - (1 << n), n);
+    (((n - 4) << 4) | (v & 0xf)) as u8
+}
+
+/// Writes a length for `BitReader::read_length`
+fn write_length(bits: &mut MsbWriter, length: u32) {
+    let v = length + 64;
+    let n = 32 - v.leading_zeros();
+    bits.write(0, n - 7);
+    bits.write(v, n);
+}
+
+/// Writes an Elias gamma coded number, as the count of long lengths is stored
+fn write_gamma(bits: &mut MsbWriter, value: u32) {
+    let n = 32 - value.leading_zeros();
+    bits.write(0, n - 1);
+    bits.write(value, n);
+}
+
+enum Op<'a> {
+    Literals(&'a [u8]),
+    Match(usize, usize),
+}
+
+/// The output of a run of LZ operations, with literals added to the byte at the last match distance in `delta` mode
+fn expand(first: &[u8], ops: &[Op], delta: bool) -> Vec<u8> {
+    let mut output = first.to_vec();
+    let mut last_distance = 8;
+    for op in ops {
+        match op {
+            Op::Literals(literals) => for literal in literals.iter() {
+                let v = match delta {
+                    true => literal.wrapping_add(output[output.len() - last_distance]),
+                    false => *literal,
+                };
+                output.push(v);
+            },
+            Op::Match(distance, length) => {
+                last_distance = *distance;
+                for _i in 0..*length {
+                    output.push(output[output.len() - distance]);
+                }
+            },
+        }
+    }
+    output
+}
+
+/// Checks that every truncation of a stream fails, and that corrupting any byte of it never panics
+fn check_malformed(stream: &[u8], size: usize) {
+    for length in 0..stream.len() {
+        assert!(decompress_native(size as u64, &stream[..length]).is_err(), "truncated to {}", length);
+    }
+    for i in 0..stream.len() {
+        for v in [0x00, 0xff, stream[i] ^ 0x01, stream[i] ^ 0x80, stream[i].wrapping_add(0x10)] {
+            let mut corrupted = stream.to_vec();
+            corrupted[i] = v;
+            let _ = decompress_native(size as u64, &corrupted);
+        }
+    }
+}
+
+#[test]
+fn stored_blocks() {
+    let data: Vec<u8> = (0..200).map(|v| (v * 7) as u8).collect();
+
+    // Flagged as uncompressed, so there is no size
+    let mut stream = vec![0x4c, KRAKEN];
+    stream.extend_from_slice(&data);
+    assert_eq!(decompress_native(200, &stream).unwrap(), data);
+    check_malformed(&stream, 200);
+
+    // A compressed block that is the size of its output
+    let mut stream = vec![0x0c, KRAKEN, 0x00, 0x00, 199];
+    stream.extend_from_slice(&data);
+    assert_eq!(decompress_native(200, &stream).unwrap(), data);
+    check_malformed(&stream, 200);
+}
+
+#[test]
+fn memset_blocks() {
+    // A full block and a short one, each filled with a single byte
+    let stream = [0x0c, KRAKEN, 0x07, 0xff, 0xff, 0xab, 0x0c, KRAKEN, 0x07, 0xff, 0xff, 0xcd];
+    let output = decompress_native(BLOCK_SIZE as u64 + 100, &stream).unwrap();
+    assert!(output[..BLOCK_SIZE].iter().all(|v| *v == 0xab));
+    assert!(output[BLOCK_SIZE..].iter().all(|v| *v == 0xcd));
+    check_malformed(&stream, BLOCK_SIZE + 100);
+}
+
+/// A Kraken chunk at the start of the output, with raw streams and a single new offset
+fn kraken_stream(mode: u32) -> (Vec<u8>, Vec<u8>) {
+    let first = b"abcdefgh";
+    let ops = [
+        Op::Literals(b"XY"),
+        Op::Match(8, 16),
+        Op::Match(10, 5),
+        Op::Match(10, 16),
+        Op::Literals(b"!"),
+    ];
+    let expected = expand(first, &ops, mode == 0);
+
+    // Literals, then the match length and which offset to use: a recent one, or the next new one
+    let commands = [2 | (14 << 2), (3 << 2) | (3 << 6), 14 << 2];
+    let mut forward = MsbWriter::default();
+    let mut backward = MsbWriter::default();
+    write_gamma(&mut backward, 1);
+    let packed_offset = write_distance(&mut forward, 10);
+
+    let mut chunk = first.to_vec();
+    chunk.extend(stored(b"XY!"));
+    chunk.extend(stored(&commands));
+    chunk.extend(stored(&[packed_offset]));
+    chunk.extend(stored(&[]));
+    chunk.extend(split_streams(forward, backward));
+
+    (block(KRAKEN, &[lz_chunk(mode, &chunk)]), expected)
+}
+
+#[test]
+fn kraken() {
+    for mode in 0..2 {
+        let (stream, expected) = kraken_stream(mode);
+        assert_eq!(decompress_native(expected.len() as u64, &stream).unwrap(), expected, "mode {}", mode);
+        check_malformed(&stream, expected.len());
+    }
+}
+
+#[test]
+fn multi_chunk_block() {
+    // The first chunk fills the first 128KB with 16 byte matches and trailing literals, the second fills the rest with one long match
+    let first = b"01234567";
+    let mut chunk = first.to_vec();
+    chunk.extend(stored(first));
+    chunk.extend(stored(&vec![14 << 2; (CHUNK_SIZE - 16) / 16]));
+    chunk.extend(stored(&[]));
+    chunk.extend(stored(&[]));
+    let mut backward = MsbWriter::default();
+    write_gamma(&mut backward, 1);
+    chunk.extend(split_streams(MsbWriter::default(), backward));
+    let first_chunk = lz_chunk(1, &chunk);
+
+    // A match of 15 takes its length from the length stream, where 255 means a long length from the extra bits
+    let length = 0x10000;
+    let mut chunk = stored(&[]);
+    chunk.extend(stored(&[15 << 2]));
+    chunk.extend(stored(&[]));
+    chunk.extend(stored(&[255]));
+    let mut forward = MsbWriter::default();
+    let mut backward = MsbWriter::default();
+    write_gamma(&mut backward, 2);
+    write_length(&mut forward, length - 14 - 255 - 3);
+    chunk.extend(split_streams(forward, backward));
+    let second_chunk = lz_chunk(1, &chunk);
+
+    let size = CHUNK_SIZE + length as usize;
+    let stream = block(KRAKEN, &[first_chunk, second_chunk]);
+    let output = decompress_native(size as u64, &stream).unwrap();
+    let expected: Vec<u8> = first.iter().cycle().take(size).copied().collect();
+    assert_eq!(output, expected);
+
+    for length in [0, 2, 5, stream.len() / 2, stream.len() - 1] {
+        assert!(decompress_native(size as u64, &stream[..length]).is_err());
+    }
+}
+
+#[test]
+fn mermaid() {
+    let first = b"abcdefgh";
+    let literals: Vec<u8> = (0..70).map(|v| b'A' + (v % 26) as u8).collect();
+    let ops = [
+        Op::Literals(&literals[0..3]),
+        Op::Match(8, 15),
+        Op::Literals(&literals[3..4]),
+        Op::Match(12, 10),
+        Op::Literals(&literals[4..68]),
+        Op::Match(3, 100),
+        Op::Match(8 + 3 + 15 + 1 + 10 + 64 + 100, 10),
+        Op::Literals(&literals[68..70]),
+    ];
+
+    // Short runs at the recent or a new near offset, a long literal run, a long match, and a far match
+    let commands = [0x80 | (15 << 3) | 3, (10 << 3) | 1, 0, 1, 5];
+    let mut chunk = first.to_vec();
+    chunk.extend(stored(&literals));
+    chunk.extend(stored(&commands));
+    chunk.extend_from_slice(&2u16.to_le_bytes());
+    chunk.extend_from_slice(&12u16.to_le_bytes());
+    chunk.extend_from_slice(&3u16.to_le_bytes());
+    chunk.extend_from_slice(&(1u32 << 12).to_le_bytes()[..3]);
+    chunk.extend_from_slice(&[0, 0, 0]);
+    chunk.extend_from_slice(&[0, 9]);
+
+    for mode in 0..2 {
+        let expected = expand(first, &ops, mode == 0);
+        let stream = block(MERMAID, &[lz_chunk(mode, &chunk)]);
+        assert_eq!(decompress_native(expected.len() as u64, &stream).unwrap(), expected, "mode {}", mode);
+        check_malformed(&stream, expected.len());
+    }
+}
+
+#[test]
+fn leviathan() {
+    let first = b"abcdefgh";
+    let ops = [
+        Op::Literals(b"XY"),
+        Op::Match(8, 8),
+        Op::Literals(b"Z"),
+        Op::Match(9, 5),
+        Op::Match(9, 7),
+        Op::Match(9, 7),
+        Op::Match(9, 7),
+        Op::Match(9, 7),
+        Op::Literals(b"!"),
+    ];
+
+    // Offset index, then the literal count, then the match length
+    let commands = [(2 << 3) | 6, (7 << 5) | (1 << 3) | 3, 5, 5, 5, 5];
+    let mut forward = MsbWriter::default();
+    let mut backward = MsbWriter::default();
+    write_gamma(&mut backward, 1);
+    let packed_offset = write_distance(&mut forward, 9);
+
+    let mut chunk = first.to_vec();
+    chunk.extend(stored(&[packed_offset]));
+    chunk.extend(stored(&[]));
+    chunk.extend(stored(b"XYZ!"));
+    chunk.extend(stored(&commands));
+    chunk.extend(split_streams(forward, backward));
+
+    for mode in 0..2 {
+        let expected = expand(first, &ops, mode == 0);
+        let stream = block(LEVIATHAN, &[lz_chunk(mode, &chunk)]);
+        assert_eq!(decompress_native(expected.len() as u64, &stream).unwrap(), expected, "mode {}", mode);
+        check_malformed(&stream, expected.len());
+    }
+}
+
+/// An RLE array: literals are read from the front, and commands from the back
+fn rle_block() -> (Vec<u8>, Vec<u8>) {
+    let commands = [0x00, b'q', b'x', b'y', b'z', b'a', b'b', 0xfd, 0xfc, 0x01];
+    let expected = [&b"xyz"[..], &[b'q'; 15], b"ab", &[b'q'; 15]].concat();
+    (entropy_block(3, &commands, expected.len()), expected)
+}
+
+#[test]
+fn rle() {
+    let (block, expected) = rle_block();
+    let (output, used) = decode_bytes(&block, expected.len()).unwrap();
+    assert_eq!(output, expected);
+    assert_eq!(used, block.len());
+
+    // A chunk that is only an entropy coded array has no LZ header
+    let stream = self::block(KRAKEN, &[block]);
+    assert_eq!(decompress_native(expected.len() as u64, &stream).unwrap(), expected);
+    check_malformed(&stream, expected.len());
+}
+
+#[test]
+fn recursive() {
+    // Arrays of any type, one after the other
+    let (rle, mut expected) = rle_block();
+    let mut data = vec![2];
+    data.extend(rle);
+    data.extend(stored(b"tail"));
+    expected.extend_from_slice(b"tail");
+    let block = entropy_block(5, &data, expected.len());
+    assert_eq!(decode_bytes(&block, expected.len()).unwrap().0, expected);
+
+    let stream = self::block(KRAKEN, &[block]);
+    assert_eq!(decompress_native(expected.len() as u64, &stream).unwrap(), expected);
+    check_malformed(&stream, expected.len());
+}
+
+/// A Huffman code of 4 symbols, with lengths 1, 2, 3 and 3, as a sparse code length table
+fn huffman_table() -> (MsbWriter, [(u8, u32, u32); 4]) {
+    let codes = [(b'a', 0b0, 1), (b'b', 0b10, 2), (b'c', 0b110, 3), (b'd', 0b111, 3)];
+    let mut table = MsbWriter::default();
+    table.write(0, 2);
+    table.write(codes.len() as u32, 8);
+    table.write(2, 3);
+    for (symbol, _, length) in codes.iter() {
+        table.write(*symbol as u32, 8);
+        table.write(length - 1, 2);
+    }
+    (table, codes)
+}
+
+/// Splits symbols between three streams: forwards from the start, backwards from the end, and forwards from the middle
+fn huffman_streams(symbols: &[u8], codes: &[(u8, u32, u32)]) -> Vec<u8> {
+    let mut streams = [LsbWriter::default(), LsbWriter::default(), LsbWriter::default()];
+    for (i, symbol) in symbols.iter().enumerate() {
+        let (_, code, length) = codes.iter().find(|v| v.0 == *symbol).unwrap();
+        for bit in (0..*length).rev() {
+            streams[i % 3].write((code >> bit) & 1, 1);
+        }
+    }
+    let [a, b, c] = streams;
+    let mut data = (a.bytes.len() as u16).to_le_bytes().to_vec();
+    data.extend(a.bytes);
+    data.extend(c.bytes);
+    data.extend(b.bytes.iter().rev());
+    data
+}
+
+#[test]
+fn huffman() {
+    let symbols: Vec<u8> = (0..97).map(|v| match v % 7 {
+        0 => b'd',
+        1 | 4 => b'b',
+        3 => b'c',
+        _ => b'a',
+    }).collect();
+    let (table, codes) = huffman_table();
+
+    let mut data = table.bytes.clone();
+    data.extend(huffman_streams(&symbols, &codes));
+    let block = entropy_block(2, &data, symbols.len());
+    assert_eq!(decode_bytes(&block, symbols.len()).unwrap().0, symbols);
+    let stream = self::block(KRAKEN, &[block]);
+    assert_eq!(decompress_native(symbols.len() as u64, &stream).unwrap(), symbols);
+    check_malformed(&stream, symbols.len());
+
+    // The 4-way form is two 3-way halves, with the size of the first half before them
+    let half = (symbols.len() + 1) / 2;
+    let left = huffman_streams(&symbols[..half], &codes);
+    let mut data = table.bytes;
+    data.extend_from_slice(&(left.len() as u32).to_le_bytes()[..3]);
+    data.extend(left);
+    data.extend(huffman_streams(&symbols[half..], &codes));
+    let block = entropy_block(4, &data, symbols.len());
+    assert_eq!(decode_bytes(&block, symbols.len()).unwrap().0, symbols);
+    let stream = self::block(KRAKEN, &[block]);
+    assert_eq!(decompress_native(symbols.len() as u64, &stream).unwrap(), symbols);
+    check_malformed(&stream, symbols.len());
+}
+
+#[test]
+fn malformed_headers() {
+    let (stream, expected) = kraken_stream(1);
+    let size = expected.len() as u64;
+
+    let bad_stream = |i: usize, v: u8| {
+        let mut stream = stream.clone();
+        stream[i] = v;
+        decompress_native(size, &stream)
+    };
+    // Not a block header, then LZNA, BitKnit and an unknown decoder
+    assert!(bad_stream(0, 0x0d).is_err());
+    assert!(bad_stream(1, 5).is_err());
+    assert!(bad_stream(1, 11).is_err());
+    assert!(bad_stream(1, 0x7f).is_err());
+    // A chunk mode that doesn't exist
+    assert!(bad_stream(5, 0xa0).is_err());
+
+    assert!(decompress_native(size + 1, &stream).is_err());
+    let mut trailing = stream.clone();
+    trailing.push(0);
+    assert!(decompress_native(size, &trailing).is_err());
+    assert!(decompress_native(16, &[]).is_err());
+}
@@ -1,15 +1,20 @@
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Cursor};
 use std::sync::Arc;
-use block_modes::{BlockMode, Ecb, block_padding::ZeroPadding};
-use aes::Aes256;
 use flate2::read::ZlibDecoder;
-use crate::assets::{FMappedName, FGuid, FPackageObjectIndex, Newable, ReaderCursor, read_string, read_short_string, read_tarray, ParserResult, ParserError};
-use crate::decompress::oodle;
+use rayon::prelude::*;
+use crate::archives::{decode_key, decrypt_buffer};
+use crate::keys::is_mount_point;
+use crate::assets::{FMappedName, FGuid, FPackageObjectIndex, Newable, ReaderCursor, read_string, read_short_string, read_tarray, ParserResult, ParserError, ParserType};
+use crate::cache::BlockCache;
+use crate::decompress;
+use crate::source::{ContainerSource, SourceMode};
 
 const MAX_INT: u32 = 4294967295;
+const TOC_MAGIC: &[u8; 16] = b"-==--==--==--==-";
 
 #[derive(Debug)]
 struct FIoDirectoryIndexEntry {
@@ -65,61 +70,89 @@ impl FIoDirectoryIndexResource {
         }
     }
 
-    pub fn get_files(&self, size: usize) -> Vec<String> {
+    pub fn get_files(&self, size: usize) -> ParserResult<Vec<String>> {
         let mut files = vec!["".to_owned(); size];
 
-        let dir = &self.directories[0];
-        self.process_directory(dir, &mut files, "".to_owned());
+        let dir = match self.directories.first() {
+            Some(dir) => dir,
+            None => return Ok(files),
+        };
+        self.process_directory(dir, &mut files, "".to_owned())?;
 
-        files
+        Ok(files)
     }
 
-    fn process_directory(&self, dir: &FIoDirectoryIndexEntry, filenames: &mut Vec<String>, mut dirpath: String) {
+    fn get_string(&self, idx: u32) -> ParserResult<&str> {
+        match self.string_table.get(idx as usize) {
+            Some(name) => Ok(name),
+            None => Err(ParserError::typed(format!("Directory index name out of range: {}", idx), ParserType::Malformed)),
+        }
+    }
+
+    fn process_directory(&self, dir: &FIoDirectoryIndexEntry, filenames: &mut Vec<String>, mut dirpath: String) -> ParserResult<()> {
         if dir.name != MAX_INT {
-            dirpath += &self.string_table[dir.name as usize];
+            dirpath += self.get_string(dir.name)?;
             dirpath += "/";
         }
 
         if dir.first_child != MAX_INT {
-            let children = self.get_directories(&self.directories[dir.first_child as usize]);
+            let children = self.get_directories(dir.first_child)?;
             for child in children {
-                self.process_directory(&child, filenames, dirpath.clone());
+                self.process_directory(child, filenames, dirpath.clone())?;
             }
         }
 
         if dir.first_file != MAX_INT {
-            let files = self.get_file_entries(&self.files[dir.first_file as usize]);
+            let files = self.get_file_entries(dir.first_file)?;
             for file in files {
                 if file.name != MAX_INT && file.user_data != MAX_INT {
-                    let filepath = dirpath.clone() + &self.string_table[file.name as usize];
-                    filenames[file.user_data as usize] = filepath;
+                    let filepath = dirpath.clone() + self.get_string(file.name)?;
+                    match filenames.get_mut(file.user_data as usize) {
+                        Some(filename) => *filename = filepath,
+                        None => return Err(ParserError::typed(format!("File has no matching chunk: {}", filepath), ParserType::Malformed)),
+                    }
                 }
             }
         }
+
+        Ok(())
     }
 
-    fn get_directories<'a>(&'a self, dir: &'a FIoDirectoryIndexEntry) -> Vec<&'a FIoDirectoryIndexEntry> {
+    fn get_directories(&self, first: u32) -> ParserResult<Vec<&FIoDirectoryIndexEntry>> {
         let mut dirs = Vec::new();
-        let mut active = dir;
-        dirs.push(active);
-        while active.next_sibling != MAX_INT {
-            active = &self.directories[active.next_sibling as usize];
+        let mut next = first;
+        while next != MAX_INT {
+            let active = match self.directories.get(next as usize) {
+                Some(dir) => dir,
+                None => return Err(ParserError::typed(format!("Directory index entry out of range: {}", next), ParserType::Malformed)),
+            };
+            // A corrupt (or badly decrypted) index could otherwise loop forever
+            if dirs.len() >= self.directories.len() {
+                return Err(ParserError::typed("Directory index contains a loop".to_owned(), ParserType::Malformed));
+            }
             dirs.push(active);
+            next = active.next_sibling;
         }
 
-        dirs
+        Ok(dirs)
     }
 
-    fn get_file_entries<'a>(&'a self, file: &'a FIoFileIndexEntry) -> Vec<&'a FIoFileIndexEntry> {
+    fn get_file_entries(&self, first: u32) -> ParserResult<Vec<&FIoFileIndexEntry>> {
         let mut files = Vec::new();
-        let mut active = file;
-        files.push(active);
-        while active.next_file_entry != MAX_INT {
-            active = &self.files[active.next_file_entry as usize];
+        let mut next = first;
+        while next != MAX_INT {
+            let active = match self.files.get(next as usize) {
+                Some(file) => file,
+                None => return Err(ParserError::typed(format!("File index entry out of range: {}", next), ParserType::Malformed)),
+            };
+            if files.len() >= self.files.len() {
+                return Err(ParserError::typed("File index contains a loop".to_owned(), ParserType::Malformed));
+            }
             files.push(active);
+            next = active.next_file_entry;
         }
 
-        files
+        Ok(files)
     }
 }
 
@@ -134,9 +167,19 @@ impl Newable for FIoDirectoryIndexResource {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum EIoStoreTocVersion {
+    Invalid,
+    Initial,
+    DirectoryIndex,
+    PartitionSize,
+    PerfectHash,
+    PerfectHashWithOverflow,
+}
+
 #[derive(Debug, Clone)]
 pub struct FIoStoreTocHeader {
-    version: u32,
+    version: EIoStoreTocVersion,
     header_size: u32,
     entry_count: u32,
     compressed_block_entry_count: u32,
@@ -145,18 +188,21 @@ pub struct FIoStoreTocHeader {
     compression_method_name_length: u32,
     compression_block_size: u32,
     directory_index_size: u32,
-    something: u32,
+    partition_count: u32,
     container_id: u64,
     encryption_key_guid: FGuid,
-    container_flags: u32,
+    container_flags: u8,
+    perfect_hash_seeds_count: u32,
+    partition_size: u64,
+    chunks_without_perfect_hash_count: u32,
 }
 
 impl FIoStoreTocHeader {
-    fn is_encrypted(&self) -> bool {
+    pub fn is_encrypted(&self) -> bool {
         self.container_flags & (1 << 1) != 0
     }
 
-    fn is_signed(&self) -> bool {
+    pub fn is_signed(&self) -> bool {
         self.container_flags & (1 << 2) != 0
     }
 
@@ -167,37 +213,105 @@ impl FIoStoreTocHeader {
     pub fn get_key_guid(&self) -> &FGuid {
         &self.encryption_key_guid
     }
+
+    pub fn get_directory_index_size(&self) -> u32 {
+        self.directory_index_size
+    }
+
+    pub fn get_version(&self) -> EIoStoreTocVersion {
+        self.version
+    }
+
+    pub fn get_partition_count(&self) -> u32 {
+        self.partition_count
+    }
+
+    /// Maps an offset in the container onto the partition (`.ucas` file) holding it, and the offset inside that partition
+    fn get_partition_offset(&self, offset: u64) -> (usize, u64) {
+        ((offset / self.partition_size) as usize, offset % self.partition_size)
+    }
 }
 
 impl Newable for FIoStoreTocHeader {
     fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
         let mut magic_img = [0u8; 16];
         reader.read_exact(&mut magic_img)?;
-        let result = Self {
-            version: reader.read_u32::<LittleEndian>()?,
-            header_size: reader.read_u32::<LittleEndian>()?,
-            entry_count: reader.read_u32::<LittleEndian>()?,
-            compressed_block_entry_count: reader.read_u32::<LittleEndian>()?,
-            compressed_block_entry_size: reader.read_u32::<LittleEndian>()?,
-            compression_method_name_count: reader.read_u32::<LittleEndian>()?,
-            compression_method_name_length: reader.read_u32::<LittleEndian>()?,
-            compression_block_size: reader.read_u32::<LittleEndian>()?,
-            directory_index_size: reader.read_u32::<LittleEndian>()?,
-            something: reader.read_u32::<LittleEndian>()?,
-            container_id: reader.read_u64::<LittleEndian>()?,
-            encryption_key_guid: FGuid::new(reader)?,
-            container_flags: reader.read_u32::<LittleEndian>()?,
-        };
+        if &magic_img != TOC_MAGIC {
+            return Err(ParserError::typed("Invalid utoc file".to_owned(), ParserType::Malformed));
+        }
 
-        let mut padding = [0u8; 60];
+        let version = match reader.read_u8()? {
+            1 => EIoStoreTocVersion::Initial,
+            2 => EIoStoreTocVersion::DirectoryIndex,
+            3 => EIoStoreTocVersion::PartitionSize,
+            4 => EIoStoreTocVersion::PerfectHash,
+            5 => EIoStoreTocVersion::PerfectHashWithOverflow,
+            v => return Err(ParserError::new(format!("Unsupported utoc version: {}", v))),
+        };
+        let _reserved0 = reader.read_u8()?;
+        let _reserved1 = reader.read_u16::<LittleEndian>()?;
+
+        let header_size = reader.read_u32::<LittleEndian>()?;
+        let entry_count = reader.read_u32::<LittleEndian>()?;
+        let compressed_block_entry_count = reader.read_u32::<LittleEndian>()?;
+        let compressed_block_entry_size = reader.read_u32::<LittleEndian>()?;
+        let compression_method_name_count = reader.read_u32::<LittleEndian>()?;
+        let compression_method_name_length = reader.read_u32::<LittleEndian>()?;
+        let compression_block_size = reader.read_u32::<LittleEndian>()?;
+        let directory_index_size = reader.read_u32::<LittleEndian>()?;
+        let partition_count = reader.read_u32::<LittleEndian>()?;
+        let container_id = reader.read_u64::<LittleEndian>()?;
+        let encryption_key_guid = FGuid::new(reader)?;
+        let container_flags = reader.read_u8()?;
+        let _reserved3 = reader.read_u8()?;
+        let _reserved4 = reader.read_u16::<LittleEndian>()?;
+        let perfect_hash_seeds_count = reader.read_u32::<LittleEndian>()?;
+        let partition_size = reader.read_u64::<LittleEndian>()?;
+        let chunks_without_perfect_hash_count = reader.read_u32::<LittleEndian>()?;
+
+        let mut padding = [0u8; 44];
         reader.read_exact(&mut padding)?;
 
-        Ok(result)
+        // Fields added in later versions are reserved (and zeroed) in earlier ones
+        Ok(Self {
+            version,
+            header_size,
+            entry_count,
+            compressed_block_entry_count,
+            compressed_block_entry_size,
+            compression_method_name_count,
+            compression_method_name_length,
+            compression_block_size,
+            directory_index_size: match version >= EIoStoreTocVersion::DirectoryIndex {
+                true => directory_index_size,
+                false => 0,
+            },
+            partition_count: match version >= EIoStoreTocVersion::PartitionSize {
+                true => std::cmp::max(partition_count, 1),
+                false => 1,
+            },
+            container_id,
+            encryption_key_guid,
+            container_flags,
+            perfect_hash_seeds_count: match version >= EIoStoreTocVersion::PerfectHash {
+                true => perfect_hash_seeds_count,
+                false => 0,
+            },
+            partition_size: match version >= EIoStoreTocVersion::PartitionSize && partition_size > 0 {
+                true => partition_size,
+                false => u64::MAX,
+            },
+            chunks_without_perfect_hash_count: match version >= EIoStoreTocVersion::PerfectHashWithOverflow {
+                true => chunks_without_perfect_hash_count,
+                false => 0,
+            },
+        })
     }
 }
 
-#[derive(Debug)]
-enum EIoChunkType {
+/// The chunk types of both UE4 and UE5 containers, which number them differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EIoChunkType {
     Invalid,
     InstallManifest,
     ExportBundleData,
@@ -209,6 +323,54 @@ enum EIoChunkType {
     LoaderGlobalNames,
     LoaderGlobalNameHashes,
     ContainerHeader,
+    // UE5 only
+    ScriptObjects,
+    ExternalFile,
+    ShaderCodeLibrary,
+    ShaderCode,
+    PackageStoreEntry,
+    DerivedData,
+    EditorDerivedData,
+    PackageResource,
+}
+
+impl EIoChunkType {
+    fn from_ue4(v: u8) -> Option<Self> {
+        Some(match v {
+            0 => EIoChunkType::Invalid,
+            1 => EIoChunkType::InstallManifest,
+            2 => EIoChunkType::ExportBundleData,
+            3 => EIoChunkType::BulkData,
+            4 => EIoChunkType::OptionalBulkData,
+            5 => EIoChunkType::MemoryMappedBulkData,
+            6 => EIoChunkType::LoaderGlobalMeta,
+            7 => EIoChunkType::LoaderInitialLoadMeta,
+            8 => EIoChunkType::LoaderGlobalNames,
+            9 => EIoChunkType::LoaderGlobalNameHashes,
+            10 => EIoChunkType::ContainerHeader,
+            _ => return None,
+        })
+    }
+
+    fn from_ue5(v: u8) -> Option<Self> {
+        Some(match v {
+            0 => EIoChunkType::Invalid,
+            1 => EIoChunkType::ExportBundleData,
+            2 => EIoChunkType::BulkData,
+            3 => EIoChunkType::OptionalBulkData,
+            4 => EIoChunkType::MemoryMappedBulkData,
+            5 => EIoChunkType::ScriptObjects,
+            6 => EIoChunkType::ContainerHeader,
+            7 => EIoChunkType::ExternalFile,
+            8 => EIoChunkType::ShaderCodeLibrary,
+            9 => EIoChunkType::ShaderCode,
+            10 => EIoChunkType::PackageStoreEntry,
+            11 => EIoChunkType::DerivedData,
+            12 => EIoChunkType::EditorDerivedData,
+            13 => EIoChunkType::PackageResource,
+            _ => return None,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -222,29 +384,30 @@ impl FIoChunkId {
     pub fn get_id(&self) -> u64 {
         self.id
     }
-}
 
-impl Newable for FIoChunkId {
-    fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
+    pub fn get_index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn get_type(&self) -> EIoChunkType {
+        self.chunk_type
+    }
+
+    /// Reads a chunk id from a container of the given version. UE5 containers, from `PerfectHash` on, renumber the chunk types.
+    pub fn new(reader: &mut ReaderCursor, version: EIoStoreTocVersion) -> ParserResult<Self> {
         let id = reader.read_u64::<LittleEndian>()?;
         let index = reader.read_u16::<LittleEndian>()? as u32;
         let _zero = reader.read_u8()?;
 
-        let chunk_type = match reader.read_u8()? {
-            0 => EIoChunkType::Invalid,
-            1 => EIoChunkType::InstallManifest,
-            2 => EIoChunkType::ExportBundleData,
-            3 => EIoChunkType::BulkData,
-            4 => EIoChunkType::OptionalBulkData,
-            5 => EIoChunkType::MemoryMappedBulkData,
-            6 => EIoChunkType::LoaderGlobalMeta,
-            7 => EIoChunkType::LoaderInitialLoadMeta,
-            8 => EIoChunkType::LoaderGlobalNames,
-            9 => EIoChunkType::LoaderGlobalNameHashes,
-            10 => EIoChunkType::ContainerHeader,
-            _ => panic!("Unknown Chunk ID"),
+        let raw_type = reader.read_u8()?;
+        let chunk_type = match version >= EIoStoreTocVersion::PerfectHash {
+            true => EIoChunkType::from_ue5(raw_type),
+            false => EIoChunkType::from_ue4(raw_type),
+        };
+        let chunk_type = match chunk_type {
+            Some(chunk_type) => chunk_type,
+            None => return Err(ParserError::typed(format!("Unknown chunk type: {}", raw_type), ParserType::Malformed)),
         };
-        
 
         Ok(Self {
             id, index, chunk_type,
@@ -337,36 +500,61 @@ pub fn align_value(x: u32, a: u32) -> u32 {
     if r != 0 { x + (a - r) } else { x }
 }
 
-fn get_chunk(file: &mut File, chunk: &FIoStoreTocCompressedBlockEntry, header: &FIoStoreTocHeader, key: &Option<Vec<u8>>) -> ParserResult<Vec<u8>> {
-    file.seek(SeekFrom::Start(chunk.offset))?;
+fn get_partition_path(path: &str, partition: usize) -> String {
+    match partition {
+        0 => path.to_owned() + ".ucas",
+        _ => format!("{}_s{}.ucas", path, partition),
+    }
+}
+
+fn read_raw_chunk(partitions: &[ContainerSource], chunk: &FIoStoreTocCompressedBlockEntry, header: &FIoStoreTocHeader) -> ParserResult<Vec<u8>> {
+    let (partition, offset) = header.get_partition_offset(chunk.offset);
+    let file = match partitions.get(partition) {
+        Some(file) => file,
+        None => return Err(ParserError::typed(format!("Block is outside of partitions: {}", chunk.offset), ParserType::Malformed)),
+    };
 
     let chunk_size = align_value(chunk.compressed_size, 16);
+    file.read_vec(offset, chunk_size as usize)
+}
 
-    let mut buf = vec![0u8; chunk_size as usize];
-    file.read_exact(&mut buf)?;
+fn get_chunk(partitions: &[ContainerSource], chunk: &FIoStoreTocCompressedBlockEntry, data: &ReaderData) -> ParserResult<Vec<u8>> {
+    let buf = read_raw_chunk(partitions, chunk, &data.header)?;
+    decode_chunk(buf, chunk, data)
+}
 
-    if header.is_encrypted() {
-        let hex_key = match key {
+/// Decrypts and decompresses a block read with `read_raw_chunk`
+fn decode_chunk(mut buf: Vec<u8>, chunk: &FIoStoreTocCompressedBlockEntry, data: &ReaderData) -> ParserResult<Vec<u8>> {
+    if data.header.is_encrypted() {
+        let hex_key = match &data.key {
             Some(k) => k,
-            None => return Err(ParserError::new(format!("Key not specified"))),
+            None => return Err(ParserError::typed("Key not specified".to_owned(), ParserType::Decryption)),
         };
 
-        let decrypt = Ecb::<Aes256, ZeroPadding>::new_from_slices(&hex_key, Default::default()).unwrap();
-        decrypt.decrypt(&mut buf).unwrap();
+        decrypt_buffer(hex_key, &mut buf)?;
     }
 
+    // Stored blocks are padded to the AES block size, and the padding isn't part of the data
     if chunk.compression_method == 0 {
+        buf.truncate(chunk.size as usize);
         return Ok(buf);
     }
 
-    Ok(oodle::decompress_stream(chunk.size as u64, &buf).unwrap())
+    // Method 0 is no compression, so the name table starts at 1
+    let method = match data.compression_methods.get(chunk.compression_method as usize - 1) {
+        Some(method) => method,
+        None => return Err(ParserError::typed(format!("Unknown compression method index: {}", chunk.compression_method), ParserType::Decompression)),
+    };
+    decompress::decompress(method, &buf, chunk.size as usize)
 }
 
 pub struct ReaderData {
     compressed_blocks: Vec<FIoStoreTocCompressedBlockEntry>,
     compression_methods: Vec<String>,
+    block_hashes: Vec<[u8; 20]>,
     header: FIoStoreTocHeader,
     key: Option<Vec<u8>>,
+    cache_id: u64,
 }
 
 impl ReaderData {
@@ -379,47 +567,215 @@ impl ReaderData {
     }
 }
 
-struct UcasReader {
+/// UcasStore holds the `.ucas` partitions of a container, and decodes blocks from them through `&self`
+///
+/// It can be shared between threads, with any number of `UcasReader`s reading from it at once.
+pub struct UcasStore {
+    path: String,
     data: Arc<ReaderData>,
+    cache: Arc<BlockCache>,
+    partitions: Vec<ContainerSource>,
+    total_size: u64,
+}
+
+impl UcasStore {
+    fn new(path: &str, data: Arc<ReaderData>, cache: Arc<BlockCache>, mode: SourceMode) -> ParserResult<Self> {
+        let mut partitions = Vec::new();
+        for partition in 0..data.header.partition_count {
+            partitions.push(ContainerSource::open(&get_partition_path(path, partition as usize), mode)?);
+        }
+
+        // Chunks start on a block boundary, so offsets are virtual and only the last block can be short
+        let total_size = match data.compressed_blocks.last() {
+            Some(last) => (data.compressed_blocks.len() as u64 - 1) * data.header.compression_block_size as u64 + last.size as u64,
+            None => 0,
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            data,
+            cache,
+            partitions,
+            total_size,
+        })
+    }
+
+    /// The size of the container's virtual address space, which every chunk offset falls inside
+    ///
+    /// This is larger than the decompressed data, since blocks that end a chunk are usually short.
+    pub fn get_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Gets a decoded block, from the cache if it has been read recently
+    fn load_block(&self, idx: usize) -> ParserResult<Arc<Vec<u8>>> {
+        if let Some(block) = self.cache.get(self.data.cache_id, idx) {
+            return Ok(block);
+        }
+
+        let chunk = match self.data.compressed_blocks.get(idx) {
+            Some(chunk) => chunk,
+            None => return Err(ParserError::new(format!("Block index out of range: {}", idx))),
+        };
+        let block = get_chunk(&self.partitions, chunk, &self.data)
+            .map_err(|v| ParserError::add(v, format!("Block {} of {}", idx, self.path)))?;
+        let block = Arc::new(block);
+        self.cache.insert(self.data.cache_id, idx, Arc::clone(&block));
+
+        Ok(block)
+    }
+
+    /// Gets a run of decoded blocks, decompressing any that aren't cached in parallel
+    fn load_blocks(&self, first: usize, last: usize) -> ParserResult<Vec<Arc<Vec<u8>>>> {
+        let mut blocks: Vec<Option<Arc<Vec<u8>>>> = (first..=last).map(|v| self.cache.get(self.data.cache_id, v)).collect();
+
+        let mut raw_blocks = Vec::new();
+        for (i, block) in blocks.iter().enumerate() {
+            if block.is_some() {
+                continue;
+            }
+            let chunk = match self.data.compressed_blocks.get(first + i) {
+                Some(chunk) => chunk,
+                None => return Err(ParserError::new(format!("Block index out of range: {}", first + i))),
+            };
+            let raw_block = read_raw_chunk(&self.partitions, chunk, &self.data.header)
+                .map_err(|v| ParserError::add(v, format!("Block {} of {}", first + i, self.path)))?;
+            raw_blocks.push((i, raw_block));
+        }
+
+        let data = &self.data;
+        let path = &self.path;
+        let decoded: Vec<ParserResult<(usize, Vec<u8>)>> = raw_blocks.into_par_iter().map(|(i, buf)| {
+            let block = decode_chunk(buf, &data.compressed_blocks[first + i], data)
+                .map_err(|v| ParserError::add(v, format!("Block {} of {}", first + i, path)))?;
+            Ok((i, block))
+        }).collect();
+
+        for result in decoded {
+            let (i, block) = result?;
+            let block = Arc::new(block);
+            self.cache.insert(self.data.cache_id, first + i, Arc::clone(&block));
+            blocks[i] = Some(block);
+        }
+
+        Ok(blocks.into_iter().flatten().collect())
+    }
+
+    /// Fills `buf` with the decompressed bytes starting at the virtual offset `offset`
+    ///
+    /// Each block starts at its index times the block size. Any gap after a short block reads as zeros.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> ParserResult<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let end = offset + buf.len() as u64;
+        if end > self.total_size {
+            return Err(ParserError::new(format!("Read past end of container: {} + {}", offset, buf.len())));
+        }
+
+        let block_size = self.data.header.compression_block_size as u64;
+        let first = (offset / block_size) as usize;
+        let last = ((end - 1) / block_size) as usize;
+        let blocks = self.load_blocks(first, last)?;
+
+        for (i, block) in blocks.iter().enumerate() {
+            let block_start = (first + i) as u64 * block_size;
+            let start = std::cmp::max(offset, block_start);
+            let stop = std::cmp::min(end, block_start + block_size);
+            let available = std::cmp::min(stop, block_start + block.len() as u64);
+
+            let target = &mut buf[((start - offset) as usize)..((stop - offset) as usize)];
+            let copied = available.saturating_sub(start) as usize;
+            if copied > 0 {
+                let block_offset = (start - block_start) as usize;
+                target[..copied].copy_from_slice(&block[block_offset..(block_offset + copied)]);
+            }
+            target[copied..].fill(0);
+        }
+
+        Ok(())
+    }
+}
+
+/// UcasReader is a `Read` + `Seek` cursor over the decompressed contents of a `UcasStore`
+pub struct UcasReader {
+    store: Arc<UcasStore>,
     current_chunk: usize,
-    current_chunk_data: Vec<u8>,
+    current_chunk_data: Arc<Vec<u8>>,
     current_offset: usize,
     current_total_offset: u64,
-    total_size: u64,
-    handle: File,
 }
 
 impl UcasReader {
-    fn new(path: &str, data: Arc<ReaderData>) -> ParserResult<Self> {
-        let ucas_path = path.to_owned() + ".ucas";
-        let mut file = File::open(ucas_path)?;
-
-        let total_size = data.compressed_blocks.iter().fold(0, |acc, v| acc + v.size as u64);
+    pub fn new(store: Arc<UcasStore>) -> ParserResult<Self> {
+        let current_chunk_data = match store.data.compressed_blocks.is_empty() {
+            true => Arc::new(Vec::new()),
+            false => store.load_block(0)?,
+        };
 
-        let first_chunk = get_chunk(&mut file, &data.compressed_blocks[0], &data.header, &data.key)?;
         Ok(Self {
-            data,
+            store,
             current_chunk: 0,
             current_offset: 0,
-            handle: file,
-            current_chunk_data: first_chunk,
+            current_chunk_data,
             current_total_offset: 0,
-            total_size: total_size,
         })
     }
+
+    /// Reads into a buffer that spans several blocks, decoding all of them up front
+    fn read_blocks(&mut self, buf: &mut [u8]) -> ParserResult<usize> {
+        let block_size = self.store.data.header.compression_block_size as u64;
+        let start = self.current_total_offset;
+        let end = std::cmp::min(start + buf.len() as u64, self.store.total_size);
+        if end <= start {
+            return Ok(0);
+        }
+
+        let length = (end - start) as usize;
+        self.store.read_at(start, &mut buf[..length])?;
+
+        let last = ((end - 1) / block_size) as usize;
+        if last != self.current_chunk {
+            self.current_chunk_data = self.store.load_block(last)?;
+            self.current_chunk = last;
+        }
+        self.current_total_offset = end;
+        self.current_offset = (end - (last as u64 * block_size)) as usize;
+
+        Ok(length)
+    }
 }
 
 impl Read for UcasReader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Reads covering more than the rest of this block and the next are decoded in parallel
+        let remaining = self.current_chunk_data.len().saturating_sub(self.current_offset);
+        if buf.len() > remaining + self.store.data.header.compression_block_size as usize {
+            return match self.read_blocks(buf) {
+                Ok(length) => Ok(length),
+                Err(e) => Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, e)),
+            };
+        }
+
         if self.current_offset >= self.current_chunk_data.len() {
-            self.current_chunk += 1;
-            if self.current_chunk >= self.data.compressed_blocks.len() {
+            if self.current_chunk + 1 >= self.store.data.compressed_blocks.len() {
                 return Ok(0);
             }
 
+            // The next block starts on a block boundary, so the gap after a short block reads as zeros, like in `read_at`
+            let block_size = self.store.data.header.compression_block_size as usize;
+            if self.current_offset < block_size {
+                let length = std::cmp::min(buf.len(), block_size - self.current_offset);
+                buf[..length].fill(0);
+                self.current_total_offset += length as u64;
+                self.current_offset += length;
+                return Ok(length);
+            }
+
+            self.current_chunk += 1;
             self.current_offset = 0;
-            let new_chunk = &self.data.compressed_blocks[self.current_chunk];
-            self.current_chunk_data = match get_chunk(&mut self.handle, &new_chunk, &self.data.header, &self.data.key) {
+            self.current_chunk_data = match self.store.load_block(self.current_chunk) {
                 Ok(d) => d,
                 Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, e)),
             };
@@ -440,22 +796,132 @@ impl Read for UcasReader {
 impl Seek for UcasReader {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
         let target = match pos {
-            SeekFrom::Start(p) => p,
-            SeekFrom::Current(p) => (self.current_total_offset as i64 + p) as u64,
-            SeekFrom::End(p) => (self.total_size as i64 + p) as u64,
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(p) => self.current_total_offset.checked_add_signed(p),
+            SeekFrom::End(p) => self.store.total_size.checked_add_signed(p),
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
         };
 
-        let block_size = self.data.header.compression_block_size as u64;
+        let block_size = self.store.data.header.compression_block_size as u64;
+
+        let target_chunk = (target / block_size) as usize;
+
+        // Only load a new block if the seek lands outside of the current one
+        if target_chunk != self.current_chunk {
+            self.current_chunk_data = match target_chunk < self.store.data.compressed_blocks.len() {
+                true => match self.store.load_block(target_chunk) {
+                    Ok(d) => d,
+                    Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+                },
+                false => Arc::new(Vec::new()),
+            };
+            self.current_chunk = target_chunk;
+        }
 
         self.current_total_offset = target;
-        self.current_chunk = (target / block_size) as usize;
         self.current_offset = (target % block_size) as usize;
-        self.current_chunk_data = get_chunk(&mut self.handle, &self.data.compressed_blocks[self.current_chunk], &self.data.header, &self.data.key).unwrap();
 
         Ok(self.current_total_offset)
     }
 }
 
+/// ChunkReader is a `Read` + `Seek` stream over a single chunk in a `UcasStore`
+///
+/// Blocks are only decoded as they're read. Decoded blocks go through the store's `BlockCache` like any other read.
+pub struct ChunkReader {
+    store: Arc<UcasStore>,
+    offset: u64,
+    length: u64,
+    position: u64,
+    current_block: Option<(usize, Arc<Vec<u8>>)>,
+}
+
+impl ChunkReader {
+    fn new(store: Arc<UcasStore>, offset: u64, length: u64) -> ParserResult<Self> {
+        if offset + length > store.total_size {
+            return Err(ParserError::typed(format!("Chunk runs past end of container: {} + {}", offset, length), ParserType::Malformed));
+        }
+
+        Ok(Self {
+            store,
+            offset,
+            length,
+            position: 0,
+            current_block: None,
+        })
+    }
+
+    /// The size of the chunk
+    pub fn get_size(&self) -> u64 {
+        self.length
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.length || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.store.data.header.compression_block_size as u64;
+        let length = std::cmp::min(buf.len() as u64, self.length - self.position) as usize;
+        let start = self.offset + self.position;
+
+        // Reads spanning several blocks go straight to the store, which decodes them in parallel
+        if length as u64 > block_size * 2 {
+            if let Err(e) = self.store.read_at(start, &mut buf[..length]) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+            }
+            self.position += length as u64;
+            return Ok(length);
+        }
+
+        let idx = (start / block_size) as usize;
+        let block = match &self.current_block {
+            Some((current, block)) if *current == idx => Arc::clone(block),
+            _ => match self.store.load_block(idx) {
+                Ok(block) => {
+                    self.current_block = Some((idx, Arc::clone(&block)));
+                    block
+                },
+                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            },
+        };
+
+        let block_offset = (start % block_size) as usize;
+        if block_offset >= block.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "block is shorter than the container says"));
+        }
+        let length = std::cmp::min(length, block.len() - block_offset);
+        buf[..length].copy_from_slice(&block[block_offset..(block_offset + length)]);
+        self.position += length as u64;
+
+        Ok(length)
+    }
+}
+
+impl Seek for ChunkReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(p) => self.position.checked_add_signed(p),
+            SeekFrom::End(p) => self.length.checked_add_signed(p),
+        };
+
+        // Blocks are only decoded once they're read from, so seeking is free
+        match target {
+            Some(target) => {
+                self.position = target;
+                Ok(target)
+            },
+            None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct FPackageStoreEntry {
     export_bundle_size: u64,
@@ -537,6 +1003,26 @@ impl Newable for FContainerHeader {
     }
 }
 
+impl FContainerHeader {
+    pub fn get_container_id(&self) -> u64 {
+        self.container_id
+    }
+
+    /// The raw name batch, which can be read with `FNameMap::new`
+    pub fn get_names(&self) -> &[u8] {
+        &self.names
+    }
+
+    pub fn get_package_ids(&self) -> &[u64] {
+        &self.package_ids
+    }
+
+    /// The ids of the packages imported by the package at a position in `get_package_ids`
+    pub fn get_imported_packages(&self, idx: usize) -> Option<&[u64]> {
+        self.packages.get(idx).map(|v| v.imported_packages.as_slice())
+    }
+}
+
 #[derive(Debug)]
 struct FMinimalName {
     index: u32,
@@ -634,6 +1120,10 @@ impl FNameMap {
         }
     }
 
+    pub fn get_names(&self) -> &[String] {
+        &self.names
+    }
+
     pub fn get_name(&self, idx: usize) -> ParserResult<&str> {
         match &self.names.get(idx) {
             Some(data) => Ok(data),
@@ -687,6 +1177,55 @@ pub enum ChunkData {
     ContainerHeader(FContainerHeader),
     LoaderInitialLoadMeta(InitialLoadMetaData),
     LoaderGlobalNames(FNameMap),
+    Raw(EIoChunkType, Vec<u8>),
+}
+
+/// Finds where the directory index starts in a `.utoc`, without parsing the tables before it
+///
+/// Returns the header along with the offset of the directory index. Its size is given by the header.
+pub fn find_directory_index(utoc: &[u8]) -> ParserResult<(FIoStoreTocHeader, u64)> {
+    let mut reader = Cursor::new(utoc);
+    let header = FIoStoreTocHeader::new(&mut reader)?;
+
+    let blocks = header.compressed_block_entry_count as u64;
+    let sizes = [
+        header.header_size as u64,
+        header.entry_count as u64 * (12 + 10),
+        get_perfect_hash_size(&header),
+        blocks * 12,
+        header.compression_method_name_count as u64 * header.compression_method_name_length as u64,
+    ];
+    let mut offset = checked_sum(&sizes)?;
+
+    if header.is_signed() {
+        reader.seek(SeekFrom::Start(offset))?;
+        let hash_size = reader.read_u32::<LittleEndian>()? as u64;
+        offset = checked_sum(&[offset, 4, hash_size * 2, blocks * 20])?;
+    }
+
+    Ok((header, offset))
+}
+
+/// Adds up the sizes of the TOC's tables, which come from untrusted header fields
+fn checked_sum(sizes: &[u64]) -> ParserResult<u64> {
+    sizes.iter().try_fold(0u64, |acc, v| acc.checked_add(*v))
+        .ok_or_else(|| ParserError::typed("TOC table sizes overflow".to_owned(), ParserType::Malformed))
+}
+
+/// The size of the perfect hash seeds, and the list of chunks not covered by them
+fn get_perfect_hash_size(header: &FIoStoreTocHeader) -> u64 {
+    (header.perfect_hash_seeds_count as u64 + header.chunks_without_perfect_hash_count as u64) * 4
+}
+
+/// Reads a table whose size comes from the TOC header, failing instead of allocating more than is left in the file
+fn read_toc_bytes(reader: &mut ReaderCursor, size: u64) -> ParserResult<Vec<u8>> {
+    let remaining = (reader.get_ref().len() as u64).saturating_sub(reader.position());
+    if size > remaining {
+        return Err(ParserError::typed(format!("TOC table of {} bytes runs past the end of the file", size), ParserType::Malformed));
+    }
+    let mut data = vec![0u8; size as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
 }
 
 pub struct UtocManager {
@@ -694,6 +1233,8 @@ pub struct UtocManager {
     offsets: Vec<FIoOffsetAndLength>,
     directory_index: FIoDirectoryIndexResource,
     file_list: Vec<String>,
+    file_index: HashMap<String, usize>,
+    chunk_index: HashMap<(u64, u32, EIoChunkType), usize>,
     data: Arc<ReaderData>,
 }
 
@@ -702,11 +1243,11 @@ impl UtocManager {
         let mut reader = Cursor::new(utoc);
         let header = FIoStoreTocHeader::new(&mut reader)?;
 
-        reader.seek(SeekFrom::Start(header.header_size as u64)).unwrap();
+        reader.seek(SeekFrom::Start(header.header_size as u64))?;
 
         let mut chunk_ids = Vec::new();
         for _i in 0..header.entry_count {
-            chunk_ids.push(FIoChunkId::new(&mut reader)?);
+            chunk_ids.push(FIoChunkId::new(&mut reader, header.version)?);
         }
 
         let mut offsets = Vec::new();
@@ -714,6 +1255,10 @@ impl UtocManager {
             offsets.push(FIoOffsetAndLength::new(&mut reader)?);
         }
 
+        // Perfect hash tables are only needed for lookups by chunk id, which are indexed separately
+        let perfect_hash_end = checked_sum(&[reader.position(), get_perfect_hash_size(&header)])?;
+        reader.seek(SeekFrom::Start(perfect_hash_end))?;
+
         let mut compressed_blocks = Vec::new();
         for _i in 0..header.compressed_block_entry_count {
             compressed_blocks.push(FIoStoreTocCompressedBlockEntry::new(&mut reader)?);
@@ -721,58 +1266,70 @@ impl UtocManager {
 
         let mut compression_methods = Vec::new();
         for _i in 0..header.compression_method_name_count {
-            let mut str_data = vec![0u8; header.compression_method_name_length as usize];
-            reader.read_exact(&mut str_data)?;
-            let mut str_content = std::str::from_utf8(&str_data).unwrap().to_owned();
+            let str_data = read_toc_bytes(&mut reader, header.compression_method_name_length as u64)?;
+            let mut str_content = std::str::from_utf8(&str_data)?.to_owned();
             str_content.retain(|c| c != '\u{0}');
             compression_methods.push(str_content);
         }
 
+        let mut block_hashes = Vec::new();
         if header.is_signed() { // Signed
-            let hash_size = reader.read_u32::<LittleEndian>()? as usize;
-            let mut toc_hash = vec![0u8; hash_size];
-            reader.read_exact(&mut toc_hash)?;
-
-            let mut block_hash = vec![0u8; hash_size];
-            reader.read_exact(&mut block_hash)?;
+            // The TOC and block hash signatures need the game's public key to check, so only the block hashes are kept
+            let hash_size = reader.read_u32::<LittleEndian>()? as u64;
+            let _toc_hash = read_toc_bytes(&mut reader, hash_size)?;
+            let _block_hash = read_toc_bytes(&mut reader, hash_size)?;
 
-            let mut hashes = Vec::new();
             for _i in 0..header.compressed_block_entry_count {
-                hashes.push(FSHAHash::new(&mut reader)?);
+                block_hashes.push(FSHAHash::new(&mut reader)?.content);
             }
         }
 
         let hex_key = if header.is_encrypted() {
             match key {
-                Some(k) => Some(hex::decode(k).expect("Hex error")),
-                None => return Err(ParserError::new(format!("No key provided"))),
+                Some(k) => Some(decode_key(k)?),
+                None => return Err(ParserError::typed("No key provided".to_owned(), ParserType::Decryption)),
             }
         } else { None };
 
         let (directory_index, file_list) = match header.directory_index_size > 0 {
             true => {
-                let mut directory_buf = vec![0u8; header.directory_index_size as usize];
-                reader.read_exact(&mut directory_buf)?;
+                let mut directory_buf = read_toc_bytes(&mut reader, header.directory_index_size as u64)?;
 
-                if header.is_encrypted() {
-                    let decrypt = Ecb::<Aes256, ZeroPadding>::new_from_slices((&hex_key).as_ref().unwrap(), Default::default()).unwrap();
-                    decrypt.decrypt(&mut directory_buf).unwrap();
+                if let Some(hex_key) = &hex_key {
+                    decrypt_buffer(hex_key, &mut directory_buf)?;
+                    if !is_mount_point(&directory_buf) {
+                        return Err(ParserError::typed("AES key does not unlock the directory index".to_owned(), ParserType::Decryption));
+                    }
                 }
 
                 let mut directory_cursor = Cursor::new(directory_buf.as_slice());
 
-                let index = FIoDirectoryIndexResource::new(&mut directory_cursor)?;
-                let list = index.get_files(header.entry_count as usize);
+                let index = FIoDirectoryIndexResource::new(&mut directory_cursor)
+                    .map_err(|v| ParserError::add(v, "Directory index".to_owned()))?;
+                let list = index.get_files(header.entry_count as usize)
+                    .map_err(|v| ParserError::add(v, "Directory index".to_owned()))?;
                 (index, list)
             },
             false => (FIoDirectoryIndexResource::empty(), Vec::new())
         };
 
+        // When a path or chunk id is listed more than once, the first is used, as a linear search would find
+        let mut file_index = HashMap::new();
+        for (i, file) in file_list.iter().enumerate().filter(|(_, v)| !v.is_empty()) {
+            file_index.entry(file.clone()).or_insert(i);
+        }
+        let mut chunk_index = HashMap::new();
+        for (i, chunk_id) in chunk_ids.iter().enumerate() {
+            chunk_index.entry((chunk_id.id, chunk_id.index, chunk_id.chunk_type)).or_insert(i);
+        }
+
         let data = Arc::new(ReaderData {
             compressed_blocks,
             compression_methods,
+            block_hashes,
             header,
             key: hex_key,
+            cache_id: BlockCache::next_container_id(),
         });
 
         Ok(Self {
@@ -780,6 +1337,8 @@ impl UtocManager {
             offsets,
             directory_index,
             file_list,
+            file_index,
+            chunk_index,
             data,
         })
     }
@@ -797,24 +1356,30 @@ impl UtocManager {
     }
 
     pub fn get_file(&self, file: &str) -> Option<&FIoOffsetAndLength> {
-        for i in 0..self.file_list.len() {
-            if file == self.file_list[i] {
-                let chunk = &self.offsets[i];
-                return Some(chunk);
-            }
-        }
+        self.file_index.get(file).map(|v| &self.offsets[*v])
+    }
 
-        None
+    /// Finds the position of a file in the TOC
+    pub fn find_file(&self, file: &str) -> Option<usize> {
+        self.file_index.get(file).copied()
     }
 
     pub fn get_chunk_ids(&self) -> &Vec<FIoChunkId> {
         &self.chunk_ids
     }
+
+    /// Finds the position of a chunk in the TOC, from its id, index and type
+    pub fn find_chunk(&self, id: u64, index: u32, chunk_type: EIoChunkType) -> Option<usize> {
+        self.chunk_index.get(&(id, index, chunk_type)).copied()
+    }
 }
 
+/// Extractor reads files and chunks out of an IoStore container
+///
+/// Every read goes through `&self`, so an `Extractor` can be shared between threads.
 pub struct Extractor {
     utoc: UtocManager,
-    reader: UcasReader,
+    store: Arc<UcasStore>,
 }
 
 impl Extractor {
@@ -831,22 +1396,40 @@ impl Extractor {
     }
 
     pub fn new(path: &str, key: Option<&str>) -> ParserResult<Self> {
-        let utoc_path = path.to_owned() + ".utoc";
-        let mut file = File::open(utoc_path)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
+        Self::new_with_cache(path, key, Arc::new(BlockCache::default()))
+    }
 
-        let utoc = UtocManager::new(&buffer, key)?;        
+    /// Opens a container using a block cache that can be shared with other containers
+    pub fn new_with_cache(path: &str, key: Option<&str>, cache: Arc<BlockCache>) -> ParserResult<Self> {
+        Self::new_with_options(path, key, cache, SourceMode::File)
+    }
+
+    /// Opens a container with a shared block cache, choosing whether the `.utoc` and `.ucas` files are read through file handles or memory mapped
+    pub fn new_with_options(path: &str, key: Option<&str>, cache: Arc<BlockCache>, mode: SourceMode) -> ParserResult<Self> {
+        let utoc_path = path.to_owned() + ".utoc";
+        let utoc = match ContainerSource::open(&utoc_path, mode)? {
+            ContainerSource::Mapped(map) => UtocManager::new(&map, key)?,
+            ContainerSource::File(mut file) => {
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer)?;
+                UtocManager::new(&buffer, key)?
+            },
+        };
 
-        let ucas_reader = UcasReader::new(path, utoc.get_reader_data())?;
+        let store = UcasStore::new(path, utoc.get_reader_data(), cache, mode)?;
 
         Ok(Self {
             utoc,
-            reader: ucas_reader,
+            store: Arc::new(store),
         })
     }
 
-    pub fn read_global(&mut self) -> ParserResult<LoaderGlobalData> {
+    /// Opens a `Read` + `Seek` cursor over the whole decompressed container
+    pub fn get_reader(&self) -> ParserResult<UcasReader> {
+        UcasReader::new(Arc::clone(&self.store))
+    }
+
+    pub fn read_global(&self) -> ParserResult<LoaderGlobalData> {
         let initial_data = match self.read_chunk(0)? {
             ChunkData::LoaderInitialLoadMeta(d) => d,
             _ => return Err(ParserError::new(format!("Not a global chunk"))),
@@ -863,21 +1446,32 @@ impl Extractor {
         })
     }
 
-    pub fn read_chunk(&mut self, idx: usize) -> ParserResult<ChunkData> {
-        let chunk_offset = &self.utoc.offsets[idx];
+    pub fn read_chunk(&self, idx: usize) -> ParserResult<ChunkData> {
+        let chunk_data = self.read_chunk_data(idx)?;
         let chunk_id = &self.utoc.chunk_ids[idx];
 
-        let mut chunk_data = vec![0u8; chunk_offset.length as usize];
-        self.reader.seek(SeekFrom::Start(chunk_offset.offset))?;
-        self.reader.read_exact(&mut chunk_data)?;
-
         let mut reader = Cursor::new(chunk_data.as_slice());
 
+        // UE5 container headers have a different layout, which isn't read yet
         match chunk_id.chunk_type {
-            EIoChunkType::ContainerHeader => Ok(ChunkData::ContainerHeader(FContainerHeader::new(&mut reader)?)),
+            EIoChunkType::ContainerHeader if self.utoc.data.header.version < EIoStoreTocVersion::PerfectHash => Ok(ChunkData::ContainerHeader(FContainerHeader::new(&mut reader)?)),
             EIoChunkType::LoaderInitialLoadMeta => Ok(ChunkData::LoaderInitialLoadMeta(InitialLoadMetaData::new(&mut reader)?)),
             EIoChunkType::LoaderGlobalNames => Ok(ChunkData::LoaderGlobalNames(FNameMap::new(&mut reader)?)),
-            _ => Err(ParserError::new(format!("Type not supported: {:#?}", chunk_id))),
+            chunk_type => Ok(ChunkData::Raw(chunk_type, chunk_data)),
+        }
+    }
+
+    pub fn find_chunk(&self, id: u64, index: u32, chunk_type: EIoChunkType) -> Option<usize> {
+        self.utoc.find_chunk(id, index, chunk_type)
+    }
+
+    /// Reads the raw bytes of any chunk, from its id, index and type
+    ///
+    /// This is how bulk data (`EIoChunkType::BulkData`, `OptionalBulkData` and `MemoryMappedBulkData`) and other chunks without a filename can be extracted.
+    pub fn get_chunk(&self, id: u64, index: u32, chunk_type: EIoChunkType) -> ParserResult<Vec<u8>> {
+        match self.utoc.find_chunk(id, index, chunk_type) {
+            Some(idx) => self.read_chunk_data(idx),
+            None => Err(ParserError::new(format!("Could not find chunk: {} {} {:?}", id, index, chunk_type))),
         }
     }
 
@@ -893,19 +1487,269 @@ impl Extractor {
         self.utoc.get_chunk_ids()
     }
 
-    pub fn get_file(&mut self, file: &str) -> ParserResult<Vec<u8>> {
-        for i in 0..self.utoc.file_list.len() {
-            if file == self.utoc.file_list[i] {
-                let chunk = &self.utoc.offsets[i];
-                let mut data = vec![0u8; chunk.length as usize];
+    pub fn get_block_count(&self) -> usize {
+        self.utoc.data.compressed_blocks.len()
+    }
+
+    /// Checks a block against its SHA1 hash, or `None` if the container isn't signed
+    ///
+    /// The hash covers the block as it is stored, so nothing is decrypted or decompressed.
+    pub fn check_block_hash(&self, idx: usize) -> ParserResult<Option<bool>> {
+        let expected = match self.utoc.data.block_hashes.get(idx) {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        let chunk = match self.utoc.data.compressed_blocks.get(idx) {
+            Some(chunk) => chunk,
+            None => return Err(ParserError::new(format!("Block index out of range: {}", idx))),
+        };
+        let buf = read_raw_chunk(&self.store.partitions, chunk, &self.utoc.data.header)?;
+        Ok(Some(sha1_smol::Sha1::from(&buf).digest().bytes() == *expected))
+    }
+
+    /// The positions in the TOC of the chunks with data in a block
+    pub fn get_block_chunks(&self, idx: usize) -> Vec<usize> {
+        let block_size = self.utoc.data.header.get_block_size() as u64;
+        let start = idx as u64 * block_size;
+        let end = start + block_size;
+        self.utoc.offsets.iter().enumerate()
+            .filter(|(_, v)| v.offset < end && v.offset + v.length > start)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Reads the raw bytes of the chunk at a position in the TOC
+    pub fn read_chunk_data(&self, idx: usize) -> ParserResult<Vec<u8>> {
+        let chunk_offset = match self.utoc.offsets.get(idx) {
+            Some(offset) => offset,
+            None => return Err(ParserError::new(format!("Chunk index out of range: {}", idx))),
+        };
+        let mut chunk_data = vec![0u8; chunk_offset.length as usize];
+        self.store.read_at(chunk_offset.offset, &mut chunk_data)
+            .map_err(|v| ParserError::add(v, format!("Chunk {}", idx)))?;
+
+        Ok(chunk_data)
+    }
+
+    /// Opens a `Read` + `Seek` stream over a chunk, which only decodes the blocks that are read
+    pub fn open_chunk(&self, idx: usize) -> ParserResult<ChunkReader> {
+        match self.utoc.offsets.get(idx) {
+            Some(chunk_offset) => ChunkReader::new(Arc::clone(&self.store), chunk_offset.offset, chunk_offset.length)
+                .map_err(|v| ParserError::add(v, format!("Chunk {}", idx))),
+            None => Err(ParserError::new(format!("Chunk index out of range: {}", idx))),
+        }
+    }
+
+    /// Opens a `Read` + `Seek` stream over a file, see `open_chunk`
+    pub fn open(&self, file: &str) -> ParserResult<ChunkReader> {
+        match self.utoc.find_file(file) {
+            Some(idx) => self.open_chunk(idx).map_err(|v| ParserError::add(v, format!("File: {}", file))),
+            None => Err(ParserError::new(format!("Could not find file: {}", file))),
+        }
+    }
+
+    /// Reads the data for a package, and any bulk data that goes with it, straight from the container
+    ///
+    /// `file` is the path of the package inside the container, without an extension (as with `Package::from_file`).
+    /// The optional bulk data (`.uptnl`) is returned in place of the regular bulk data (`.ubulk`) when both are present.
+    pub fn get_package_data(&self, file: &str) -> ParserResult<(Vec<u8>, Option<Vec<u8>>)> {
+        let asset_file = file.to_owned() + ".uasset";
+        let idx = match self.utoc.find_file(&asset_file) {
+            Some(idx) => idx,
+            None => return Err(ParserError::new(format!("Could not find file: {}", asset_file))),
+        };
+
+        let package_id = &self.utoc.chunk_ids[idx];
+        if package_id.chunk_type != EIoChunkType::ExportBundleData {
+            return Err(ParserError::new(format!("Not a package: {}", asset_file)));
+        }
+        let package_id = package_id.id;
+
+        let bulk_idx = self.utoc.find_chunk(package_id, 0, EIoChunkType::OptionalBulkData)
+            .or_else(|| self.utoc.find_chunk(package_id, 0, EIoChunkType::BulkData));
 
-                self.reader.seek(SeekFrom::Start(chunk.offset))?;
-                self.reader.read_exact(&mut data)?;
+        let asset = self.read_chunk_data(idx)?;
+        let bulk = match bulk_idx {
+            Some(bulk_idx) => Some(self.read_chunk_data(bulk_idx)?),
+            None => None,
+        };
+
+        Ok((asset, bulk))
+    }
 
-                return Ok(data);
+    pub fn get_file(&self, file: &str) -> ParserResult<Vec<u8>> {
+        match self.utoc.find_file(file) {
+            Some(idx) => self.read_chunk_data(idx).map_err(|v| ParserError::add(v, format!("File: {}", file))),
+            None => Err(ParserError::new(format!("Could not find file: {}", file))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: u32 = 64;
+
+    /// Writes an uncompressed, version 1 container with a chunk for each of `chunks`, returning its path without an extension
+    ///
+    /// Each chunk starts on a block boundary, so a chunk that isn't a multiple of the block size ends on a short block.
+    fn write_container(name: &str, chunks: &[Vec<u8>]) -> String {
+        let path = std::env::temp_dir().join(format!("jwp-{}-{}", name, std::process::id())).to_string_lossy().into_owned();
+
+        let mut blocks = Vec::new();
+        let mut offsets = Vec::new();
+        let mut ucas = Vec::new();
+        for chunk in chunks {
+            offsets.push(blocks.len() as u64 * BLOCK_SIZE as u64);
+            for block in chunk.chunks(BLOCK_SIZE as usize) {
+                blocks.push((ucas.len() as u64, block.len() as u32));
+                ucas.extend_from_slice(block);
+                ucas.resize(align_value(ucas.len() as u32, 16) as usize, 0);
             }
         }
 
-        Err(ParserError::new(format!("Could not find file: {}", file)))
+        let mut utoc = Vec::new();
+        utoc.extend_from_slice(TOC_MAGIC);
+        utoc.extend_from_slice(&[1, 0, 0, 0]);
+        for v in [144, chunks.len() as u32, blocks.len() as u32, 12, 0, 32, BLOCK_SIZE, 0, 1] {
+            utoc.extend_from_slice(&v.to_le_bytes());
+        }
+        utoc.resize(144, 0);
+
+        for i in 0..chunks.len() {
+            utoc.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+            utoc.extend_from_slice(&[0, 0, 0, 2]);
+        }
+        for (offset, chunk) in offsets.iter().zip(chunks) {
+            utoc.extend_from_slice(&offset.to_be_bytes()[3..]);
+            utoc.extend_from_slice(&(chunk.len() as u64).to_be_bytes()[3..]);
+        }
+        for (offset, size) in &blocks {
+            utoc.extend_from_slice(&offset.to_le_bytes()[..5]);
+            utoc.extend_from_slice(&size.to_le_bytes()[..3]);
+            utoc.extend_from_slice(&size.to_le_bytes()[..3]);
+            utoc.push(0);
+        }
+
+        std::fs::write(path.clone() + ".utoc", utoc).unwrap();
+        std::fs::write(path.clone() + ".ucas", ucas).unwrap();
+        path
     }
-}
\ No newline at end of file
+
+    fn remove_container(path: &str) {
+        let _ = std::fs::remove_file(path.to_owned() + ".utoc");
+        let _ = std::fs::remove_file(path.to_owned() + ".ucas");
+    }
+
+    #[test]
+    fn chunks_after_a_short_block() {
+        let first: Vec<u8> = (0..100).collect();
+        let second: Vec<u8> = (100..150).collect();
+        let path = write_container("short-block", &[first.clone(), second.clone()]);
+        let dispatch = Extractor::new(&path, None).unwrap();
+
+        // The second chunk starts at block 2, past the 150 decompressed bytes before it
+        assert_eq!(dispatch.read_chunk_data(0).unwrap(), first);
+        assert_eq!(dispatch.read_chunk_data(1).unwrap(), second);
+
+        let mut data = Vec::new();
+        dispatch.open_chunk(1).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, second);
+
+        let mut data = vec![0u8; 1];
+        assert!(dispatch.store.read_at(178, &mut data).is_err());
+
+        remove_container(&path);
+    }
+
+    #[test]
+    fn readers_after_a_short_block() {
+        let first: Vec<u8> = (0..100).collect();
+        let second: Vec<u8> = (0..200).map(|v| v as u8 ^ 0x5a).collect();
+        let path = write_container("short-block-readers", &[first.clone(), second.clone()]);
+        let dispatch = Extractor::new(&path, None).unwrap();
+
+        // Reading block by block, the gap after the short block reads as zeros
+        let mut reader = dispatch.get_reader().unwrap();
+        let mut data = vec![0u8; 100];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(data, first);
+        let mut data = vec![0xffu8; 28];
+        reader.read_exact(&mut data).unwrap();
+        assert!(data.iter().all(|v| *v == 0));
+        let mut data = vec![0u8; 50];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(data, second[..50]);
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 128 + 200);
+
+        // Reads spanning several blocks are decoded together
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        let mut data = vec![0xffu8; 128 + 200];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(reader.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(data[..100], first[..]);
+        assert!(data[100..128].iter().all(|v| *v == 0));
+        assert_eq!(data[128..], second[..]);
+
+        let mut chunk = dispatch.open_chunk(1).unwrap();
+        let mut data = Vec::new();
+        chunk.read_to_end(&mut data).unwrap();
+        assert_eq!(data, second);
+        chunk.seek(SeekFrom::End(-10)).unwrap();
+        let mut data = Vec::new();
+        chunk.read_to_end(&mut data).unwrap();
+        assert_eq!(data, second[190..]);
+
+        remove_container(&path);
+    }
+
+    #[test]
+    fn chunk_types_by_version() {
+        let chunk_type = |raw_type: u8, version| {
+            let mut data = 0x1234u64.to_le_bytes().to_vec();
+            data.extend_from_slice(&[0, 0, 0, raw_type]);
+            FIoChunkId::new(&mut Cursor::new(data.as_slice()), version).map(|v| v.get_type())
+        };
+
+        assert_eq!(chunk_type(10, EIoStoreTocVersion::PartitionSize).unwrap(), EIoChunkType::ContainerHeader);
+        assert_eq!(chunk_type(2, EIoStoreTocVersion::PartitionSize).unwrap(), EIoChunkType::ExportBundleData);
+        assert!(chunk_type(11, EIoStoreTocVersion::PartitionSize).is_err());
+
+        assert_eq!(chunk_type(6, EIoStoreTocVersion::PerfectHash).unwrap(), EIoChunkType::ContainerHeader);
+        assert_eq!(chunk_type(1, EIoStoreTocVersion::PerfectHashWithOverflow).unwrap(), EIoChunkType::ExportBundleData);
+        assert_eq!(chunk_type(2, EIoStoreTocVersion::PerfectHash).unwrap(), EIoChunkType::BulkData);
+        assert_eq!(chunk_type(11, EIoStoreTocVersion::PerfectHash).unwrap(), EIoChunkType::DerivedData);
+        assert_eq!(chunk_type(12, EIoStoreTocVersion::PerfectHash).unwrap(), EIoChunkType::EditorDerivedData);
+        assert!(chunk_type(14, EIoStoreTocVersion::PerfectHash).is_err());
+    }
+
+    #[test]
+    fn oversized_toc_tables() {
+        let path = write_container("oversized", &[vec![1u8; 100]]);
+        let utoc = std::fs::read(path.clone() + ".utoc").unwrap();
+        remove_container(&path);
+        assert!(UtocManager::new(&utoc, None).is_ok());
+
+        // Counts and sizes large enough to overflow 32-bit arithmetic, set one at a time
+        let set = |fields: &[(usize, u32)]| {
+            let mut utoc = utoc.clone();
+            utoc[16] = 5;
+            for (offset, v) in fields {
+                utoc[*offset..(*offset + 4)].copy_from_slice(&v.to_le_bytes());
+            }
+            utoc
+        };
+        let malformed = [
+            set(&[(84, u32::MAX), (96, u32::MAX)]),
+            set(&[(36, 0x10000), (40, 0x10000)]),
+            set(&[(36, 1), (40, u32::MAX)]),
+            set(&[(48, u32::MAX)]),
+            set(&[(20, u32::MAX), (24, u32::MAX)]),
+        ];
+        for utoc in malformed.iter() {
+            assert!(UtocManager::new(utoc, None).is_err());
+            let _ = find_directory_index(utoc);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! The package dependency graph of a game, built from the package store entries in each IoStore container header
+//!
+//! Packages are keyed by id, and named through a `PackageIdResolver`. Ids that can't be resolved are shown in hex.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use serde::ser::{Serialize, Serializer, SerializeMap, SerializeStruct};
+use crate::assets::ParserResult;
+use crate::dispatch::{ChunkData, EIoChunkType, Extractor};
+use crate::packages::{PackageIdResolver, get_package_id};
+use crate::provider::{FileProvider, Container};
+
+#[derive(Debug, Default)]
+struct PackageNode {
+    imports: Vec<u64>,
+    importers: Vec<u64>,
+    /// The priority of the container the imports were read from, so patches replace the packages they override
+    ///
+    /// `None` for packages that have only been seen as imports.
+    priority: Option<i32>,
+}
+
+/// A forward and reverse dependency graph across a set of packages
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    packages: HashMap<u64, PackageNode>,
+    names: PackageIdResolver,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the graph for every IoStore container mounted in a provider
+    pub fn from_provider(provider: &FileProvider) -> ParserResult<Self> {
+        let mut graph = Self {
+            packages: HashMap::new(),
+            names: PackageIdResolver::from_provider(provider)?,
+        };
+        for container in provider.get_containers() {
+            if let Container::IoStore(dispatch) = container.get_container() {
+                graph.add_container(dispatch, container.get_priority())?;
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Adds the packages in a container's header, replacing any that were added from a lower priority container
+    pub fn add_container(&mut self, dispatch: &Extractor, priority: i32) -> ParserResult<()> {
+        let idx = match dispatch.get_chunk_ids().iter().position(|v| v.get_type() == EIoChunkType::ContainerHeader) {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        let header = match dispatch.read_chunk(idx)? {
+            ChunkData::ContainerHeader(header) => header,
+            _ => return Ok(()),
+        };
+
+        for (i, id) in header.get_package_ids().iter().enumerate() {
+            let imports = header.get_imported_packages(i).unwrap_or(&[]);
+            self.set_imports(*id, imports, priority);
+        }
+        Ok(())
+    }
+
+    /// Adds a single package and the packages it imports, such as those from `Package::get_imported_packages`
+    ///
+    /// This replaces anything read from a container for the same package.
+    pub fn add_package(&mut self, id: u64, imports: &[u64]) {
+        self.set_imports(id, imports, i32::MAX);
+    }
+
+    fn set_imports(&mut self, id: u64, imports: &[u64], priority: i32) {
+        let previous = match self.packages.get(&id) {
+            Some(node) if node.priority > Some(priority) => return,
+            Some(node) => node.imports.clone(),
+            None => Vec::new(),
+        };
+        // Packages that were only seen as imports are dropped once nothing imports them
+        for import in previous {
+            if let Some(node) = self.packages.get_mut(&import) {
+                node.importers.retain(|v| *v != id);
+                if node.priority.is_none() && node.importers.is_empty() {
+                    self.packages.remove(&import);
+                }
+            }
+        }
+
+        for import in imports {
+            let node = self.packages.entry(*import).or_default();
+            if !node.importers.contains(&id) {
+                node.importers.push(id);
+            }
+        }
+        let node = self.packages.entry(id).or_default();
+        node.imports = imports.to_vec();
+        node.priority = Some(priority);
+    }
+
+    /// Gives the resolver used to name packages, so names from outside the graph's containers can be added
+    pub fn get_resolver_mut(&mut self) -> &mut PackageIdResolver {
+        &mut self.names
+    }
+
+    /// The name of a package, or its id in hex when it can't be resolved
+    pub fn get_name(&self, id: u64) -> String {
+        match self.names.resolve(id) {
+            Some(name) => name.to_owned(),
+            None => format!("{:016x}", id),
+        }
+    }
+
+    pub fn get_package_ids(&self) -> Vec<u64> {
+        self.packages.keys().copied().collect()
+    }
+
+    /// The packages a package loads directly
+    pub fn get_dependencies(&self, id: u64) -> &[u64] {
+        self.packages.get(&id).map(|v| v.imports.as_slice()).unwrap_or(&[])
+    }
+
+    /// The packages that load a package directly
+    pub fn get_dependents(&self, id: u64) -> &[u64] {
+        self.packages.get(&id).map(|v| v.importers.as_slice()).unwrap_or(&[])
+    }
+
+    /// Every package a package loads, directly or through other packages
+    pub fn get_all_dependencies(&self, id: u64) -> Vec<u64> {
+        self.walk(id, |v| self.get_dependencies(v))
+    }
+
+    /// Every package that loads a package, directly or through other packages
+    pub fn get_all_dependents(&self, id: u64) -> Vec<u64> {
+        self.walk(id, |v| self.get_dependents(v))
+    }
+
+    fn walk<'a, F>(&'a self, id: u64, edges: F) -> Vec<u64> where F: Fn(u64) -> &'a [u64] {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        let mut queue: VecDeque<u64> = edges(id).iter().copied().collect();
+        seen.insert(id);
+        while let Some(next) = queue.pop_front() {
+            if !seen.insert(next) {
+                continue;
+            }
+            found.push(next);
+            queue.extend(edges(next));
+        }
+        found
+    }
+
+    /// The packages a package loads directly, by name (`/Game/Athena/Foo`)
+    pub fn get_dependencies_by_name(&self, package_name: &str) -> Vec<String> {
+        self.get_dependencies(get_package_id(package_name)).iter().map(|v| self.get_name(*v)).collect()
+    }
+
+    /// The packages that load a package directly, by name (`/Game/Athena/Foo`)
+    pub fn get_dependents_by_name(&self, package_name: &str) -> Vec<String> {
+        self.get_dependents(get_package_id(package_name)).iter().map(|v| self.get_name(*v)).collect()
+    }
+
+    /// Every package by name, in name order, with the names of the packages it imports
+    fn get_sorted(&self) -> BTreeMap<String, (u64, Vec<String>, Vec<String>)> {
+        self.packages.iter().map(|(id, node)| {
+            let mut imports: Vec<String> = node.imports.iter().map(|v| self.get_name(*v)).collect();
+            let mut importers: Vec<String> = node.importers.iter().map(|v| self.get_name(*v)).collect();
+            imports.sort();
+            importers.sort();
+            (self.get_name(*id), (*id, imports, importers))
+        }).collect()
+    }
+
+    /// Writes the graph in Graphviz DOT format, with an edge from each package to each package it imports
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph packages {\n".to_owned();
+        for (name, (_, imports, _)) in self.get_sorted() {
+            dot += &format!("    \"{}\";\n", escape_dot(&name));
+            for import in imports {
+                dot += &format!("    \"{}\" -> \"{}\";\n", escape_dot(&name), escape_dot(&import));
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    /// Writes the graph as GraphML, with an edge from each package to each package it imports
+    pub fn to_graphml(&self) -> String {
+        let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_owned();
+        xml += "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n";
+        xml += "  <key id=\"name\" for=\"node\" attr.name=\"name\" attr.type=\"string\"/>\n";
+        xml += "  <graph id=\"packages\" edgedefault=\"directed\">\n";
+        let sorted = self.get_sorted();
+        for (name, (id, _, _)) in &sorted {
+            xml += &format!("    <node id=\"{:016x}\"><data key=\"name\">{}</data></node>\n", id, escape_xml(name));
+        }
+        for (id, _, _) in sorted.values() {
+            for import in self.get_dependencies(*id) {
+                xml += &format!("    <edge source=\"{:016x}\" target=\"{:016x}\"/>\n", id, import);
+            }
+        }
+        xml += "  </graph>\n</graphml>\n";
+        xml
+    }
+}
+
+fn escape_dot(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(name: &str) -> String {
+    name.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+struct PackageEntry<'a> {
+    id: u64,
+    imports: &'a [String],
+    imported_by: &'a [String],
+}
+
+impl<'a> Serialize for PackageEntry<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut state = serializer.serialize_struct("PackageEntry", 3)?;
+        state.serialize_field("id", &format!("{:016x}", self.id))?;
+        state.serialize_field("imports", self.imports)?;
+        state.serialize_field("imported_by", self.imported_by)?;
+        state.end()
+    }
+}
+
+/// The JSON form of the graph is a map of package name to its id, imports and importers
+impl Serialize for DependencyGraph {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let sorted = self.get_sorted();
+        let mut map = serializer.serialize_map(Some(sorted.len()))?;
+        for (name, (id, imports, imported_by)) in &sorted {
+            map.serialize_entry(name, &PackageEntry {
+                id: *id,
+                imports,
+                imported_by,
+            })?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut ids: Vec<u64>) -> Vec<u64> {
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn priority_override() {
+        let (a, b, c) = (1, 2, 3);
+        let mut graph = DependencyGraph::new();
+        graph.set_imports(a, &[b], 0);
+        graph.set_imports(c, &[], 0);
+
+        // A patch container replaces the imports, and drops the package that was only known as an import
+        graph.set_imports(a, &[c], 5);
+        assert_eq!(graph.get_dependencies(a), [c]);
+        assert_eq!(graph.get_dependents(c), [a]);
+        assert!(graph.get_dependents(b).is_empty());
+        assert_eq!(sorted(graph.get_package_ids()), [a, c]);
+        assert!(!graph.to_dot().contains(&graph.get_name(b)));
+        assert!(!graph.to_graphml().contains(&format!("{:016x}", b)));
+
+        // Lower priority containers don't replace it
+        graph.set_imports(a, &[b], 1);
+        assert_eq!(graph.get_dependencies(a), [c]);
+        assert!(graph.get_dependents(b).is_empty());
+
+        // Packages added directly replace anything from a container, and packages with their own entry stay
+        graph.add_package(a, &[b]);
+        assert_eq!(graph.get_dependents(b), [a]);
+        assert!(graph.get_dependents(c).is_empty());
+        assert_eq!(sorted(graph.get_package_ids()), [a, b, c]);
+    }
+
+    #[test]
+    fn walk_cycles() {
+        let (a, b, c, d) = (1, 2, 3, 4);
+        let mut graph = DependencyGraph::new();
+        graph.add_package(a, &[b]);
+        graph.add_package(b, &[c]);
+        graph.add_package(c, &[a, d]);
+
+        assert_eq!(graph.get_all_dependencies(a), [b, c, d]);
+        assert_eq!(graph.get_all_dependents(a), [c, b]);
+        assert_eq!(graph.get_all_dependents(d), [c, b, a]);
+    }
+}
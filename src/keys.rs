@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::archives::{PakExtractor, decode_key, decrypt_buffer};
+use crate::assets::{FGuid, ParserResult, ParserError, ParserType};
+use crate::dispatch::{Extractor, find_directory_index};
+use crate::source::{ContainerSource, SourceMode};
+
+/// The most of an encrypted index that is decrypted to test a key: the longest mount point that is accepted, plus its length
+const INDEX_PREFIX_SIZE: u64 = 4 + 1024 * 2 + 16;
+
+/// Keychain holds the AES keys for a game, by the GUID each container names in its header
+///
+/// The main key for a game has an all-zero GUID. Additional (dynamic) keys have their own GUIDs.
+#[derive(Debug, Clone, Default)]
+pub struct Keychain {
+    keys: HashMap<FGuid, String>,
+}
+
+impl Keychain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a keychain holding a single main key
+    pub fn from_key(key: &str) -> ParserResult<Self> {
+        let mut keychain = Self::new();
+        keychain.add_key(FGuid::default(), key)?;
+        Ok(keychain)
+    }
+
+    /// Reads a keychain from a JSON object of GUIDs to hex keys
+    ///
+    /// `{ "00000000000000000000000000000000": "0x...", "A1B2C3D4...": "0x..." }`
+    pub fn from_json(data: &str) -> ParserResult<Self> {
+        let entries: HashMap<String, String> = serde_json::from_str(data)?;
+        let mut keychain = Self::new();
+        for (guid, key) in entries {
+            let guid: FGuid = guid.parse()?;
+            keychain.add_key(guid, &key)?;
+        }
+        Ok(keychain)
+    }
+
+    pub fn from_file(path: &str) -> ParserResult<Self> {
+        let data = fs::read_to_string(path)?;
+        Self::from_json(&data).map_err(|v| ParserError::add(v, format!("Keychain: {}", path)))
+    }
+
+    /// Adds a hex key (with or without a leading `0x`) for a GUID, replacing any key it already had
+    pub fn add_key(&mut self, guid: FGuid, key: &str) -> ParserResult<()> {
+        let key = key.trim();
+        let decoded = decode_key(key)?;
+        if decoded.len() != 32 {
+            return Err(ParserError::typed(format!("AES key for {} is {} bytes, expected 32", guid, decoded.len()), ParserType::Decryption));
+        }
+        self.keys.insert(guid, key.trim_start_matches("0x").to_owned());
+        Ok(())
+    }
+
+    pub fn get_key(&self, guid: &FGuid) -> Option<&str> {
+        self.keys.get(guid).map(|v| v.as_ref())
+    }
+
+    pub fn get_keys(&self) -> &HashMap<FGuid, String> {
+        &self.keys
+    }
+
+    /// Picks the key a `.pak` or `.utoc` container needs, reading only its header
+    ///
+    /// Containers that aren't encrypted get an empty key when the keychain has nothing for them.
+    /// Returns `None` when the container is encrypted with a key the keychain doesn't hold.
+    pub fn get_container_key(&self, path: &str) -> ParserResult<Option<&str>> {
+        let (guid, encrypted) = get_container_encryption(path)?;
+        match self.get_key(&guid) {
+            Some(key) => Ok(Some(key)),
+            None if !encrypted => Ok(Some("")),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads the key GUID of a `.pak` or `.utoc` container, and whether its index is encrypted
+pub fn get_container_encryption(path: &str) -> ParserResult<(FGuid, bool)> {
+    match Path::new(path).extension().and_then(|v| v.to_str()) {
+        Some("pak") => {
+            let header = PakExtractor::new_header(path)?;
+            Ok((header.get_key_guid().clone(), header.is_encrypted()))
+        },
+        Some("utoc") => {
+            let header = Extractor::new_header(&path[..(path.len() - 5)])?;
+            Ok((header.get_key_guid().clone(), header.is_encrypted()))
+        },
+        _ => Err(ParserError::new(format!("Unrecognised container: {}", path))),
+    }
+}
+
+/// The outcome of testing candidate keys against a container
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContainerKey<'a> {
+    /// The container's index isn't encrypted, so any key (or none) will open it
+    NotEncrypted,
+    /// The first candidate that decrypted the index to a valid mount point
+    Found(&'a str),
+    /// None of the candidates unlocked the container
+    NotFound,
+}
+
+/// Tests candidate hex keys against a `.pak` or `.utoc` container, returning the first one that unlocks it
+///
+/// Only the start of the encrypted index is decrypted, and checked for a sensible mount point. Nothing else is parsed.
+pub fn find_container_key<'a>(path: &str, candidates: &[&'a str]) -> ParserResult<ContainerKey<'a>> {
+    let prefix = match read_index_prefix(path)? {
+        Some(prefix) => prefix,
+        None => return Ok(ContainerKey::NotEncrypted),
+    };
+
+    for candidate in candidates {
+        if unlocks_index(&prefix, candidate) {
+            return Ok(ContainerKey::Found(candidate));
+        }
+    }
+
+    Ok(ContainerKey::NotFound)
+}
+
+/// Tests a single hex key against a `.pak` or `.utoc` container, see `find_container_key`
+pub fn test_container_key(path: &str, key: &str) -> ParserResult<bool> {
+    Ok(find_container_key(path, &[key])? != ContainerKey::NotFound)
+}
+
+/// Reads the start of a container's encrypted index, or `None` if the index isn't encrypted
+fn read_index_prefix(path: &str) -> ParserResult<Option<Vec<u8>>> {
+    let (container, offset, size) = match Path::new(path).extension().and_then(|v| v.to_str()) {
+        Some("pak") => {
+            let header = PakExtractor::new_header(path)?;
+            if !header.is_encrypted() {
+                return Ok(None);
+            }
+            let (offset, size) = header.get_index_sizes();
+            (path.to_owned(), offset, size)
+        },
+        Some("utoc") => {
+            let utoc = fs::read(path)?;
+            let (header, offset) = find_directory_index(&utoc)?;
+            if !header.is_encrypted() {
+                return Ok(None);
+            }
+            if header.get_directory_index_size() == 0 {
+                return Err(ParserError::new(format!("No directory index to test keys against: {}", path)));
+            }
+            (path.to_owned(), offset, header.get_directory_index_size() as u64)
+        },
+        _ => return Err(ParserError::new(format!("Unrecognised container: {}", path))),
+    };
+
+    // Encrypted indexes are padded to the AES block size, so any prefix of 16 byte blocks can be decrypted alone
+    let length = std::cmp::min(size, INDEX_PREFIX_SIZE) & !15;
+    let source = ContainerSource::open(&container, SourceMode::File)?;
+    Ok(Some(source.read_vec(offset, length as usize)?))
+}
+
+fn unlocks_index(prefix: &[u8], key: &str) -> bool {
+    let key = match decode_key(key.trim()) {
+        Ok(key) if key.len() == 32 => key,
+        _ => return false,
+    };
+
+    let mut data = prefix.to_vec();
+    decrypt_buffer(&key, &mut data).is_ok() && is_mount_point(&data)
+}
+
+/// Checks that a decrypted index starts with a plausible mount point, which is how a wrong key is told apart from the right one
+pub(crate) fn is_mount_point(data: &[u8]) -> bool {
+    if data.len() < 4 {
+        return false;
+    }
+    let length = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let text = &data[4..];
+
+    match length {
+        1..=1024 => {
+            let length = length as usize;
+            text.len() >= length && text[length - 1] == 0 && text[..(length - 1)].iter().all(|v| (0x20..0x7f).contains(v))
+        },
+        -1024..=-1 => {
+            let length = length.unsigned_abs() as usize;
+            if text.len() < length * 2 {
+                return false;
+            }
+            let chars: Vec<u16> = text[..(length * 2)].chunks(2).map(|v| u16::from_le_bytes([v[0], v[1]])).collect();
+            chars[length - 1] == 0 && match String::from_utf16(&chars[..(length - 1)]) {
+                Ok(mount_point) => !mount_point.chars().any(char::is_control),
+                Err(_) => false,
+            }
+        },
+        _ => false,
+    }
+}
\ No newline at end of file
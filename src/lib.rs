@@ -1,30 +1,31 @@
 #![allow(dead_code)]
 
-use lazy_static::lazy_static;
 use crate::assets::{ParserResult, ParserError, Package, Texture2D, USoundWave};
+use crate::context::GameContext;
 
 pub mod assets;
+pub mod cache;
+pub mod context;
 pub mod archives;
 pub mod dispatch;
+pub mod graph;
 pub mod decompress;
+pub mod keys;
 pub mod manifest;
-mod mapping;
+pub mod mapping;
+pub mod packages;
+pub mod provider;
+pub mod source;
+pub mod verify;
 mod sound;
 mod texture;
 
-lazy_static! {
-    static ref GLOBAL_DATA: dispatch::LoaderGlobalData = {
-        let mut dispatch = dispatch::Extractor::new("paks/global", None).expect("Could not read global");
-        dispatch.read_global().expect("Could not parse global")
-    };
-}
-
-pub fn read_asset(asset: &[u8], ubulk: Option<&[u8]>) -> ParserResult<Package> {
-    Package::from_buffer(asset, ubulk, &GLOBAL_DATA)
+pub fn read_asset(asset: &[u8], ubulk: Option<&[u8]>, context: &GameContext) -> ParserResult<Package> {
+    Package::from_buffer(asset, ubulk, context)
 }
 
-pub fn read_asset_from_file(file: &str) -> ParserResult<Package> {
-    Package::from_file(file, &GLOBAL_DATA)
+pub fn read_asset_from_file(file: &str, context: &GameContext) -> ParserResult<Package> {
+    Package::from_file(file, context)
 }
 
 pub fn read_texture(package: Package) -> ParserResult<Vec<u8>> {
@@ -1,20 +1,30 @@
 #![allow(dead_code)]
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
 use std::io::{Read, Write};
 use std::env;
 
 mod dispatch;
+mod graph;
 mod decompress;
 mod mapping;
 mod assets;
 mod archives;
+mod cache;
+mod config;
+mod context;
 mod texture;
 mod sound;
+mod keys;
 mod manifest;
+mod packages;
+mod provider;
+mod source;
+mod verify;
 
 use dispatch::{ChunkData, LoaderGlobalData};
+use config::Config;
 
 #[derive(Debug)]
 struct CommandError {
@@ -48,46 +58,117 @@ fn cerr(message: &'static str) -> CommandResult {
     })
 }
 
-fn serialize(params: &[String]) -> CommandResult {
+/// Loads the keychain from the configured key source
+///
+/// Without one, this reads `keys.json` (GUID to hex key), falling back to a single main key in `key.txt`.
+fn load_keychain(config: &Config) -> Result<keys::Keychain, CommandError> {
+    if let Some(source) = config.get_keys() {
+        return match Path::new(source) {
+            path if path.extension().map_or(false, |v| v == "json") => Ok(keys::Keychain::from_file(source)?),
+            path if path.is_file() => match fs::read_to_string(path) {
+                Ok(data) => Ok(keys::Keychain::from_key(data.trim())?),
+                Err(e) => Err(CommandError {
+                    message: format!("Could not read key from {}: {}", source, e),
+                }),
+            },
+            _ => Ok(keys::Keychain::from_key(source)?),
+        };
+    }
+
+    if Path::new("keys.json").exists() {
+        return Ok(keys::Keychain::from_file("keys.json")?);
+    }
+
+    match fs::read_to_string("key.txt") {
+        Ok(data) => Ok(keys::Keychain::from_key(data.trim())?),
+        Err(_) => Err(CommandError {
+            message: "Could not read key".to_owned(),
+        }),
+    }
+}
+
+/// Loads the keychain for commands that can read unencrypted containers without one, unless a key source was configured
+fn load_optional_keychain(config: &Config) -> Result<keys::Keychain, CommandError> {
+    match load_keychain(config) {
+        Ok(keychain) => Ok(keychain),
+        Err(e) if config.get_keys().is_some() => Err(e),
+        Err(_) => Ok(keys::Keychain::default()),
+    }
+}
+
+/// Picks the key for a single `.pak` or `.utoc` container from the keychain
+fn container_key(config: &Config, container: &str) -> Result<String, CommandError> {
+    let keychain = load_keychain(config)?;
+    let key = keychain.get_container_key(container)?;
+    match key {
+        Some(key) => Ok(key.to_owned()),
+        None => Err(CommandError {
+            message: format!("No key for container: {}", container),
+        }),
+    }
+}
+
+/// Loads the global data from the game folder, and the mappings
+fn load_context(config: &Config) -> Result<context::GameContext, CommandError> {
+    Ok(context::GameContext::load(&config.get_global_path(), config.get_mappings(), load_optional_keychain(config)?)?)
+}
+
+/// Writes a serialized package or locres file in the configured format
+fn write_json<T: serde::Serialize>(data: &T, input: &str, config: &Config) -> CommandResult {
+    let serial_data = match config.get_package_format() {
+        "json" => serde_json::to_string(data).unwrap(),
+        "pretty-json" => serde_json::to_string_pretty(data).unwrap(),
+        format => return Err(CommandError {
+            message: format!("Unknown package format: {}", format),
+        }),
+    };
+    let mut file = fs::File::create(config.get_output_file(input, ".json")?).unwrap();
+    file.write_all(serial_data.as_bytes()).unwrap();
+
+    Ok(())
+}
+
+fn serialize(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
 
-    let mut dispatch = dispatch::Extractor::new("paks/global", None)?;
-    let global_data = dispatch.read_global()?;
+    let context = load_context(config)?;
+    let mut package = assets::Package::from_file(path, &context)?;
 
-    let package = assets::Package::from_file(path, &global_data)?;
-    let serial_package = serde_json::to_string(&package).unwrap();
-    let mut file = fs::File::create(path.to_owned() + ".json").unwrap();
-    file.write_all(serial_package.as_bytes()).unwrap();
+    // Imported packages are named by hashing the package names in every container in the game folder. Without them, imports keep their ids.
+    let resolver = provider::FileProvider::from_keychain(config.get_game_dir(), context.get_keychain())
+        .and_then(|provider| packages::PackageIdResolver::from_provider(&provider));
+    match resolver {
+        Ok(resolver) => package.resolve_package_names(&resolver),
+        Err(e) => println!("Could not resolve package names: {}", e),
+    }
 
-    Ok(())
+    write_json(&package, path, config)
 }
 
-fn debug(params: &[String]) -> CommandResult {
+fn debug(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
 
-    let name_map = LoaderGlobalData::empty();
-    let package = assets::Package::from_file(path, &name_map)?;
+    let context = context::GameContext::new(LoaderGlobalData::empty(), mapping::MappingStore::from_dir(config.get_mappings())?, keys::Keychain::new());
+    let package = assets::Package::from_file(path, &context)?;
     println!("{:#?}", package);
 
     Ok(())
 }
 
-fn texture(params: &[String]) -> CommandResult {
+fn texture(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
 
-    let mut dispatch = dispatch::Extractor::new("paks/global", None)?;
-    let global_data = dispatch.read_global()?;
-
-    let package = assets::Package::from_file(path, &global_data)?;
+    let context = load_context(config)?;
+    let package = assets::Package::from_file(path, &context)?;
     let package_export = package.get_export_move(0)?.into_any();
     let texture = match package_export.downcast::<assets::Texture2D>() {
         Ok(data) => data,
@@ -96,23 +177,20 @@ fn texture(params: &[String]) -> CommandResult {
 
     let texture_bytes = texture::decode_texture(*texture)?;
 
-    let save_path = path.clone() + ".png";
-    let mut file = fs::File::create(save_path).unwrap();
+    let mut file = fs::File::create(config.get_output_file(path, ".png")?).unwrap();
     file.write_all(&texture_bytes).unwrap();
 
     Ok(())
 }
 
-fn sound(params: &[String]) -> CommandResult {
+fn sound(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
 
-    let mut dispatch = dispatch::Extractor::new("paks/global", None)?;
-    let global_data = dispatch.read_global()?;
-
-    let package = assets::Package::from_file(path, &global_data)?;
+    let context = load_context(config)?;
+    let package = assets::Package::from_file(path, &context)?;
     let package_export = package.get_export_move(0)?.into_any();
     let sound = match package_export.downcast::<assets::USoundWave>() {
         Ok(data) => data,
@@ -121,44 +199,46 @@ fn sound(params: &[String]) -> CommandResult {
 
     let sound_data = sound::decode_sound(*sound)?;
 
-    let save_path = path.clone() + ".ogg";
-    let mut file = fs::File::create(save_path).unwrap();
+    let mut file = fs::File::create(config.get_output_file(path, ".ogg")?).unwrap();
     file.write_all(&sound_data).unwrap();
 
     Ok(())
 }
 
-fn dispatch(params: &[String]) -> CommandResult {
+fn dispatch(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
-    let key = match std::fs::read_to_string("key.txt") {
-        Ok(data) => data,
-        Err(_) => return cerr("Could not read key"),
-    };
+    let key = container_key(config, &(path.to_owned() + ".utoc"))?;
     let pattern = match params.get(1) {
         Some(data) => data,
         None => return cerr("No pattern specified"),
     };
 
-    let mut dispatch = dispatch::Extractor::new(&path, Some(&key))?;
+    let dispatch = dispatch::Extractor::new(&path, Some(&key))?;
     let entries: Vec<String> = dispatch.get_file_list().into_iter().filter(|v| v.contains(pattern)).map(|v| v.to_owned()).collect();
 
     for asset in entries {
-        let file_contents = dispatch.get_file(&asset)?;
-        let path = Path::new(&asset);
+        let file_contents = match dispatch.get_file(&asset) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Could not extract {}: {}", asset, e);
+                continue;
+            },
+        };
+        let path = config.get_output_path(&asset);
         if let Some(basename) = path.parent() {
             fs::create_dir_all(basename).expect("Could not create directory");
         }
-        let mut file = fs::File::create(&asset).unwrap();
+        let mut file = fs::File::create(&path).unwrap();
         file.write_all(&file_contents).unwrap();
     }
 
     Ok(())
 }
 
-fn manifest(params: &[String]) -> CommandResult {
+fn manifest(params: &[String], _config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
@@ -167,91 +247,57 @@ fn manifest(params: &[String]) -> CommandResult {
     Ok(())
 }
 
-fn filelist(params: &[String]) -> CommandResult {
+fn filelist(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
-    let key = match std::fs::read_to_string("key.txt") {
-        Ok(data) => data,
-        Err(_) => return cerr("Could not read key"),
-    };
+    let keychain = load_keychain(config)?;
 
-    let path_dir = Path::new(path);
-    let paths = match path_dir.is_dir() {
-        true => {
-            path_dir.read_dir().unwrap().filter(|v| {
-                if let Ok(filepath) = v {
-                    return match filepath.path().extension() {
-                        Some(extension) => {
-                            let extension_str = extension.to_str().unwrap();
-                            extension_str == "pak" || extension_str == "utoc"
-                        },
-                        None => false,
-                    };
-                }
-                false
-            }).map(|v| path.clone() + v.unwrap().file_name().to_str().unwrap()).collect()
-        },
+    let provider = match Path::new(path).is_dir() {
+        true => provider::FileProvider::from_keychain(path, &keychain)?,
         false => {
-            vec![path.clone()]
+            let mut provider = provider::FileProvider::empty();
+            provider.mount_with_keychain(path, &keychain)?;
+            provider
         },
     };
 
-    for path in paths {
-        let file_list: Vec<String> = match &path[(path.len() - 4)..] {
-            ".pak" => {
-                let archive = match archives::PakExtractor::new(&path, &key) {
-                    Ok(archive) => archive,
-                    Err(_) => continue,
-                };
-                let entries = archive.get_entries();
-                entries.iter().map(|v| v.get_filename().to_owned()).collect()
-            },
-            "utoc" => {
-                let dispatch = dispatch::Extractor::new(&path[..(path.len() - 5)], Some(&key))?;
-                dispatch.get_file_list().iter().map(|v| v.to_owned()).collect()
-            }
-            _ => return cerr("Unrecognised Extension"),
-        };
-        let file_str = file_list.iter().fold(String::new(), |acc, v| acc + v + "\n");
-        let mut file = fs::File::create(path.to_owned() + ".txt").unwrap();
+    print_skipped(&provider);
+
+    for container in provider.get_containers() {
+        let file_str = container.get_file_list().iter().fold(String::new(), |acc, v| acc + v + "\n");
+        let mut file = fs::File::create(config.get_output_file(container.get_path(), ".txt")?).unwrap();
         file.write_all(file_str.as_bytes()).unwrap();
     }
 
     Ok(())
 }
 
-fn idlist(params: &[String]) -> CommandResult {
+fn idlist(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
-    let key = match std::fs::read_to_string("key.txt") {
-        Ok(data) => data,
-        Err(_) => return cerr("Could not read key"),
-    };
+    let key = container_key(config, path)?;
     let dispatch = dispatch::Extractor::new(&path[..(path.len() - 5)], Some(&key))?;
 
     let file_list: Vec<String> = dispatch.get_chunk_ids().iter().map(|v| v.get_id().to_string()).collect();
     let file_str = file_list.iter().fold(String::new(), |acc, v| acc + v + "\n");
-    let mut file = fs::File::create(path.to_owned() + ".txt").unwrap();
+    let mut file = fs::File::create(config.get_output_file(path, ".txt")?).unwrap();
     file.write_all(file_str.as_bytes()).unwrap();
 
     Ok(())
 }
 
-fn read_header(params: &[String]) -> CommandResult {
+fn read_header(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
-    let key = match std::fs::read_to_string("key.txt") {
-        Ok(data) => data,
-        Err(_) => return cerr("Could not read key"),
-    };
+    let key = container_key(config, &(path.to_owned() + ".utoc"))?;
 
-    let mut dispatch = dispatch::Extractor::new(&path, Some(&key))?;
+    let dispatch = dispatch::Extractor::new(&path, Some(&key))?;
 
     let loader_data = match dispatch.read_chunk(0)? {
         ChunkData::ContainerHeader(data) => data,
@@ -263,37 +309,208 @@ fn read_header(params: &[String]) -> CommandResult {
     Ok(())
 }
 
-fn extract(params: &[String]) -> CommandResult {
+fn extract(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
     };
-    let key = match std::fs::read_to_string("key.txt") {
-        Ok(data) => data,
-        Err(_) => return cerr("Could not read key"),
-    };
+    let key = container_key(config, path)?;
     let pattern = match params.get(1) {
         Some(data) => data,
         None => return cerr("No pattern specified"),
     };
 
-    let mut archive = archives::PakExtractor::new(path, &key)?;
+    let archive = archives::PakExtractor::new(path, &key)?;
     let entries: Vec<archives::FPakEntry> = archive.get_entries().into_iter().filter(|v| v.get_filename().contains(pattern)).cloned().collect();
 
     for asset in entries {
-        let file_contents = archive.get_file(&asset);
-        let path = Path::new(asset.get_filename());
+        let file_contents = match archive.get_file(&asset) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Could not extract {}: {}", asset.get_filename(), e);
+                continue;
+            },
+        };
+        let path = config.get_output_path(asset.get_filename());
         if let Some(basename) = path.parent() {
             fs::create_dir_all(basename).expect("Could not create directory");
         }
-        let mut file = fs::File::create(asset.get_filename()).unwrap();
+        let mut file = fs::File::create(&path).unwrap();
         file.write_all(&file_contents).unwrap();
     }
 
     Ok(())
 }
 
-fn locale(params: &[String]) -> CommandResult {
+fn testkeys(params: &[String], config: &Config) -> CommandResult {
+    let path = match params.get(0) {
+        Some(data) => data,
+        None => return cerr("No path specified"),
+    };
+
+    // Candidates are any keys given on the command line, followed by keys.json and key.txt
+    let mut candidates: Vec<String> = params[1..].to_vec();
+    if let Ok(keychain) = load_keychain(config) {
+        candidates.extend(keychain.get_keys().values().cloned());
+    }
+    if candidates.is_empty() {
+        return cerr("No keys to test");
+    }
+    let candidates: Vec<&str> = candidates.iter().map(|v| v.as_ref()).collect();
+
+    let containers = match Path::new(path).is_dir() {
+        true => provider::find_containers(path)?,
+        false => vec![path.to_owned()],
+    };
+
+    for container in containers {
+        let guid = match keys::get_container_encryption(&container) {
+            Ok((guid, _)) => guid,
+            Err(e) => {
+                println!("{}: could not read header: {}", container, e);
+                continue;
+            },
+        };
+        match keys::find_container_key(&container, &candidates) {
+            Ok(keys::ContainerKey::NotEncrypted) => println!("{}: not encrypted", container),
+            Ok(keys::ContainerKey::Found(key)) => println!("{}: unlocked by {} (key GUID {})", container, key, guid),
+            Ok(keys::ContainerKey::NotFound) => println!("{}: no matching key (key GUID {})", container, guid),
+            Err(e) => println!("{}: could not test keys: {}", container, e),
+        }
+    }
+
+    Ok(())
+}
+
+fn verify(params: &[String], config: &Config) -> CommandResult {
+    let path = match params.get(0) {
+        Some(data) => data,
+        None => return cerr("No path specified"),
+    };
+
+    let containers = match Path::new(path).is_dir() {
+        true => provider::find_containers(path)?,
+        false => vec![path.to_owned()],
+    };
+    let keychain = load_optional_keychain(config)?;
+
+    let mut corrupt = 0;
+    for container in containers {
+        let key = match keychain.get_container_key(&container) {
+            Ok(Some(key)) => key.to_owned(),
+            Ok(None) => {
+                println!("{}: no key", container);
+                continue;
+            },
+            Err(e) => {
+                println!("{}: could not read header: {}", container, e);
+                continue;
+            },
+        };
+
+        let report = match verify::verify_container(&container, &key) {
+            Ok(report) => report,
+            Err(e) => {
+                println!("{}: could not verify: {}", container, e);
+                corrupt += 1;
+                continue;
+            },
+        };
+        println!("{}: {} checked, {} without hashes, {} failed", container, report.get_checked(), report.get_unhashed(), report.get_failures().len());
+        for failure in report.get_failures() {
+            println!("    {}: {}", failure.get_file(), failure.get_reason());
+        }
+        if !report.is_ok() {
+            corrupt += 1;
+        }
+    }
+
+    if corrupt > 0 {
+        return Err(CommandError {
+            message: format!("{} containers failed verification", corrupt),
+        });
+    }
+    Ok(())
+}
+
+/// Lists the containers a provider skipped, either for a missing key or because they could not be mounted
+fn print_skipped(provider: &provider::FileProvider) {
+    for (container, guid) in provider.get_missing_keys() {
+        println!("Skipped {}: no key for {}", container, guid);
+    }
+    for (container, error) in provider.get_failed_mounts() {
+        println!("Skipped {}: could not mount: {}", container, error);
+    }
+}
+
+fn load_graph(dir: &str, config: &Config) -> Result<graph::DependencyGraph, CommandError> {
+    let provider = provider::FileProvider::from_keychain(dir, &load_optional_keychain(config)?)?;
+    print_skipped(&provider);
+    Ok(graph::DependencyGraph::from_provider(&provider)?)
+}
+
+/// The graph format named by a file's extension
+fn graph_format(path: &str) -> Option<&str> {
+    match Path::new(path).extension().and_then(|v| v.to_str()) {
+        Some(ext @ ("dot" | "graphml" | "json")) => Some(ext),
+        _ => None,
+    }
+}
+
+fn depgraph(params: &[String], config: &Config) -> CommandResult {
+    // The game folder comes from the config when only an output is given, which needs a graph extension so a
+    // folder isn't mistaken for one
+    let (dir, output) = match (params.get(0), params.get(1)) {
+        (Some(dir), Some(output)) => (dir.as_ref(), output),
+        (Some(output), None) if graph_format(output).is_some() => (config.get_game_dir(), output),
+        (Some(_), None) => return cerr("Output must end in .dot, .graphml or .json when no paks dir is given"),
+        _ => return cerr("Usage: depgraph [paks dir] <output>"),
+    };
+
+    // The output's extension picks the format, falling back to the configured one
+    let format = graph_format(output).unwrap_or_else(|| config.get_graph_format());
+    let graph = load_graph(dir, config)?;
+    let data = match format {
+        "dot" => graph.to_dot(),
+        "graphml" => graph.to_graphml(),
+        "json" => serde_json::to_string(&graph).unwrap(),
+        _ => return cerr("Graph format must be dot, graphml or json"),
+    };
+    // Only relative outputs go in the output folder, as `get_output_path` treats a leading '/' as a container path
+    let output_path = match Path::new(output).is_absolute() {
+        true => PathBuf::from(output),
+        false => config.get_output_path(output),
+    };
+    if let Err(e) = fs::File::create(&output_path).and_then(|mut file| file.write_all(data.as_bytes())) {
+        return Err(CommandError {
+            message: format!("Could not write {}: {}", output_path.display(), e),
+        });
+    }
+
+    Ok(())
+}
+
+fn depends(params: &[String], config: &Config) -> CommandResult {
+    let (dir, package) = match (params.get(0), params.get(1)) {
+        (Some(dir), Some(package)) => (dir.as_ref(), package),
+        (Some(package), None) => (config.get_game_dir(), package),
+        _ => return cerr("Usage: depends [paks dir] <package name>"),
+    };
+
+    let graph = load_graph(dir, config)?;
+    println!("{} loads:", package);
+    for name in graph.get_dependencies_by_name(package) {
+        println!("    {}", name);
+    }
+    println!("{} is referenced by:", package);
+    for name in graph.get_dependents_by_name(package) {
+        println!("    {}", name);
+    }
+
+    Ok(())
+}
+
+fn locale(params: &[String], config: &Config) -> CommandResult {
     let path = match params.get(0) {
         Some(data) => data,
         None => return cerr("No path specified"),
@@ -307,15 +524,24 @@ fn locale(params: &[String]) -> CommandResult {
     locres.read_to_end(&mut locres_buf).unwrap();
 
     let package = assets::locale::FTextLocalizationResource::from_buffer(&locres_buf)?;
-    let serial_package = serde_json::to_string(&package).unwrap();
-    let mut file = fs::File::create(path.to_owned() + ".json").unwrap();
-    file.write_all(serial_package.as_bytes()).unwrap();
-
-    Ok(())
+    write_json(&package, path, config)
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (config, args) = match Config::from_args(&env::args().collect::<Vec<String>>()) {
+        Ok(data) => data,
+        Err(error) => {
+            println!("Error: {}", error);
+            return
+        },
+    };
+    if let Some(path) = config.get_oodle() {
+        if let Err(error) = decompress::oodle::load_library(path) {
+            println!("Error: {}", error);
+            return
+        }
+    }
+
     let command = args.get(1);
     let command = match command {
         Some(data) => data,
@@ -327,17 +553,21 @@ fn main() {
     let params = &args[2..];
 
     let err = match (*command).as_ref() {
-        "serialize" => serialize(params),
-        "filelist" => filelist(params),
-        "idlist" => idlist(params),
-        "extract" => extract(params),
-        "texture" => texture(params),
-        "locale" => locale(params),
-        "debug" => debug(params),
-        "sound" => sound(params),
-        "dispatch" => dispatch(params),
-        "manifest" => manifest(params),
-        "read_header" => read_header(params),
+        "serialize" => serialize(params, &config),
+        "filelist" => filelist(params, &config),
+        "idlist" => idlist(params, &config),
+        "extract" => extract(params, &config),
+        "texture" => texture(params, &config),
+        "locale" => locale(params, &config),
+        "debug" => debug(params, &config),
+        "sound" => sound(params, &config),
+        "dispatch" => dispatch(params, &config),
+        "manifest" => manifest(params, &config),
+        "read_header" => read_header(params, &config),
+        "testkeys" => testkeys(params, &config),
+        "verify" => verify(params, &config),
+        "depgraph" => depgraph(params, &config),
+        "depends" => depends(params, &config),
         _ => {
             println!("Invalid command");
             Ok(())
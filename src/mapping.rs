@@ -1,6 +1,7 @@
+use std::collections::HashMap;
 use std::io::Read;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use serde::Deserialize;
 use crate::assets::{ParserResult, ParserError, ParserType};
 
@@ -99,26 +100,35 @@ pub struct EnumMapping {
 pub struct MappingStore {
     class_mappings: Vec<ClassMapping>,
     enum_mappings: Vec<EnumMapping>,
+    class_index: HashMap<String, usize>,
+    enum_index: HashMap<String, usize>,
 }
 
-fn get_files(path: &str, file_ext: &str) -> ParserResult<Vec<String>> {
-    let path_dir = Path::new(path);
-    let mapping_files: Vec<String> = path_dir.read_dir()?.filter(|v| {
-        match v {
-            Ok(path) => match path.path().extension() {
-                Some(ext) => ext == file_ext,
-                None => false,
-            },
-            _ => false,
+fn get_files(path_dir: &Path, file_ext: &str) -> ParserResult<Vec<PathBuf>> {
+    // Missing folders are skipped, as a game may only have usmap files or only have json mappings
+    if !path_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mapping_files: Vec<PathBuf> = path_dir.read_dir()?.filter_map(|v| {
+        let path = v.ok()?.path();
+        match path.extension() {
+            Some(ext) if ext == file_ext => Some(path),
+            _ => None,
         }
-    }).map(|v| path_dir.to_str().unwrap().to_owned() + v.unwrap().file_name().to_str().unwrap()).collect();
+    }).collect();
 
     Ok(mapping_files)
 }
 
 impl MappingStore {
-    pub fn build_mappings() -> ParserResult<Self> {
-        let class_files = get_files("mappings/classes/", "json")?;
+    /// Loads the mappings in a folder: `.usmap` files, and json mappings in its `classes` and `enums` subfolders
+    pub fn from_dir(dir: &str) -> ParserResult<Self> {
+        let path_dir = Path::new(dir);
+        if !path_dir.is_dir() {
+            return Err(ParserError::new(format!("Mappings folder not found: {}", dir)));
+        }
+
+        let class_files = get_files(&path_dir.join("classes"), "json")?;
 
         let mut class_mappings = Vec::new();
         for file in class_files {
@@ -137,7 +147,7 @@ impl MappingStore {
             class_mappings.append(&mut store_mappings);
         }
 
-        let enum_files = get_files("mappings/enums/", "json")?;
+        let enum_files = get_files(&path_dir.join("enums"), "json")?;
         let mut enum_mappings = Vec::new();
         for file in enum_files {
             let mut file = File::open(file)?;
@@ -155,30 +165,44 @@ impl MappingStore {
             enum_mappings.append(&mut store_mappings);
         }
 
-        let usmap_files = get_files("mappings/", "usmap")?;
+        let usmap_files = get_files(path_dir, "usmap")?;
         for file in usmap_files {
             let (mut n_class_mappings, mut n_enum_mappings) = smrt::read_usmap(std::fs::read(file)?)?;
             class_mappings.append(&mut n_class_mappings);
             enum_mappings.append(&mut n_enum_mappings);
         }
 
-        Ok(Self {
+        Ok(Self::from_mappings(class_mappings, enum_mappings))
+    }
+
+    fn from_mappings(class_mappings: Vec<ClassMapping>, enum_mappings: Vec<EnumMapping>) -> Self {
+        // When a name is mapped more than once, the first mapping loaded is used
+        let mut class_index = HashMap::new();
+        for (i, mapping) in class_mappings.iter().enumerate() {
+            class_index.entry(mapping.name.clone()).or_insert(i);
+        }
+
+        let mut enum_index = HashMap::new();
+        for (i, mapping) in enum_mappings.iter().enumerate() {
+            enum_index.entry(mapping.name.clone()).or_insert(i);
+        }
+
+        Self {
             class_mappings,
             enum_mappings,
-        })
+            class_index,
+            enum_index,
+        }
     }
 
     pub fn empty() -> Self {
-        Self {
-            class_mappings: Vec::new(),
-            enum_mappings: Vec::new(),
-        }
+        Self::from_mappings(Vec::new(), Vec::new())
     }
 
     fn find_class_mapping(&self, class_name: &str) -> ParserResult<&ClassMapping> {
-        match self.class_mappings.iter().find(|v| v.name == class_name) {
-            Some(map) => Ok(map),
-            None => Err(ParserError::typed(format!("Class not found: {}", class_name), ParserType::ClassMappingMissing)),
+        match self.class_index.get(class_name) {
+            Some(idx) => Ok(&self.class_mappings[*idx]),
+            None => Err(ParserError::typed(format!("Class not found: {}", class_name), ParserType::MissingMapping)),
         }
     }
 
@@ -210,7 +234,7 @@ impl MappingStore {
         for index in &indices {
             let mapping = match properties.iter().find(|v| &v.index == index) {
                 Some(map) => map,
-                None => return Err(ParserError::typed(format!("Index not found: {}-{} of {:#?}", class_name, index, indices), ParserType::PropertyIndexMissing)),
+                None => return Err(ParserError::typed(format!("Index not found: {}-{} of {:#?}", class_name, index, indices), ParserType::MissingMapping)),
             };
             mappings.push(mapping.clone());
         }
@@ -220,8 +244,8 @@ impl MappingStore {
 
     pub fn get_enum_mapping(&self, enum_name: &Option<String>, idx: usize) -> Option<&str> {
         match enum_name {
-            Some(name) => match self.enum_mappings.iter().find(|v| &v.name == name) {
-                Some(mapping) => match mapping.values.get(idx) {
+            Some(name) => match self.enum_index.get(name) {
+                Some(mapping_idx) => match self.enum_mappings[*mapping_idx].values.get(idx) {
                     Some(val) => Some(val),
                     None => None,
                 },
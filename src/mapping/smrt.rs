@@ -1,8 +1,8 @@
 use std::io::{Read, Cursor};
 use byteorder::{LittleEndian, ReadBytesExt};
 use crate::mapping::{ClassMapping, EnumMapping, PropertyMapping, TagMapping};
-use crate::assets::{Newable, ReaderCursor, ParserResult, ParserError};
-use crate::decompress::oodle;
+use crate::assets::{Newable, ReaderCursor, ParserResult, ParserError, ParserType};
+use crate::decompress;
 
 const USMAP_MAGIC: u16 = 0x30C4;
 const USMAP_HEADER_SIZE: usize = 12;
@@ -13,6 +13,17 @@ enum CompressionMethod {
     Brotli,
 }
 
+impl CompressionMethod {
+    /// The name the method's codec is registered under
+    fn get_name(&self) -> &'static str {
+        match self {
+            CompressionMethod::None => "None",
+            CompressionMethod::Oodle => "Oodle",
+            CompressionMethod::Brotli => "Brotli",
+        }
+    }
+}
+
 struct UsmapHeader {
     version: u8,
     method: CompressionMethod, 
@@ -24,7 +35,7 @@ impl Newable for UsmapHeader {
     fn new(reader: &mut ReaderCursor) -> ParserResult<Self> {
         let magic = reader.read_u16::<LittleEndian>()?;
         if magic != USMAP_MAGIC {
-            return Err(ParserError::new(format!("Magic does not match usmap.")));
+            return Err(ParserError::typed("Magic does not match usmap.".to_owned(), ParserType::Malformed));
         }
 
         let version = reader.read_u8()?;
@@ -33,7 +44,7 @@ impl Newable for UsmapHeader {
             0 => CompressionMethod::None,
             1 => CompressionMethod::Oodle,
             2 => CompressionMethod::Brotli,
-            _ => return Err(ParserError::new(format!("Unknown compression method"))),
+            _ => return Err(ParserError::typed("Unknown compression method".to_owned(), ParserType::Decompression)),
         };
 
         let comp_size = reader.read_u32::<LittleEndian>()?;
@@ -50,10 +61,7 @@ fn get_usmap_data(mut data: Vec<u8>, header: &UsmapHeader) -> ParserResult<Vec<u
         CompressionMethod::None => {
             Ok(data)
         },
-        CompressionMethod::Oodle => {
-            Ok(oodle::decompress_stream(header.size as u64, data.as_slice())?)
-        },
-        _ => return Err(ParserError::new(format!("Unsupported Compression Method"))),
+        _ => decompress::decompress(header.method.get_name(), &data, header.size as usize),
     }
 }
 
@@ -119,7 +127,7 @@ fn read_usmap_prop(reader: &mut ReaderCursor, name_map: &Vec<String>) -> ParserR
             TagMapping::EnumProperty { enum_name }
         },
         27 => TagMapping::FieldPathProperty,
-        _ => return Err(ParserError::new(format!("Unknown Property Type"))),
+        _ => return Err(ParserError::typed("Unknown Property Type".to_owned(), ParserType::UnsupportedProperty)),
     })
 }
 
@@ -197,4 +205,50 @@ pub(super) fn read_usmap(data: Vec<u8>) -> ParserResult<(Vec<ClassMapping>, Vec<
 
 
     Ok((class_mappings, enum_mappings))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A brotli stream holding `data` in a single uncompressed meta-block, followed by an empty last one
+    fn brotli_stored(data: &[u8]) -> Vec<u8> {
+        let mut stream = ((((data.len() - 1) as u32) << 4) | (1 << 20)).to_le_bytes()[..3].to_vec();
+        stream.extend_from_slice(data);
+        stream.push(0x03);
+        stream
+    }
+
+    #[test]
+    fn brotli_usmap() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&3u32.to_le_bytes());
+        for name in ["EColor", "Red", "Green"] {
+            body.push(name.len() as u8);
+            body.extend_from_slice(name.as_bytes());
+        }
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.push(2);
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body.extend_from_slice(&2u32.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+
+        let compressed = brotli_stored(&body);
+        let mut data = vec![0xC4, 0x30, 0, 2];
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend(compressed);
+
+        let (classes, enums) = read_usmap(data.clone()).unwrap();
+        assert!(classes.is_empty());
+        assert_eq!(enums.len(), 1);
+        assert_eq!(enums[0].name, "EColor");
+        assert_eq!(enums[0].values, ["Red", "Green"]);
+
+        // A body that is shorter than the header says fails in the codec
+        let size = body.len() as u32 + 1;
+        data[8..12].copy_from_slice(&size.to_le_bytes());
+        let error = read_usmap(data).unwrap_err();
+        assert_eq!(*error.get_type(), ParserType::Decompression);
+    }
+}
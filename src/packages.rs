@@ -0,0 +1,324 @@
+//! Resolving package ids back to package names
+//!
+//! IoStore refers to packages by id, which is the CityHash64 of the lowercased package name (`/Game/Athena/Foo`) as UTF-16.
+//! Hashing the names of every package in a set of containers gives a map back from id to name.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use crate::assets::{Newable, ParserResult};
+use crate::dispatch::{ChunkData, EIoChunkType, Extractor, FNameMap};
+use crate::provider::{FileProvider, Container};
+
+const K0: u64 = 0xc3a5c85c97cb3127;
+const K1: u64 = 0xb492b66fbe98f273;
+const K2: u64 = 0x9ae16a3b2f90404f;
+const K_MUL: u64 = 0x9ddfea08eb382d69;
+
+fn fetch_64(s: &[u8], pos: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&s[pos..(pos + 8)]);
+    u64::from_le_bytes(buf)
+}
+
+fn fetch_32(s: &[u8], pos: usize) -> u64 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&s[pos..(pos + 4)]);
+    u32::from_le_bytes(buf) as u64
+}
+
+fn shift_mix(val: u64) -> u64 {
+    val ^ (val >> 47)
+}
+
+fn hash_len_16_mul(u: u64, v: u64, mul: u64) -> u64 {
+    let mut a = (u ^ v).wrapping_mul(mul);
+    a ^= a >> 47;
+    let mut b = (v ^ a).wrapping_mul(mul);
+    b ^= b >> 47;
+    b.wrapping_mul(mul)
+}
+
+fn hash_len_16(u: u64, v: u64) -> u64 {
+    hash_len_16_mul(u, v, K_MUL)
+}
+
+fn hash_len_0_to_16(s: &[u8]) -> u64 {
+    let len = s.len();
+    if len >= 8 {
+        let mul = K2.wrapping_add(len as u64 * 2);
+        let a = fetch_64(s, 0).wrapping_add(K2);
+        let b = fetch_64(s, len - 8);
+        let c = b.rotate_right(37).wrapping_mul(mul).wrapping_add(a);
+        let d = a.rotate_right(25).wrapping_add(b).wrapping_mul(mul);
+        return hash_len_16_mul(c, d, mul);
+    }
+    if len >= 4 {
+        let mul = K2.wrapping_add(len as u64 * 2);
+        let a = fetch_32(s, 0);
+        return hash_len_16_mul((len as u64).wrapping_add(a << 3), fetch_32(s, len - 4), mul);
+    }
+    if len > 0 {
+        let y = s[0] as u32 + ((s[len >> 1] as u32) << 8);
+        let z = len as u32 + ((s[len - 1] as u32) << 2);
+        return shift_mix((y as u64).wrapping_mul(K2) ^ (z as u64).wrapping_mul(K0)).wrapping_mul(K2);
+    }
+    K2
+}
+
+fn hash_len_17_to_32(s: &[u8]) -> u64 {
+    let len = s.len();
+    let mul = K2.wrapping_add(len as u64 * 2);
+    let a = fetch_64(s, 0).wrapping_mul(K1);
+    let b = fetch_64(s, 8);
+    let c = fetch_64(s, len - 8).wrapping_mul(mul);
+    let d = fetch_64(s, len - 16).wrapping_mul(K2);
+    hash_len_16_mul(
+        a.wrapping_add(b).rotate_right(43).wrapping_add(c.rotate_right(30)).wrapping_add(d),
+        a.wrapping_add(b.wrapping_add(K2).rotate_right(18)).wrapping_add(c),
+        mul,
+    )
+}
+
+fn hash_len_33_to_64(s: &[u8]) -> u64 {
+    let len = s.len();
+    let mul = K2.wrapping_add(len as u64 * 2);
+    let a = fetch_64(s, 0).wrapping_mul(K2);
+    let b = fetch_64(s, 8);
+    let c = fetch_64(s, len - 24);
+    let d = fetch_64(s, len - 32);
+    let e = fetch_64(s, 16).wrapping_mul(K2);
+    let f = fetch_64(s, 24).wrapping_mul(9);
+    let g = fetch_64(s, len - 8);
+    let h = fetch_64(s, len - 16).wrapping_mul(mul);
+    let u = a.wrapping_add(g).rotate_right(43).wrapping_add(b.rotate_right(30).wrapping_add(c).wrapping_mul(9));
+    let v = (a.wrapping_add(g) ^ d).wrapping_add(f).wrapping_add(1);
+    let w = u.wrapping_add(v).wrapping_mul(mul).swap_bytes().wrapping_add(h);
+    let x = e.wrapping_add(f).rotate_right(42).wrapping_add(c);
+    let y = v.wrapping_add(w).wrapping_mul(mul).swap_bytes().wrapping_add(g).wrapping_mul(mul);
+    let z = e.wrapping_add(f).wrapping_add(c);
+    let a = x.wrapping_add(z).wrapping_mul(mul).wrapping_add(y).swap_bytes().wrapping_add(b);
+    let b = shift_mix(z.wrapping_add(a).wrapping_mul(mul).wrapping_add(d).wrapping_add(h)).wrapping_mul(mul);
+    b.wrapping_add(x)
+}
+
+fn weak_hash_len_32_with_seeds(s: &[u8], pos: usize, a: u64, b: u64) -> (u64, u64) {
+    let w = fetch_64(s, pos);
+    let x = fetch_64(s, pos + 8);
+    let y = fetch_64(s, pos + 16);
+    let z = fetch_64(s, pos + 24);
+    let mut a = a.wrapping_add(w);
+    let mut b = b.wrapping_add(a).wrapping_add(z).rotate_right(21);
+    let c = a;
+    a = a.wrapping_add(x).wrapping_add(y);
+    b = b.wrapping_add(a.rotate_right(44));
+    (a.wrapping_add(z), b.wrapping_add(c))
+}
+
+/// CityHash64, as of CityHash 1.1, which is the version the engine uses
+pub fn city_hash_64(s: &[u8]) -> u64 {
+    let len = s.len();
+    if len <= 16 {
+        return hash_len_0_to_16(s);
+    }
+    if len <= 32 {
+        return hash_len_17_to_32(s);
+    }
+    if len <= 64 {
+        return hash_len_33_to_64(s);
+    }
+
+    // Longer strings are hashed from the end first, then in 64 byte chunks from the start
+    let mut x = fetch_64(s, len - 40);
+    let mut y = fetch_64(s, len - 16).wrapping_add(fetch_64(s, len - 56));
+    let mut z = hash_len_16(fetch_64(s, len - 48).wrapping_add(len as u64), fetch_64(s, len - 24));
+    let mut v = weak_hash_len_32_with_seeds(s, len - 64, len as u64, z);
+    let mut w = weak_hash_len_32_with_seeds(s, len - 32, y.wrapping_add(K1), x);
+    x = x.wrapping_mul(K1).wrapping_add(fetch_64(s, 0));
+
+    let mut pos = 0;
+    let mut remaining = (len - 1) & !63;
+    while remaining != 0 {
+        x = x.wrapping_add(y).wrapping_add(v.0).wrapping_add(fetch_64(s, pos + 8)).rotate_right(37).wrapping_mul(K1);
+        y = y.wrapping_add(v.1).wrapping_add(fetch_64(s, pos + 48)).rotate_right(42).wrapping_mul(K1);
+        x ^= w.1;
+        y = y.wrapping_add(v.0).wrapping_add(fetch_64(s, pos + 40));
+        z = z.wrapping_add(w.0).rotate_right(33).wrapping_mul(K1);
+        v = weak_hash_len_32_with_seeds(s, pos, v.1.wrapping_mul(K1), x.wrapping_add(w.0));
+        w = weak_hash_len_32_with_seeds(s, pos + 32, z.wrapping_add(w.1), y.wrapping_add(fetch_64(s, pos + 16)));
+        std::mem::swap(&mut z, &mut x);
+        pos += 64;
+        remaining -= 64;
+    }
+
+    hash_len_16(
+        hash_len_16(v.0, w.0).wrapping_add(shift_mix(y).wrapping_mul(K1)).wrapping_add(z),
+        hash_len_16(v.1, w.1).wrapping_add(x),
+    )
+}
+
+/// Works out the id of a package from its name, such as `/Game/Athena/Foo`
+pub fn get_package_id(package_name: &str) -> u64 {
+    let bytes: Vec<u8> = package_name.to_lowercase().encode_utf16().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    city_hash_64(&bytes)
+}
+
+/// Works out the name of the package stored at a game path, such as `FortniteGame/Content/Athena/Foo.uasset`
+///
+/// The project's content is mounted at `/Game`, the engine's at `/Engine`, and each plugin's under its own name.
+/// Returns `None` for files that aren't packages or aren't inside a `Content` directory.
+pub fn get_package_name(game_path: &str) -> Option<String> {
+    let path = game_path.strip_suffix(".uasset").or_else(|| game_path.strip_suffix(".umap"))?;
+    let idx = path.find("/Content/")?;
+    let root = &path[..idx];
+    let mount = match root.rfind('/') {
+        Some(idx) => &root[(idx + 1)..],
+        None if root == "Engine" => "Engine",
+        None => "Game",
+    };
+    Some(format!("/{}/{}", mount, &path[(idx + 9)..]))
+}
+
+/// Maps package ids back to package names, for every package in a set of containers
+#[derive(Debug, Default)]
+pub struct PackageIdResolver {
+    names: HashMap<u64, String>,
+}
+
+impl PackageIdResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a resolver from every container mounted in a provider
+    pub fn from_provider(provider: &FileProvider) -> ParserResult<Self> {
+        let mut resolver = Self::new();
+        for container in provider.get_containers() {
+            for path in container.get_file_list() {
+                resolver.add_game_path(path);
+            }
+            if let Container::IoStore(dispatch) = container.get_container() {
+                resolver.add_container_header(dispatch)?;
+            }
+        }
+        Ok(resolver)
+    }
+
+    /// Adds a package by name, returning its id
+    pub fn add_name(&mut self, package_name: &str) -> u64 {
+        let id = get_package_id(package_name);
+        self.names.insert(id, package_name.to_owned());
+        id
+    }
+
+    /// Adds the package stored at a game path, if it is one
+    pub fn add_game_path(&mut self, game_path: &str) -> Option<u64> {
+        get_package_name(game_path).map(|v| self.add_name(&v))
+    }
+
+    /// Adds the packages of an IoStore container, using its mount point to work out their names
+    pub fn add_container(&mut self, dispatch: &Extractor) -> ParserResult<()> {
+        let mount_point = dispatch.get_mount_point().trim_start_matches("../").trim_start_matches('/').to_owned();
+        for file in dispatch.get_file_list().iter().filter(|v| !v.is_empty()) {
+            self.add_game_path(&(mount_point.clone() + file));
+        }
+        self.add_container_header(dispatch)
+    }
+
+    /// Adds the names held by a container's header, which are the names of its localised and redirected packages
+    fn add_container_header(&mut self, dispatch: &Extractor) -> ParserResult<()> {
+        let idx = match dispatch.get_chunk_ids().iter().position(|v| v.get_type() == EIoChunkType::ContainerHeader) {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        let header = match dispatch.read_chunk(idx)? {
+            ChunkData::ContainerHeader(header) => header,
+            _ => return Ok(()),
+        };
+
+        let mut reader = Cursor::new(header.get_names());
+        let names = FNameMap::new(&mut reader)?;
+        for name in names.get_names().iter().filter(|v| v.starts_with('/')) {
+            self.add_name(name);
+        }
+        Ok(())
+    }
+
+    pub fn resolve(&self, id: u64) -> Option<&str> {
+        self.names.get(&id).map(|v| v.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The data the CityHash test suite hashes slices of
+    fn city_test_data(size: usize) -> Vec<u8> {
+        let (mut a, mut b) = (9u64, 777u64);
+        (0..size).map(|i| {
+            a = a.wrapping_add(b);
+            b = b.wrapping_add(a);
+            a = (a ^ (a >> 41)).wrapping_mul(K0);
+            b = (b ^ (b >> 41)).wrapping_mul(K0).wrapping_add(i as u64);
+            (b >> 37) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn city_hash() {
+        // CityHash64 of `data[i * i..][..i]`, from the CityHash 1.1 test suite, covering each length range
+        let expected: [(usize, u64); 13] = [
+            (0, 0x9ae16a3b2f90404f),
+            (1, 0x541150e87f415e96),
+            (3, 0xef923a7a1af78eab),
+            (5, 0x831f448bdc5600b3),
+            (7, 0x1b5a063fb4c7f9f1),
+            (12, 0xe3f6828b6017086d),
+            (16, 0x03ead5f21d344056),
+            (20, 0x4182832b52d63735),
+            (32, 0x0782fa1b08b475e7),
+            (40, 0x4ec0b54cf1566aff),
+            (64, 0xe88419922b87176f),
+            (100, 0x6369163565814de6),
+            (256, 0x2b67cdd38c307a5e),
+        ];
+        let data = city_test_data(257 * 256);
+        for (i, hash) in expected.iter() {
+            assert_eq!(city_hash_64(&data[(i * i)..(i * i + i)]), *hash, "length {}", i);
+        }
+    }
+
+    #[test]
+    fn package_ids() {
+        // Names are hashed lowercased, as UTF-16. The id is CityHash 1.1 of those bytes, not one read from a container.
+        let bytes: Vec<u8> = "/game/athena/athena_terrain".bytes().flat_map(|v| [v, 0]).collect();
+        assert_eq!(get_package_id("/Game/Athena/Athena_Terrain"), city_hash_64(&bytes));
+        assert_eq!(get_package_id("/Game/Athena/Athena_Terrain"), 0xc123c908265810a5);
+        assert_eq!(get_package_id("/GAME/ATHENA/ATHENA_TERRAIN"), 0xc123c908265810a5);
+    }
+
+    #[test]
+    fn package_names() {
+        let cases = [
+            ("FortniteGame/Content/Athena/Foo.uasset", Some("/Game/Athena/Foo")),
+            ("FortniteGame/Content/Maps/Map.umap", Some("/Game/Maps/Map")),
+            ("Engine/Content/BasicShapes/Cube.uasset", Some("/Engine/BasicShapes/Cube")),
+            ("FortniteGame/Plugins/GameFeatures/Ranked/Content/UI/Icon.uasset", Some("/Ranked/UI/Icon")),
+            ("Engine/Plugins/Runtime/Niagara/Content/Default.uasset", Some("/Niagara/Default")),
+            ("FortniteGame/Content/Athena/Foo.uexp", None),
+            ("FortniteGame/Content/Localization/Game/en/Game.locres", None),
+            ("FortniteGame/Config/Foo.uasset", None),
+        ];
+        for (path, name) in cases.iter() {
+            assert_eq!(get_package_name(path).as_deref(), *name, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use crate::archives::PakExtractor;
+use crate::cache::BlockCache;
+use crate::source::SourceMode;
+use crate::dispatch::Extractor;
+use crate::keys::{Keychain, get_container_encryption};
+use crate::assets::{FGuid, ParserResult, ParserError};
+
+/// The backing archive for a mounted container
+pub enum Container {
+    Pak(PakExtractor),
+    IoStore(Extractor),
+}
+
+/// A single `.pak` or `.utoc`/`.ucas` container mounted into a `FileProvider`
+pub struct MountedContainer {
+    path: String,
+    container: Container,
+    files: Vec<String>,
+    priority: i32,
+}
+
+impl MountedContainer {
+    fn new(path: &str, key: &str, priority: i32, cache: &Arc<BlockCache>, mode: SourceMode) -> ParserResult<Self> {
+        let (container, files) = match Path::new(path).extension().and_then(|v| v.to_str()) {
+            Some("pak") => {
+                let archive = PakExtractor::new_with_mode(path, key, mode)?;
+                let mount_point = archive.get_mount_point().to_owned();
+                let files = archive.get_entries().iter().map(|v| get_game_path(&mount_point, v.get_filename())).collect();
+                (Container::Pak(archive), files)
+            },
+            Some("utoc") => {
+                let dispatch = Extractor::new_with_options(&path[..(path.len() - 5)], Some(key), Arc::clone(cache), mode)?;
+                let mount_point = dispatch.get_mount_point().to_owned();
+                let files = dispatch.get_file_list().iter().map(|v| match v.len() {
+                    0 => "".to_owned(),
+                    _ => get_game_path(&mount_point, v),
+                }).collect();
+                (Container::IoStore(dispatch), files)
+            },
+            _ => return Err(ParserError::new(format!("Unrecognised container: {}", path))),
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            container,
+            files,
+            priority,
+        })
+    }
+
+    /// The path on disk this container was mounted from
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_container(&self) -> &Container {
+        &self.container
+    }
+
+    /// Containers with a higher priority override files from those with a lower one
+    pub fn get_priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// Lists the game paths of every file in this container, with the mount point applied
+    pub fn get_file_list(&self) -> Vec<&str> {
+        self.files.iter().filter(|v| !v.is_empty()).map(|v| v.as_ref()).collect()
+    }
+
+    fn read(&self, entry: usize) -> ParserResult<Vec<u8>> {
+        match &self.container {
+            Container::Pak(archive) => {
+                let file = archive.get_entries()[entry].clone();
+                archive.get_file(&file)
+            },
+            Container::IoStore(dispatch) => {
+                let file = dispatch.get_file_list()[entry].clone();
+                dispatch.get_file(&file)
+            },
+        }
+    }
+}
+
+/// Joins a container's mount point with one of its filenames, giving a path relative to the game root
+fn get_game_path(mount_point: &str, filename: &str) -> String {
+    let path = mount_point.to_owned() + filename;
+    path.trim_start_matches("../").trim_start_matches('/').to_owned()
+}
+
+/// Works out a container's priority from its filename, following the engine's patch ordering
+///
+/// Base containers have a priority of 0. Patch containers (`pakchunk0_s1-WindowsClient_P.pak`) are ordered above them,
+/// with a numbered patch (`..._2_P.pak`) taking priority over earlier patches.
+pub fn get_container_priority(path: &str) -> i32 {
+    let stem = match Path::new(path).file_stem().and_then(|v| v.to_str()) {
+        Some(stem) => stem,
+        None => return 0,
+    };
+
+    if !stem.ends_with("_P") {
+        return 0;
+    }
+
+    // Default to version 1 for single patch containers
+    let stripped = &stem[..(stem.len() - 2)];
+    let version = match stripped.rfind('_') {
+        Some(idx) => match stripped[(idx + 1)..].parse::<i32>() {
+            // Patch versions start at 2 so that the first numbered patch still beats an unnumbered one
+            Ok(version) if version >= 1 => version + 1,
+            _ => 1,
+        },
+        None => 1,
+    };
+
+    100 * version
+}
+
+/// Lists the `.pak` and `.utoc` containers in a directory, in a stable mount order
+pub fn find_containers(dir: &str) -> ParserResult<Vec<String>> {
+    let mut paths: Vec<String> = Path::new(dir).read_dir()?.filter_map(|v| {
+        let path = v.ok()?.path();
+        match path.extension()?.to_str()? {
+            "pak" | "utoc" => Some(path.to_str()?.to_owned()),
+            _ => None,
+        }
+    }).collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+struct FileLocation {
+    container: usize,
+    entry: usize,
+}
+
+/// FileProvider mounts any number of `.pak` and IoStore containers and presents them as one file system
+///
+/// Paths are given relative to the game root, eg. `FortniteGame/Content/Athena/Items/Cosmetics/Foo.uasset`, and are matched case-insensitively.
+///
+/// When a path exists in more than one container, the copy in the container with the highest priority wins (see `get_container_priority`).
+/// If the priorities are equal, the container mounted first wins, as it does in the engine.
+///
+/// Decoded blocks are cached in a single `BlockCache` shared by every container.
+pub struct FileProvider {
+    containers: Vec<MountedContainer>,
+    files: HashMap<String, Vec<FileLocation>>,
+    cache: Arc<BlockCache>,
+    mode: SourceMode,
+    missing_keys: Vec<(String, FGuid)>,
+    failed_mounts: Vec<(String, ParserError)>,
+}
+
+impl FileProvider {
+    pub fn empty() -> Self {
+        Self::with_cache(Arc::new(BlockCache::default()))
+    }
+
+    /// Creates an empty provider that caches decoded blocks in an existing `BlockCache`
+    pub fn with_cache(cache: Arc<BlockCache>) -> Self {
+        Self {
+            containers: Vec::new(),
+            files: HashMap::new(),
+            cache,
+            mode: SourceMode::File,
+            missing_keys: Vec::new(),
+            failed_mounts: Vec::new(),
+        }
+    }
+
+    /// Chooses how containers mounted after this call are read from disk
+    pub fn set_source_mode(&mut self, mode: SourceMode) {
+        self.mode = mode;
+    }
+
+    /// Mounts every `.pak` and `.utoc` container found in a game's `Paks/` directory
+    ///
+    /// Containers that can not be opened with the given key are skipped, and listed by `get_failed_mounts`.
+    pub fn new(dir: &str, key: &str) -> ParserResult<Self> {
+        let mut provider = Self::empty();
+        for path in find_containers(dir)? {
+            if let Err(e) = provider.mount(&path, key) {
+                provider.failed_mounts.push((path, e));
+            }
+        }
+
+        Ok(provider)
+    }
+
+    /// Mounts every container found in a game's `Paks/` directory, picking each one's key from a keychain
+    ///
+    /// Containers encrypted with a key the keychain doesn't hold are skipped, and listed by `get_missing_keys`. Those that
+    /// could not be read are listed by `get_failed_mounts`.
+    pub fn from_keychain(dir: &str, keychain: &Keychain) -> ParserResult<Self> {
+        let mut provider = Self::empty();
+        for path in find_containers(dir)? {
+            if let Err(e) = provider.mount_with_keychain(&path, keychain) {
+                provider.failed_mounts.push((path, e));
+            }
+        }
+
+        Ok(provider)
+    }
+
+    /// Mounts a single `.pak` or `.utoc` container, with the priority given by its filename
+    pub fn mount(&mut self, path: &str, key: &str) -> ParserResult<()> {
+        self.mount_with_priority(path, key, get_container_priority(path))
+    }
+
+    /// Mounts a single container using the key its header names, returning `false` if the keychain doesn't hold that key
+    pub fn mount_with_keychain(&mut self, path: &str, keychain: &Keychain) -> ParserResult<bool> {
+        let (guid, encrypted) = get_container_encryption(path)?;
+        let key = match keychain.get_key(&guid) {
+            Some(key) => key,
+            None if !encrypted => "",
+            None => {
+                self.missing_keys.push((path.to_owned(), guid));
+                return Ok(false);
+            },
+        };
+
+        self.mount(path, key)?;
+        Ok(true)
+    }
+
+    /// Lists the containers that were skipped because their key wasn't in the keychain, with the GUID of the key they need
+    pub fn get_missing_keys(&self) -> &Vec<(String, FGuid)> {
+        &self.missing_keys
+    }
+
+    /// Lists the containers that `new` or `from_keychain` skipped because they could not be mounted, with the error
+    pub fn get_failed_mounts(&self) -> &Vec<(String, ParserError)> {
+        &self.failed_mounts
+    }
+
+    /// Mounts a single `.pak` or `.utoc` container with an explicit priority
+    pub fn mount_with_priority(&mut self, path: &str, key: &str, priority: i32) -> ParserResult<()> {
+        let container = MountedContainer::new(path, key, priority, &self.cache, self.mode)?;
+        let container_idx = self.containers.len();
+        for (entry, file) in container.files.iter().enumerate() {
+            if file.is_empty() {
+                continue;
+            }
+            let versions = self.files.entry(file.to_lowercase()).or_default();
+            let containers = &self.containers;
+            let position = versions.iter().position(|v| containers[v.container].priority < priority).unwrap_or(versions.len());
+            versions.insert(position, FileLocation {
+                container: container_idx,
+                entry,
+            });
+        }
+        self.containers.push(container);
+
+        Ok(())
+    }
+
+    pub fn get_containers(&self) -> &Vec<MountedContainer> {
+        &self.containers
+    }
+
+    /// Checks whether any mounted container holds the file
+    pub fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(&path.to_lowercase())
+    }
+
+    /// Extracts the highest priority copy of a file
+    pub fn read(&self, path: &str) -> ParserResult<Vec<u8>> {
+        self.read_version(path, 0)
+    }
+
+    /// Lists every container holding a copy of the file, from highest priority to lowest
+    ///
+    /// The first container is the one `read` extracts from, any others are shadowed by it.
+    pub fn get_versions(&self, path: &str) -> Vec<&MountedContainer> {
+        match self.files.get(&path.to_lowercase()) {
+            Some(versions) => versions.iter().map(|v| &self.containers[v.container]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Extracts a specific copy of a file, using the order given by `get_versions`
+    pub fn read_version(&self, path: &str, version: usize) -> ParserResult<Vec<u8>> {
+        let location = match self.files.get(&path.to_lowercase()).and_then(|v| v.get(version)) {
+            Some(location) => location,
+            None => return Err(ParserError::new(format!("Could not find file: {} (version {})", path, version))),
+        };
+        self.containers[location.container].read(location.entry)
+    }
+
+    /// Lists every file across all mounted containers
+    pub fn list(&self) -> Vec<&str> {
+        self.files.values().map(|v| self.containers[v[0].container].files[v[0].entry].as_ref()).collect()
+    }
+}
\ No newline at end of file
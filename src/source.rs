@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::sync::Arc;
+use memmap2::Mmap;
+use crate::assets::{ParserResult, ParserError, ParserType};
+
+/// How the files making up a container are read from disk
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SourceMode {
+    /// Positional reads through a file handle
+    #[default]
+    File,
+    /// Memory map the whole file, so reads are copies out of the mapping
+    Mapped,
+}
+
+/// ContainerSource is a `.pak`, `.utoc` or `.ucas` file opened for random access
+///
+/// Reads never move a shared cursor, so a single source can be read from any number of threads at once.
+pub enum ContainerSource {
+    File(File),
+    Mapped(Arc<Mmap>),
+}
+
+impl ContainerSource {
+    pub fn open(path: &str, mode: SourceMode) -> ParserResult<Self> {
+        let file = File::open(path).map_err(|v| ParserError::typed(format!("Could not open {}: {}", path, v), ParserType::Io).with_source(v))?;
+        Ok(match mode {
+            SourceMode::File => ContainerSource::File(file),
+            // Containers are never written to while the game data is being read
+            SourceMode::Mapped => ContainerSource::Mapped(Arc::new(unsafe { Mmap::map(&file)? })),
+        })
+    }
+
+    /// Gets the size of the underlying file
+    pub fn get_size(&self) -> ParserResult<u64> {
+        match self {
+            ContainerSource::File(file) => Ok(file.metadata()?.len()),
+            ContainerSource::Mapped(map) => Ok(map.len() as u64),
+        }
+    }
+
+    /// Fills `buf` with the bytes starting at `offset`
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> ParserResult<()> {
+        match self {
+            ContainerSource::File(file) => read_file_at(file, offset, buf)?,
+            ContainerSource::Mapped(map) => {
+                let start = offset as usize;
+                match map.get(start..(start + buf.len())) {
+                    Some(data) => buf.copy_from_slice(data),
+                    None => return Err(ParserError::typed(format!("Read past end of file: {} + {}", offset, buf.len()), ParserType::Io)),
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn read_vec(&self, offset: u64, length: usize) -> ParserResult<Vec<u8>> {
+        let mut buf = vec![0u8; length];
+        self.read_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(unix)]
+fn read_file_at(file: &File, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_file_at(file: &File, mut offset: u64, mut buf: &mut [u8]) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    // seek_read can return short reads, and moves the file cursor, which nothing else relies on
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
\ No newline at end of file
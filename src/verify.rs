@@ -0,0 +1,177 @@
+//! Integrity checks for containers, against the hashes stored in them
+//!
+//! Pak entries are checked against their SHA1 hashes and the pak against its `.sig` file when there is one.
+//! IoStore containers are checked block by block, which is only possible for signed containers.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::archives::{PakExtractor, FPakSignatureFile};
+use crate::dispatch::Extractor;
+use crate::assets::{ParserResult, ParserError};
+
+/// A file in a container that doesn't match its hash
+#[derive(Debug, Clone)]
+pub struct IntegrityFailure {
+    file: String,
+    reason: String,
+}
+
+impl IntegrityFailure {
+    fn new(file: &str, reason: String) -> Self {
+        Self {
+            file: file.to_owned(),
+            reason,
+        }
+    }
+
+    pub fn get_file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// The outcome of verifying a single container
+#[derive(Debug, Clone)]
+pub struct IntegrityReport {
+    path: String,
+    checked: usize,
+    unhashed: usize,
+    failures: Vec<IntegrityFailure>,
+}
+
+impl IntegrityReport {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            checked: 0,
+            unhashed: 0,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Records the outcome of one hash check, from a `check_*` function
+    fn record(&mut self, name: &str, result: ParserResult<Option<bool>>) {
+        match result {
+            Ok(Some(true)) => self.checked += 1,
+            Ok(Some(false)) => {
+                self.checked += 1;
+                self.failures.push(IntegrityFailure::new(name, "Hash mismatch".to_owned()));
+            },
+            Ok(None) => self.unhashed += 1,
+            Err(e) => self.failures.push(IntegrityFailure::new(name, format!("Could not be checked: {}", e))),
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// The number of entries or blocks that were checked against a hash
+    pub fn get_checked(&self) -> usize {
+        self.checked
+    }
+
+    /// The number of entries or blocks that have no hash to check against
+    pub fn get_unhashed(&self) -> usize {
+        self.unhashed
+    }
+
+    pub fn get_failures(&self) -> &[IntegrityFailure] {
+        &self.failures
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Verifies a `.pak` or `.utoc` container, by its extension
+pub fn verify_container(path: &str, key: &str) -> ParserResult<IntegrityReport> {
+    match Path::new(path).extension().and_then(|v| v.to_str()) {
+        Some("pak") => verify_pak(path, key),
+        Some("utoc") => verify_utoc(&path[..(path.len() - 5)], key),
+        _ => Err(ParserError::new(format!("Unrecognised container: {}", path))),
+    }
+}
+
+/// Verifies the index and every entry of a `.pak`, and the `.sig` file next to it if there is one
+pub fn verify_pak(path: &str, key: &str) -> ParserResult<IntegrityReport> {
+    let archive = PakExtractor::new(path, key)?;
+    let mut report = IntegrityReport::new(path);
+
+    report.record("(index)", archive.check_index_hash());
+    for entry in archive.get_entries() {
+        report.record(entry.get_filename(), archive.check_entry_hash(entry));
+    }
+
+    let sig_path = Path::new(path).with_extension("sig");
+    if let Some(sig_path) = sig_path.to_str().filter(|v| Path::new(v).exists()) {
+        let signature = FPakSignatureFile::from_file(sig_path)?;
+        let mismatched = archive.check_signature(&signature)?;
+
+        // Chunks that hold no entry data are part of the index or footer
+        let mut files: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for chunk in mismatched {
+            let entries = archive.get_chunk_entries(chunk);
+            match entries.is_empty() {
+                true => files.entry("(index)").or_default().push(chunk),
+                false => for entry in entries {
+                    files.entry(entry.get_filename()).or_default().push(chunk);
+                },
+            }
+        }
+        for (file, chunks) in files {
+            report.failures.push(IntegrityFailure::new(file, format!("Signature mismatch in chunks {:?}", chunks)));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Verifies every block of an IoStore container, from its path without an extension
+///
+/// Unsigned containers have no block hashes, so every block is counted as unhashed.
+pub fn verify_utoc(path: &str, key: &str) -> ParserResult<IntegrityReport> {
+    let dispatch = Extractor::new(path, Some(key))?;
+    let mut report = IntegrityReport::new(&(path.to_owned() + ".utoc"));
+
+    let mut files: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for idx in 0..dispatch.get_block_count() {
+        match dispatch.check_block_hash(idx) {
+            Ok(Some(true)) => {
+                report.checked += 1;
+                continue;
+            },
+            Ok(Some(false)) => report.checked += 1,
+            Ok(None) => {
+                report.unhashed += 1;
+                continue;
+            },
+            Err(e) => {
+                report.failures.push(IntegrityFailure::new(&format!("(block {})", idx), format!("Could not be checked: {}", e)));
+                continue;
+            },
+        }
+
+        // Blocks are reported by the files with data in them, or by chunk id for chunks without a name
+        for chunk in dispatch.get_block_chunks(idx) {
+            let name = match dispatch.get_file_list().get(chunk).filter(|v| !v.is_empty()) {
+                Some(name) => name.clone(),
+                None => {
+                    let chunk_id = &dispatch.get_chunk_ids()[chunk];
+                    format!("({:016x} {} {:?})", chunk_id.get_id(), chunk_id.get_index(), chunk_id.get_type())
+                },
+            };
+            files.entry(name).or_default().push(idx);
+        }
+    }
+
+    for (file, blocks) in files {
+        report.failures.push(IntegrityFailure::new(&file, format!("Hash mismatch in blocks {:?}", blocks)));
+    }
+
+    Ok(report)
+}